@@ -14,6 +14,15 @@ pub trait ATEventProcessor {
         did: String,
         rkey: String,
         parent: String,
+        root: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore;
+
+    async fn add_quote(
+        &mut self,
+        did: String,
+        rkey: String,
+        subject: String,
         rec: MaybeSemaphore,
     ) -> MaybeSemaphore;
 
@@ -22,7 +31,7 @@ pub trait ATEventProcessor {
         did: String,
         rkey: String,
         timestamp: &i64,
-        is_reply: bool,
+        parent_did: String,
         post_type: String,
         rec: MaybeSemaphore,
     ) -> MaybeSemaphore;
@@ -30,7 +39,7 @@ pub trait ATEventProcessor {
     async fn add_repost(
         &mut self,
         did: String,
-        rkey_parent: String,
+        subject: String,
         rkey: String,
         rec: MaybeSemaphore,
     ) -> MaybeSemaphore;
@@ -46,7 +55,7 @@ pub trait ATEventProcessor {
     async fn add_like(
         &mut self,
         did: String,
-        rkey_parent: String,
+        subject: String,
         rkey: String,
         rec: MaybeSemaphore,
     ) -> MaybeSemaphore;
@@ -59,6 +68,21 @@ pub trait ATEventProcessor {
         rec: MaybeSemaphore,
     ) -> MaybeSemaphore;
 
+    async fn update_handle(
+        &mut self,
+        did: String,
+        handle: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore;
+
+    async fn set_account_status(
+        &mut self,
+        did: String,
+        active: bool,
+        status: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore;
+
     //////
     async fn rm_post(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore;
 
@@ -73,4 +97,8 @@ pub trait ATEventProcessor {
     async fn rm_block(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore;
 
     async fn rm_reply(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore;
+
+    async fn rm_quote(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore;
+
+    async fn rm_account(&mut self, did: String, rec: MaybeSemaphore) -> MaybeSemaphore;
 }
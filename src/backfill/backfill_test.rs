@@ -0,0 +1,197 @@
+#[cfg(test)]
+mod backfill_test {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::backfill::{
+        JobState, JobStatus,
+        crawl_log::RecentCrawls,
+        reconcile::{EdgeDiff, diff},
+        resume_from, retry_delay, set_thresholds,
+    };
+    use crate::bsky::uri::AtUri;
+    use crate::event_database::EventDatabase;
+    use crate::graph::{memory::MemoryGraph, queries, retention::Target};
+    use crate::server::listen::now;
+
+    #[test]
+    fn retries_back_off_then_give_up() {
+        assert_eq!(retry_delay(1, 3), Some(Duration::from_secs(60)));
+        assert_eq!(retry_delay(2, 3), Some(Duration::from_secs(120)));
+        assert_eq!(retry_delay(3, 3), None);
+        assert_eq!(retry_delay(30, 50), Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn resumes_where_the_last_attempt_stopped() {
+        let follows = edges(&[("a", "1"), ("b", "2"), ("c", "3"), ("e", "4")]);
+        assert_eq!(resume_from(&follows, None), &follows[..]);
+        assert_eq!(resume_from(&follows, Some("c")), &follows[3..]);
+        // The last one done was unfollowed since, & someone earlier in the list was followed
+        let follows = edges(&[("a", "1"), ("aa", "5"), ("b", "2"), ("e", "4")]);
+        assert_eq!(resume_from(&follows, Some("c")), &follows[3..]);
+        assert!(resume_from(&follows, Some("z")).is_empty());
+    }
+
+    #[test]
+    fn status_round_trips_through_json() {
+        let status = JobStatus {
+            did: "did:plc:user1".to_owned(),
+            state: JobState::Running,
+            follows_total: 250,
+            follows_done: 100,
+            follows_last: Some("did:plc:user9".to_owned()),
+            attempts: 2,
+            error: None,
+            updated_at: 1_732_000_000_000_000,
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["state"], "running");
+        assert!(json.get("error").is_none());
+        assert_eq!(serde_json::from_value::<JobStatus>(json).unwrap(), status);
+
+        for state in [
+            JobState::Queued,
+            JobState::Running,
+            JobState::Done,
+            JobState::Failed,
+        ] {
+            assert_eq!(JobState::parse(state.as_str()), Some(state));
+        }
+    }
+
+    fn edges(e: &[(&str, &str)]) -> Vec<(String, String)> {
+        e.iter()
+            .map(|(s, r)| (s.to_string(), r.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_adds_missing_and_removes_stale_edges() {
+        let listed = edges(&[
+            ("did:plc:b", "r2"),
+            ("did:plc:c", "r3"),
+            ("did:plc:d", "r4"),
+        ]);
+        let current = edges(&[
+            ("did:plc:a", "r1"),
+            ("did:plc:b", "r2"),
+            ("did:plc:c", "r3"),
+        ]);
+
+        assert_eq!(
+            diff(&listed, &current),
+            EdgeDiff {
+                add: edges(&[("did:plc:d", "r4")]),
+                remove: edges(&[("did:plc:a", "r1")]),
+            }
+        );
+    }
+
+    #[test]
+    fn diff_of_matching_edges_is_empty() {
+        let listed = edges(&[("did:plc:a", "r1"), ("did:plc:b", "r2")]);
+        // Duplicate edges from a replayed firehose event dont count as different
+        let current = edges(&[
+            ("did:plc:b", "r2"),
+            ("did:plc:a", "r1"),
+            ("did:plc:a", "r1"),
+        ]);
+        assert!(diff(&listed, &current).is_empty());
+        assert!(diff(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn diff_replaces_a_refollow() {
+        // Unfollowed & followed again while we werent listening, so the record has a new rkey
+        let listed = edges(&[("did:plc:a", "r9")]);
+        let current = edges(&[("did:plc:a", "r1")]);
+
+        let changes = diff(&listed, &current);
+        assert_eq!(changes.add, edges(&[("did:plc:a", "r9")]));
+        assert_eq!(changes.remove, edges(&[("did:plc:a", "r1")]));
+    }
+
+    #[test]
+    fn diff_removes_everything_once_the_repo_is_empty() {
+        let current = edges(&[("did:plc:a", "r1"), ("did:plc:b", "r2")]);
+        let changes = diff(&[], &current);
+        assert!(changes.add.is_empty());
+        assert_eq!(changes.remove, current);
+    }
+
+    #[test]
+    fn claims_each_did_once_until_released() {
+        let recent = RecentCrawls::new(Duration::from_secs(60), 100);
+        assert!(recent.try_claim("did:plc:a"));
+        assert!(!recent.try_claim("did:plc:a"));
+        assert!(recent.fresh("did:plc:a"));
+
+        // A failed crawl is given back, so the next one picks it up
+        recent.remove("did:plc:a");
+        assert!(!recent.fresh("did:plc:a"));
+        assert!(recent.try_claim("did:plc:a"));
+    }
+
+    #[test]
+    fn crawls_expire_after_the_ttl() {
+        let recent = RecentCrawls::new(Duration::from_secs(60), 100);
+        recent.insert("did:plc:old".into(), Duration::from_secs(59));
+        assert!(recent.fresh("did:plc:old"));
+        assert!(!recent.try_claim("did:plc:old"));
+
+        // Crawled before a restart, too long ago to count
+        recent.insert("did:plc:older".into(), Duration::from_secs(61));
+        assert!(!recent.fresh("did:plc:older"));
+        assert!(recent.try_claim("did:plc:older"));
+    }
+
+    #[test]
+    fn crawl_log_is_bounded_oldest_first() {
+        let recent = RecentCrawls::new(Duration::from_secs(3600), 10);
+        for i in 0..10 {
+            recent.insert(format!("did:plc:{i}"), Duration::from_secs(100 - i));
+        }
+        recent.insert("did:plc:new".into(), Duration::ZERO);
+
+        // Back down to 90% of capacity, losing the oldest
+        for i in 0..2 {
+            assert!(
+                !recent.fresh(&format!("did:plc:{i}")),
+                "{i} should be evicted"
+            );
+        }
+        for i in 2..10 {
+            assert!(recent.fresh(&format!("did:plc:{i}")), "{i} should be kept");
+        }
+        assert!(recent.fresh("did:plc:new"));
+    }
+
+    // Both onboarding & each reconcile pass go through this
+    #[tokio::test]
+    async fn thresholds_follow_the_viewers_network() {
+        let g = MemoryGraph::default();
+        g.add_edge(Target::Follows, "did:viewer", "did:friend", "f1");
+        g.add_edge(Target::Follows, "did:friend", "did:author", "f2");
+        let uri = AtUri::post("did:author", "p1").to_string();
+        g.add_post("did:author", &uri, now() as i64 - 1, "");
+        g.add_edge(Target::Likes, "did:fan", &uri, "l1");
+
+        let feed = || async {
+            let params = HashMap::from([
+                ("did".to_owned(), "did:viewer".into()),
+                ("replies".to_owned(), false.into()),
+                ("time".to_owned(), now().into()),
+            ]);
+            g.read(
+                "GET_FOLLOWING_PLUS_LIKES",
+                queries::GET_FOLLOWING_PLUS_LIKES,
+                Some(params),
+            )
+            .await
+            .unwrap()
+        };
+        assert!(feed().await.is_empty());
+        set_thresholds(&g, "did:viewer").await;
+        assert!(feed().await.contains_key(&uri));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use neo4rs::Graph;
+use tracing::warn;
+
+use crate::graph::{dialect::Dialect, queries};
+use crate::server::listen::now;
+
+/// Which DIDs have had their follows crawled, & when. Held in memory up to `capacity`, oldest dropped first,
+/// with the graph as the durable copy, so a restart doesnt mean crawling everyone all over again
+pub struct CrawlLog {
+    /// None for the in memory graph, which a restart empties anyway
+    conn: Option<Graph>,
+    dialect: Dialect,
+    recent: RecentCrawls,
+}
+
+impl CrawlLog {
+    pub fn new(conn: Graph, dialect: Dialect, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            conn: Some(conn),
+            dialect,
+            recent: RecentCrawls::new(ttl, capacity),
+        }
+    }
+
+    /// Only ever held in memory, for the in memory graph
+    pub fn in_memory(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            conn: None,
+            dialect: Dialect::default(),
+            recent: RecentCrawls::new(ttl, capacity),
+        }
+    }
+
+    /// `CRAWL_TTL_SECS` (default 604800) before a follow list is worth crawling again, & `CRAWL_LOG_CAPACITY`
+    /// (default 200000) DIDs remembered in memory
+    pub fn from_env(conn: Graph, dialect: Dialect) -> Self {
+        let (ttl, capacity) = settings();
+        Self::new(conn, dialect, ttl, capacity)
+    }
+
+    /// `in_memory`, with the same settings as `from_env`
+    pub fn in_memory_from_env() -> Self {
+        let (ttl, capacity) = settings();
+        Self::in_memory(ttl, capacity)
+    }
+
+    /// The DIDs out of `dids` due a crawl, which are then claimed so no one else crawls them too.
+    /// Give them back with `release` if the crawl fails, or `record` them once it is written
+    pub async fn claim(&self, dids: &[String]) -> Vec<String> {
+        let unknown: Vec<String> = dids
+            .iter()
+            .filter(|d| !self.recent.fresh(d))
+            .cloned()
+            .collect();
+        if unknown.is_empty() {
+            return unknown;
+        }
+
+        // Anything crawled before a restart, or pushed out of memory since
+        let now = now() as i64;
+        match self.crawled_at(&unknown).await {
+            Ok(crawled) => {
+                for (did, at) in crawled {
+                    let age = Duration::from_micros(now.saturating_sub(at).max(0) as u64);
+                    self.recent.insert(did, age);
+                }
+            }
+            Err(e) => warn!("Error reading crawl log, crawling regardless: {}", e),
+        };
+
+        unknown
+            .into_iter()
+            .filter(|d| self.recent.try_claim(d))
+            .collect()
+    }
+
+    pub fn release(&self, did: &str) {
+        self.recent.remove(did);
+    }
+
+    pub async fn record(&self, dids: &[String]) {
+        if dids.is_empty() {
+            return;
+        }
+        for did in dids {
+            self.recent.insert(did.clone(), Duration::ZERO);
+        }
+        let conn = match &self.conn {
+            Some(c) => c,
+            None => return,
+        };
+        if let Err(e) = conn
+            .run(
+                self.dialect
+                    .query(queries::MARK_FOLLOWS_CRAWLED)
+                    .param("dids", dids.to_vec()),
+            )
+            .await
+        {
+            warn!("Error recording {} crawls: {}", dids.len(), e);
+        }
+    }
+
+    async fn crawled_at(&self, dids: &[String]) -> Result<Vec<(String, i64)>, neo4rs::Error> {
+        let conn = match &self.conn {
+            Some(c) => c,
+            None => return Ok(vec![]),
+        };
+        let mut res = conn
+            .execute(
+                self.dialect
+                    .query(queries::GET_FOLLOWS_CRAWLED)
+                    .param("dids", dids.to_vec()),
+            )
+            .await?;
+        let mut crawled = Vec::new();
+        while let Some(row) = res.next().await? {
+            if let (Ok(did), Ok(at)) = (row.get::<String>("did"), row.get::<i64>("crawled_at")) {
+                crawled.push((did, at));
+            }
+        }
+        Ok(crawled)
+    }
+}
+
+/// (ttl, capacity) from `CRAWL_TTL_SECS` & `CRAWL_LOG_CAPACITY`
+fn settings() -> (Duration, usize) {
+    let var = |key: &str, default: u64| {
+        env::var(key)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    (
+        Duration::from_secs(var("CRAWL_TTL_SECS", 604800)),
+        var("CRAWL_LOG_CAPACITY", 200000) as usize,
+    )
+}
+
+/// The in-memory half of the crawl log. A claimed DID counts as crawled, until it is released
+pub(crate) struct RecentCrawls {
+    ttl: Duration,
+    capacity: usize,
+    crawled: DashMap<String, Instant>,
+}
+
+impl RecentCrawls {
+    pub(crate) fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            crawled: DashMap::new(),
+        }
+    }
+
+    pub(crate) fn fresh(&self, did: &str) -> bool {
+        self.crawled
+            .get(did)
+            .is_some_and(|at| at.elapsed() < self.ttl)
+    }
+
+    /// False if it was crawled (or claimed) within the TTL
+    pub(crate) fn try_claim(&self, did: &str) -> bool {
+        let claimed = match self.crawled.entry(did.to_owned()) {
+            Entry::Occupied(mut e) => match e.get().elapsed() < self.ttl {
+                true => false,
+                false => {
+                    e.insert(Instant::now());
+                    true
+                }
+            },
+            Entry::Vacant(e) => {
+                e.insert(Instant::now());
+                true
+            }
+        };
+        self.evict();
+        claimed
+    }
+
+    /// Remembers a crawl that happened `age` ago, unless that is already too long ago to count
+    pub(crate) fn insert(&self, did: String, age: Duration) {
+        if age >= self.ttl {
+            return;
+        }
+        let at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        self.crawled.insert(did, at);
+        self.evict();
+    }
+
+    pub(crate) fn remove(&self, did: &str) {
+        self.crawled.remove(did);
+    }
+
+    /// Over capacity, drops anything expired, then the oldest down to 90% so this doesnt run every insert
+    fn evict(&self) {
+        if self.crawled.len() <= self.capacity {
+            return;
+        }
+        self.crawled.retain(|_, at| at.elapsed() < self.ttl);
+
+        let excess = self
+            .crawled
+            .len()
+            .saturating_sub(self.capacity - self.capacity / 10);
+        if excess == 0 {
+            return;
+        }
+        let mut by_age: Vec<(Instant, String)> = self
+            .crawled
+            .iter()
+            .map(|e| (*e.value(), e.key().clone()))
+            .collect();
+        by_age.sort();
+        for (_, did) in by_age.into_iter().take(excess) {
+            self.crawled.remove(&did);
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use std::{collections::HashMap, env, error, sync::Arc, time::Duration};
+
+use dashmap::DashSet;
+use futures::StreamExt;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{RwLock, Semaphore, mpsc};
+use tracing::{error, info, warn};
+
+use crate::bsky::{self, did::DidResolver, types::RecNotFound, xrpc::XrpcClient};
+use crate::common::PostMsg;
+use crate::event_database::{EventDatabase, Params};
+use crate::graph::queries;
+use crate::server::snapshot::SnapshotCache;
+use crawl_log::CrawlLog;
+use store::JobStore;
+
+mod backfill_test;
+pub mod crawl_log;
+pub mod reconcile;
+pub mod store;
+
+/// Roughly how many 2nd degree candidates each engagement threshold should let through
+pub(crate) const THRESHOLD_TARGET: usize = 300;
+/// How many of the viewer's follows are crawled between progress checkpoints
+pub(crate) const CRAWL_BATCH: usize = 100;
+/// 2nd degree follow lists fetched at once, per job
+const CRAWL_FETCHES: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "queued" => Some(JobState::Queued),
+            "running" => Some(JobState::Running),
+            "done" => Some(JobState::Done),
+            "failed" => Some(JobState::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub did: String,
+    pub state: JobState,
+    /// How many accounts the viewer follows, & how many of those have had their own follows crawled
+    pub follows_total: u64,
+    pub follows_done: u64,
+    /// Follows are crawled in DID order, this is the last one done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follows_last: Option<String>,
+    pub attempts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Unix micros
+    pub updated_at: i64,
+}
+
+#[derive(Debug)]
+pub enum BackfillMessage {
+    /// A viewer asked for a feed; crawl their network if we never have
+    Enqueue { did: String },
+    /// Crawl again, even if it finished or failed. Replies with whether it was queued
+    Requeue {
+        did: String,
+        resp: mpsc::Sender<bool>,
+    },
+    Status {
+        did: String,
+        resp: mpsc::Sender<Option<JobStatus>>,
+    },
+}
+
+enum CrawlError {
+    /// Worth another go later, e.g. a PDS or graph hiccup
+    Transient(String),
+    Permanent(String),
+}
+
+/// Backoff before retrying a job that has failed `attempts` times, or None once it has used up `max_attempts`
+pub fn retry_delay(attempts: u64, max_attempts: u64) -> Option<Duration> {
+    match attempts {
+        a if a >= max_attempts => None,
+        a => Some(Duration::from_secs(
+            (60u64 << a.saturating_sub(1).min(6)).min(3600),
+        )),
+    }
+}
+
+/// Where to pick a viewer's (sorted) follows back up from, given the last one a previous attempt got through.
+/// Going by DID rather than a count means follows & unfollows in between dont shift anyone in or out
+pub fn resume_from<'a>(
+    follows: &'a [(String, String)],
+    last: Option<&str>,
+) -> &'a [(String, String)] {
+    match last {
+        Some(last) => &follows[follows.partition_point(|(f, _)| f.as_str() <= last)..],
+        None => follows,
+    }
+}
+
+/// Runs onboarding crawls off a persisted queue, so they survive errors & restarts
+pub struct Backfill<T> {
+    store: JobStore,
+    writer: T,
+    write_lock: Arc<RwLock<()>>,
+    snapshots: Arc<SnapshotCache>,
+    client: Arc<XrpcClient>,
+    resolver: Arc<DidResolver>,
+    crawl_log: Arc<CrawlLog>,
+    running: DashSet<String>,
+    workers: Semaphore,
+    max_attempts: u64,
+    max_car_bytes: u64,
+    retry: mpsc::Sender<String>,
+}
+
+impl<T: EventDatabase<HashMap<String, PostMsg>> + Clone + Sync + 'static> Backfill<T> {
+    /// `CRAWL_WORKERS` (default 2) jobs run at once, each tried up to `CRAWL_MAX_ATTEMPTS` (default 3) times.
+    /// Repos over `CRAWL_MAX_REPO_BYTES` (default 64MiB) are onboarded through listRecords instead
+    pub fn new(
+        store: JobStore,
+        writer: T,
+        write_lock: Arc<RwLock<()>>,
+        snapshots: Arc<SnapshotCache>,
+        client: Arc<XrpcClient>,
+        resolver: Arc<DidResolver>,
+        crawl_log: Arc<CrawlLog>,
+    ) -> (Self, mpsc::Receiver<String>) {
+        let var = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let (retry, retries) = mpsc::channel(100);
+
+        let backfill = Self {
+            store,
+            writer,
+            write_lock,
+            snapshots,
+            client,
+            resolver,
+            crawl_log,
+            running: DashSet::new(),
+            workers: Semaphore::new(var("CRAWL_WORKERS", 2).max(1) as usize),
+            max_attempts: var("CRAWL_MAX_ATTEMPTS", 3).max(1),
+            max_car_bytes: var("CRAWL_MAX_REPO_BYTES", 64 << 20),
+            retry,
+        };
+        (backfill, retries)
+    }
+
+    pub async fn run(
+        self: Arc<Self>,
+        mut recv: mpsc::Receiver<BackfillMessage>,
+        mut retries: mpsc::Receiver<String>,
+    ) {
+        match self.store.pending().await {
+            Ok(dids) => {
+                info!("Resuming {} crawl jobs", dids.len());
+                for did in dids {
+                    self.spawn_job(did);
+                }
+            }
+            Err(e) => warn!("Error loading pending crawl jobs: {}", e),
+        };
+
+        loop {
+            let msg = tokio::select! {
+                Some(msg) = recv.recv() => msg,
+                Some(did) = retries.recv() => {
+                    self.spawn_job(did);
+                    continue;
+                }
+                else => return,
+            };
+
+            match msg {
+                BackfillMessage::Enqueue { did } => {
+                    if self.running.contains(&did) {
+                        continue;
+                    }
+                    match self.store.enqueue(&did, false).await {
+                        Ok(true) => self.spawn_job(did),
+                        Ok(false) => {}
+                        Err(e) => warn!("Error queueing crawl for {}: {}", did, e),
+                    }
+                }
+                BackfillMessage::Requeue { did, resp } => {
+                    let queued = match self.store.enqueue(&did, true).await {
+                        Ok(q) => q,
+                        Err(e) => {
+                            warn!("Error re-queueing crawl for {}: {}", did, e);
+                            false
+                        }
+                    };
+                    if queued {
+                        info!("Re-queued crawl for {}", did);
+                        self.spawn_job(did);
+                    }
+                    _ = resp.send(queued).await;
+                }
+                BackfillMessage::Status { did, resp } => {
+                    let status = match self.store.status(&did).await {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("Error getting crawl status for {}: {}", did, e);
+                            None
+                        }
+                    };
+                    _ = resp.send(status).await;
+                }
+            }
+        }
+    }
+
+    fn spawn_job(self: &Arc<Self>, did: String) {
+        if !self.running.insert(did.clone()) {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move {
+            let permit = this.workers.acquire().await.unwrap();
+            this.run_job(&did).await;
+            drop(permit);
+            this.running.remove(&did);
+        });
+    }
+
+    async fn run_job(&self, did: &str) {
+        let job = match self.store.start(did).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                warn!("Crawl job for {} has gone missing", did);
+                return;
+            }
+            Err(e) => {
+                // Still queued in the graph, so it'll be picked back up on restart
+                error!("Error starting crawl for {}: {}", did, e);
+                return;
+            }
+        };
+        info!(
+            "Crawling network for {} (attempt {}, resuming after {:?})",
+            did, job.attempts, job.follows_last
+        );
+
+        let (state, error) = match self.crawl(did, job.follows_last.as_deref()).await {
+            Ok(_) => (JobState::Done, String::new()),
+            Err(CrawlError::Permanent(e)) => (JobState::Failed, e),
+            Err(CrawlError::Transient(e)) => match retry_delay(job.attempts, self.max_attempts) {
+                Some(delay) => {
+                    info!("Retrying crawl for {} in {}s: {}", did, delay.as_secs(), e);
+                    let retry = self.retry.clone();
+                    let did = did.to_owned();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        _ = retry.send(did).await;
+                    });
+                    (JobState::Queued, e)
+                }
+                None => (JobState::Failed, e),
+            },
+        };
+
+        if state == JobState::Failed {
+            warn!("Crawl for {} failed: {}", did, error);
+        }
+        if let Err(e) = self.store.end(did, state, &error).await {
+            error!("Error saving crawl state for {}: {}", did, e);
+        }
+    }
+
+    /// The viewer's (blocks, follows), from their repo export in one go if we can, otherwise paging through
+    /// listRecords
+    async fn viewer_records(&self, did: &str) -> Result<RecordPair, CrawlError> {
+        match bsky::get_repo_records(did, &self.client, &self.resolver, self.max_car_bytes).await {
+            Ok(r) => {
+                info!(
+                    "{} has {} follows, {} blocks & {} list items",
+                    did,
+                    r.follows.len(),
+                    r.blocks.len(),
+                    r.list_items.len()
+                );
+                return Ok((r.blocks, r.follows));
+            }
+            Err((e, _)) if e.is::<RecNotFound>() => return Err(fetch_error("repo", e)),
+            Err((e, _)) => warn!("Falling back to listRecords for {}: {}", did, e),
+        };
+
+        let blocks = match get_blocks(did, &self.client, &self.resolver).await {
+            Ok(b) => b,
+            Err(e) => return Err(fetch_error("blocks", e)),
+        };
+        let follows = match get_follows(did, &self.client, &self.resolver).await {
+            Ok(f) => f,
+            Err(e) => return Err(fetch_error("follows", e)),
+        };
+        Ok((blocks, follows))
+    }
+
+    /// Blocks, then follows, then each follow's follows, checkpointing every `CRAWL_BATCH`
+    async fn crawl(&self, did: &str, last: Option<&str>) -> Result<(), CrawlError> {
+        let (blocks, mut follows) = self.viewer_records(did).await?;
+
+        // Blocks
+        let blocks: Vec<Params> = blocks
+            .into_iter()
+            .map(|(blockee, rkey)| {
+                HashMap::from([
+                    ("blockee".to_owned(), blockee.into()),
+                    ("did".to_owned(), did.into()),
+                    ("rkey".to_owned(), rkey.into()),
+                ])
+            })
+            .collect();
+        if let Some(e) = self
+            .writer
+            .chunk_write(queries::POPULATE_BLOCK, blocks, 60, "blocks")
+            .await
+        {
+            return Err(CrawlError::Transient(format!("writing blocks: {e}")));
+        }
+
+        // Follows, in a stable order so progress means the same thing next attempt
+        follows.sort();
+        let first_degree = follows
+            .iter()
+            .map(|(out, rkey)| (out.clone(), rkey.clone(), did.to_owned()))
+            .collect();
+        if let Some(e) =
+            chunk_and_write_follows(first_degree, self.writer.clone(), self.write_lock.clone())
+                .await
+        {
+            return Err(CrawlError::Transient(format!("writing follows: {e}")));
+        }
+        self.crawl_log.record(&[did.to_owned()]).await;
+
+        let total = follows.len();
+        let remaining = resume_from(&follows, last);
+        let mut done = total - remaining.len();
+        for batch in remaining.chunks(CRAWL_BATCH) {
+            let dids: Vec<String> = batch.iter().map(|(f, _)| f.clone()).collect();
+            if let Some(e) = crawl_follows_of(
+                &dids,
+                &self.client,
+                &self.resolver,
+                &self.crawl_log,
+                self.writer.clone(),
+                self.write_lock.clone(),
+            )
+            .await
+            {
+                return Err(CrawlError::Transient(format!(
+                    "writing 2nd degree follows: {e}"
+                )));
+            }
+
+            done += batch.len();
+            let last = batch.last().map(|(f, _)| f.as_str()).unwrap_or_default();
+            if let Err(e) = self.store.progress(did, total, done, last).await {
+                warn!("Error saving crawl progress for {}: {}", did, e);
+            }
+        }
+
+        // Now we know their network, work out what counts as popular within it
+        set_thresholds(&self.writer, did).await;
+        self.snapshots.invalidate_viewer(did);
+
+        info!("Done crawling {} follows for {did}", total);
+        Ok(())
+    }
+}
+
+fn fetch_error(what: &str, e: Box<dyn error::Error>) -> CrawlError {
+    match e.is::<RecNotFound>() {
+        true => CrawlError::Permanent(format!("{what}: repo not found")),
+        false => CrawlError::Transient(format!("getting {what}: {e}")),
+    }
+}
+
+async fn get_follows(
+    did: &str,
+    client: &XrpcClient,
+    resolver: &DidResolver,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    bsky::get_follows(did.to_owned(), client, resolver)
+        .await
+        .map_err(|e| e.0)
+}
+
+async fn get_blocks(
+    did: &str,
+    client: &XrpcClient,
+    resolver: &DidResolver,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    bsky::get_blocks(did.to_owned(), client, resolver)
+        .await
+        .map_err(|e| e.0)
+}
+
+/// A viewer's (blocks, follows), each as (subject, rkey)
+type RecordPair = (Vec<(String, String)>, Vec<(String, String)>);
+
+/// A DID & its follows as (out, rkey), or None if they couldnt be fetched
+type FollowsOf = (String, Option<Vec<(String, String)>>);
+
+/// Crawls & writes the follows of whichever of `dids` the crawl log says are due, recording them once
+/// written. Those that fail are released to be tried again by a later crawl
+pub(crate) async fn crawl_follows_of(
+    dids: &[String],
+    client: &XrpcClient,
+    resolver: &DidResolver,
+    crawl_log: &CrawlLog,
+    writer: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
+    write_lock: Arc<RwLock<()>>,
+) -> Option<Box<dyn error::Error>> {
+    let to_crawl = crawl_log.claim(dids).await;
+    if to_crawl.is_empty() {
+        return None;
+    }
+
+    let results: Vec<FollowsOf> = futures::stream::iter(to_crawl)
+        .map(|did| async move {
+            let follows = match get_follows(&did, client, resolver).await {
+                Ok(f) => Some(f),
+                Err(e) if e.is::<RecNotFound>() => {
+                    info!("{did} probably doesnt exist on this PDS, skipping...");
+                    Some(vec![])
+                }
+                Err(e) => {
+                    warn!("Error getting 2nd degree follows for {did}: {:?}", e);
+                    None
+                }
+            };
+            (did, follows)
+        })
+        .buffer_unordered(CRAWL_FETCHES)
+        .collect()
+        .await;
+
+    let mut crawled = Vec::new();
+    let mut fetched = Vec::new();
+    for (did, follows) in results {
+        match follows {
+            Some(f) => {
+                fetched.extend(f.into_iter().map(|(out, rkey)| (out, rkey, did.clone())));
+                crawled.push(did);
+            }
+            None => crawl_log.release(&did),
+        }
+    }
+
+    if let Some(e) = chunk_and_write_follows(fetched, writer, write_lock).await {
+        for did in crawled.iter() {
+            crawl_log.release(did);
+        }
+        return Some(e);
+    }
+    crawl_log.record(&crawled).await;
+    None
+}
+
+/// What counts as popular in `did`'s network, for each feed's candidates
+async fn set_thresholds(writer: &impl EventDatabase<HashMap<String, PostMsg>>, did: &str) {
+    let params = HashMap::from([
+        ("did".to_owned(), did.into()),
+        ("target".to_owned(), (THRESHOLD_TARGET as i64).into()),
+    ]);
+    if let Some(e) = writer.write(queries::SET_THRESHOLDS, Some(params)).await {
+        warn!("Error setting thresholds for {did}: {:?}", e);
+    }
+}
+
+/// Writes (out, rkey, did) follows under the global write lock
+async fn chunk_and_write_follows(
+    follows: Vec<(String, String, String)>,
+    conn: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
+    write_lock: Arc<RwLock<()>>,
+) -> Option<Box<dyn error::Error>> {
+    if follows.is_empty() {
+        return None;
+    }
+    let follow_chunks: Vec<Params> = follows
+        .into_iter()
+        .map(|(out, rkey, did)| {
+            HashMap::from([
+                ("out".to_owned(), out.into()),
+                ("rkey".to_owned(), rkey.into()),
+                ("did".to_owned(), did.into()),
+            ])
+        })
+        .collect();
+    let l = write_lock.write().await;
+    let r = conn
+        .chunk_write(queries::POPULATE_FOLLOW, follow_chunks, 20, "follows")
+        .await;
+    drop(l);
+    r
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::StreamExt;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use super::{
+    CrawlError, chunk_and_write_follows, crawl_follows_of, crawl_log::CrawlLog, fetch_error,
+    get_blocks, get_follows, set_thresholds, store::JobStore,
+};
+use crate::bsky::{did::DidResolver, xrpc::XrpcClient};
+use crate::common::PostMsg;
+use crate::event_database::{EventDatabase, Params};
+use crate::graph::queries;
+use crate::server::{listen::now, snapshot::SnapshotCache};
+
+/// Viewers re-synced per pass, & how many at once
+const RECONCILE_BATCH: usize = 50;
+const RECONCILE_FETCHES: usize = 4;
+
+/// What it takes to make the graph's (subject, rkey) edges match the repo's records
+#[derive(Debug, Default, PartialEq)]
+pub struct EdgeDiff {
+    pub add: Vec<(String, String)>,
+    pub remove: Vec<(String, String)>,
+}
+
+impl EdgeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+}
+
+pub fn diff(listed: &[(String, String)], current: &[(String, String)]) -> EdgeDiff {
+    let listed_set: HashSet<&(String, String)> = listed.iter().collect();
+    let current_set: HashSet<&(String, String)> = current.iter().collect();
+
+    let mut add: Vec<(String, String)> = listed_set
+        .difference(&current_set)
+        .map(|e| (*e).clone())
+        .collect();
+    let mut remove: Vec<(String, String)> = current_set
+        .difference(&listed_set)
+        .map(|e| (*e).clone())
+        .collect();
+    add.sort();
+    remove.sort();
+    EdgeDiff { add, remove }
+}
+
+/// Follows & blocks missed while the firehose was down or behind would otherwise stay wrong forever, so
+/// active viewers have theirs re-listed & diffed against the graph once they are older than `ttl`
+pub struct Reconciler<T> {
+    store: JobStore,
+    writer: T,
+    write_lock: Arc<RwLock<()>>,
+    snapshots: Arc<SnapshotCache>,
+    client: Arc<XrpcClient>,
+    resolver: Arc<DidResolver>,
+    crawl_log: Arc<CrawlLog>,
+    interval: Duration,
+    ttl: Duration,
+    active_for: Duration,
+}
+
+impl<T: EventDatabase<HashMap<String, PostMsg>> + Clone + Sync + 'static> Reconciler<T> {
+    /// `RECONCILE_INTERVAL_SECS` (default 300, 0 turns it off), `RECONCILE_TTL_SECS` (default 21600) &
+    /// `RECONCILE_ACTIVE_SECS` (default 86400)
+    pub fn new(
+        store: JobStore,
+        writer: T,
+        write_lock: Arc<RwLock<()>>,
+        snapshots: Arc<SnapshotCache>,
+        client: Arc<XrpcClient>,
+        resolver: Arc<DidResolver>,
+        crawl_log: Arc<CrawlLog>,
+    ) -> Self {
+        let secs = |key: &str, default: u64| {
+            Duration::from_secs(
+                env::var(key)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        Self {
+            store,
+            writer,
+            write_lock,
+            snapshots,
+            client,
+            resolver,
+            crawl_log,
+            interval: secs("RECONCILE_INTERVAL_SECS", 300),
+            ttl: secs("RECONCILE_TTL_SECS", 21600),
+            active_for: secs("RECONCILE_ACTIVE_SECS", 86400),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        if self.interval.is_zero() {
+            info!("Follow & block reconciliation is off");
+            return;
+        }
+        loop {
+            tokio::time::sleep(self.interval).await;
+
+            // Graph timestamps are micros, same as `now`
+            let now = now() as i64;
+            let dids = match self
+                .store
+                .stale_viewers(
+                    now - self.active_for.as_micros() as i64,
+                    now - self.ttl.as_micros() as i64,
+                    RECONCILE_BATCH,
+                )
+                .await
+            {
+                Ok(d) => d,
+                Err(e) => {
+                    warn!("Error finding viewers to re-sync: {}", e);
+                    continue;
+                }
+            };
+            if dids.is_empty() {
+                continue;
+            }
+
+            info!("Re-syncing follows & blocks for {} viewers", dids.len());
+            futures::stream::iter(dids)
+                .for_each_concurrent(RECONCILE_FETCHES, |did| {
+                    let this = self.clone();
+                    async move { this.reconcile(&did).await }
+                })
+                .await;
+        }
+    }
+
+    async fn reconcile(&self, did: &str) {
+        let synced = match self.sync_follows(did).await {
+            Ok(follows) => self.sync_blocks(did).await.map(|blocks| (follows, blocks)),
+            Err(e) => Err(e),
+        };
+        let (follows, blocks) = match synced {
+            Ok(s) => s,
+            Err(CrawlError::Permanent(e)) => {
+                info!("Nothing to re-sync {} from, {}", did, e);
+                (EdgeDiff::default(), EdgeDiff::default())
+            }
+            Err(CrawlError::Transient(e)) => {
+                // Left stale, so it is picked up again next pass
+                warn!("Error re-syncing {}: {}", did, e);
+                return;
+            }
+        };
+
+        // Engagement in their network has moved on since the last pass, even if who they follow hasnt
+        set_thresholds(&self.writer, did).await;
+        if !follows.is_empty() || !blocks.is_empty() {
+            info!(
+                "Re-synced {}: +{} -{} follows, +{} -{} blocks",
+                did,
+                follows.add.len(),
+                follows.remove.len(),
+                blocks.add.len(),
+                blocks.remove.len()
+            );
+            self.snapshots.invalidate_viewer(did);
+        }
+        if let Err(e) = self.store.mark_synced(did).await {
+            warn!("Error marking {} synced: {}", did, e);
+        }
+    }
+
+    /// The graph is read before listing, so edges the firehose adds in between are never removed
+    async fn sync_follows(&self, did: &str) -> Result<EdgeDiff, CrawlError> {
+        let current = match self.store.edges(queries::GET_FOLLOW_EDGES, did).await {
+            Ok(c) => c,
+            Err(e) => return Err(CrawlError::Transient(format!("reading follows: {e}"))),
+        };
+        let listed = match get_follows(did, &self.client, &self.resolver).await {
+            Ok(l) => l,
+            Err(e) => return Err(fetch_error("follows", e)),
+        };
+        let changes = diff(&listed, &current);
+
+        let added = changes
+            .add
+            .iter()
+            .map(|(out, rkey)| (out.clone(), rkey.clone(), did.to_owned()))
+            .collect();
+        if let Some(e) =
+            chunk_and_write_follows(added, self.writer.clone(), self.write_lock.clone()).await
+        {
+            return Err(CrawlError::Transient(format!("writing follows: {e}")));
+        }
+        if !changes.remove.is_empty()
+            && let Some(e) = self
+                .writer
+                .chunk_write(
+                    queries::REMOVE_FOLLOW,
+                    edge_params(did, &changes.remove, "out"),
+                    60,
+                    "follows",
+                )
+                .await
+        {
+            return Err(CrawlError::Transient(format!("removing follows: {e}")));
+        }
+        self.crawl_log.record(&[did.to_owned()]).await;
+
+        // New follows bring their own follows into the viewer's network, unless we already know them
+        let followed: Vec<String> = changes.add.iter().map(|(out, _)| out.clone()).collect();
+        if let Some(e) = crawl_follows_of(
+            &followed,
+            &self.client,
+            &self.resolver,
+            &self.crawl_log,
+            self.writer.clone(),
+            self.write_lock.clone(),
+        )
+        .await
+        {
+            return Err(CrawlError::Transient(format!(
+                "writing 2nd degree follows: {e}"
+            )));
+        }
+        Ok(changes)
+    }
+
+    async fn sync_blocks(&self, did: &str) -> Result<EdgeDiff, CrawlError> {
+        let current = match self.store.edges(queries::GET_BLOCK_EDGES, did).await {
+            Ok(c) => c,
+            Err(e) => return Err(CrawlError::Transient(format!("reading blocks: {e}"))),
+        };
+        let listed = match get_blocks(did, &self.client, &self.resolver).await {
+            Ok(l) => l,
+            Err(e) => return Err(fetch_error("blocks", e)),
+        };
+        let changes = diff(&listed, &current);
+
+        for (query, edges) in [
+            (queries::POPULATE_BLOCK, &changes.add),
+            (queries::REMOVE_BLOCK, &changes.remove),
+        ] {
+            if edges.is_empty() {
+                continue;
+            }
+            if let Some(e) = self
+                .writer
+                .chunk_write(query, edge_params(did, edges, "blockee"), 60, "blocks")
+                .await
+            {
+                return Err(CrawlError::Transient(format!("writing blocks: {e}")));
+            }
+        }
+        Ok(changes)
+    }
+}
+
+fn edge_params(did: &str, edges: &[(String, String)], subject: &str) -> Vec<Params> {
+    edges
+        .iter()
+        .map(|(s, rkey)| {
+            HashMap::from([
+                ("did".to_owned(), did.into()),
+                (subject.to_owned(), s.as_str().into()),
+                ("rkey".to_owned(), rkey.as_str().into()),
+            ])
+        })
+        .collect()
+}
@@ -0,0 +1,159 @@
+use neo4rs::{Graph, Row};
+
+use super::{JobState, JobStatus};
+use crate::graph::{dialect::Dialect, queries};
+
+/// Crawl jobs & re-sync bookkeeping, persisted on the viewer's User node
+#[derive(Clone)]
+pub struct JobStore {
+    conn: Graph,
+    dialect: Dialect,
+}
+
+impl JobStore {
+    pub fn new(conn: Graph, dialect: Dialect) -> Self {
+        Self { conn, dialect }
+    }
+
+    /// True if the job was queued, false if it already exists (or is still pending, when forced)
+    pub async fn enqueue(&self, did: &str, force: bool) -> Result<bool, neo4rs::Error> {
+        let mut res = self
+            .conn
+            .execute(
+                self.dialect
+                    .query(queries::ENQUEUE_CRAWL)
+                    .param("did", did)
+                    .param("force", force),
+            )
+            .await?;
+        Ok(res.next().await?.is_some())
+    }
+
+    /// Marks the job running & counts the attempt. The returned progress is where to resume from
+    pub async fn start(&self, did: &str) -> Result<Option<JobStatus>, neo4rs::Error> {
+        self.fetch_one(self.dialect.query(queries::START_CRAWL).param("did", did))
+            .await
+    }
+
+    /// `last` is the last follow whose own follows were crawled
+    pub async fn progress(
+        &self,
+        did: &str,
+        total: usize,
+        done: usize,
+        last: &str,
+    ) -> Result<(), neo4rs::Error> {
+        self.conn
+            .run(
+                self.dialect
+                    .query(queries::CRAWL_PROGRESS)
+                    .param("did", did)
+                    .param("total", total as i64)
+                    .param("done", done as i64)
+                    .param("last", last),
+            )
+            .await
+    }
+
+    pub async fn end(&self, did: &str, state: JobState, error: &str) -> Result<(), neo4rs::Error> {
+        self.conn
+            .run(
+                self.dialect
+                    .query(queries::END_CRAWL)
+                    .param("did", did)
+                    .param("state", state.as_str())
+                    .param("error", error),
+            )
+            .await
+    }
+
+    pub async fn status(&self, did: &str) -> Result<Option<JobStatus>, neo4rs::Error> {
+        self.fetch_one(self.dialect.query(queries::GET_CRAWL).param("did", did))
+            .await
+    }
+
+    pub async fn pending(&self) -> Result<Vec<String>, neo4rs::Error> {
+        let mut res = self
+            .conn
+            .execute(self.dialect.query(queries::GET_PENDING_CRAWLS))
+            .await?;
+        let mut dids = Vec::new();
+        while let Some(row) = res.next().await? {
+            if let Ok(did) = row.get::<String>("did") {
+                dids.push(did);
+            }
+        }
+        Ok(dids)
+    }
+
+    /// Active viewers due a re-sync, see `GET_STALE_VIEWERS`
+    pub async fn stale_viewers(
+        &self,
+        active_since: i64,
+        stale_before: i64,
+        limit: usize,
+    ) -> Result<Vec<String>, neo4rs::Error> {
+        let mut res = self
+            .conn
+            .execute(
+                self.dialect
+                    .query(queries::GET_STALE_VIEWERS)
+                    .param("active_since", active_since)
+                    .param("stale_before", stale_before)
+                    .param("limit", limit as i64),
+            )
+            .await?;
+        let mut dids = Vec::new();
+        while let Some(row) = res.next().await? {
+            if let Ok(did) = row.get::<String>("did") {
+                dids.push(did);
+            }
+        }
+        Ok(dids)
+    }
+
+    /// The viewer's FOLLOWS or BLOCKED edges as (subject, rkey), given one of the `GET_*_EDGES` queries
+    pub async fn edges(
+        &self,
+        edge_query: &str,
+        did: &str,
+    ) -> Result<Vec<(String, String)>, neo4rs::Error> {
+        let mut res = self
+            .conn
+            .execute(self.dialect.query(edge_query).param("did", did))
+            .await?;
+        let mut edges = Vec::new();
+        while let Some(row) = res.next().await? {
+            if let (Ok(subject), Ok(rkey)) =
+                (row.get::<String>("subject"), row.get::<String>("rkey"))
+            {
+                edges.push((subject, rkey));
+            }
+        }
+        Ok(edges)
+    }
+
+    pub async fn mark_synced(&self, did: &str) -> Result<(), neo4rs::Error> {
+        self.conn
+            .run(self.dialect.query(queries::MARK_SYNCED).param("did", did))
+            .await
+    }
+
+    async fn fetch_one(&self, q: neo4rs::Query) -> Result<Option<JobStatus>, neo4rs::Error> {
+        let mut res = self.conn.execute(q).await?;
+        Ok(res.next().await?.and_then(|row| to_status(&row)))
+    }
+}
+
+fn to_status(row: &Row) -> Option<JobStatus> {
+    Some(JobStatus {
+        did: row.get("did").ok()?,
+        state: JobState::parse(&row.get::<String>("state").ok()?)?,
+        follows_total: row.get::<i64>("total").unwrap_or_default() as u64,
+        follows_done: row.get::<i64>("done").unwrap_or_default() as u64,
+        follows_last: row.get("last").ok(),
+        attempts: row.get::<i64>("attempts").unwrap_or_default() as u64,
+        error: row.get("error").ok(),
+        updated_at: row.get("updated_at").unwrap_or_default(),
+    })
+}
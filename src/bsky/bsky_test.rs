@@ -0,0 +1,494 @@
+#[cfg(test)]
+mod bsky_test {
+    use crate::bsky::uri::{AtUri, tid_micros};
+
+    #[test]
+    fn parse_post_uri() {
+        let uri = AtUri::parse("at://did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x").unwrap();
+        assert_eq!(uri.did, "did:plc:abc123");
+        assert_eq!(uri.collection, "app.bsky.feed.post");
+        assert_eq!(uri.rkey, "3lbqt2dzzc22x");
+        assert_eq!(
+            uri.to_string(),
+            "at://did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x"
+        );
+    }
+
+    #[test]
+    fn tid_timestamps() {
+        // 2024-11-25T05:46:22.021888Z
+        assert_eq!(tid_micros("3lbqt2dzzc22x"), Some(1732513582021888));
+        for bad in [
+            "self",
+            "following_plus",
+            "zzzzzzzzzzzzz",
+            "3lbqt2dzzc22",
+            "3lbqt2dzzc221",
+        ] {
+            assert_eq!(tid_micros(bad), None, "{bad} isnt a TID");
+        }
+    }
+
+    #[test]
+    fn parse_non_tid_rkey() {
+        let uri = AtUri::parse("at://did:web:example.com/app.bsky.feed.generator/following_plus")
+            .unwrap();
+        assert_eq!(uri.did, "did:web:example.com");
+        assert_eq!(uri.rkey, "following_plus");
+        assert_eq!(AtUri::post("did:web:example.com", "self").rkey, "self");
+    }
+
+    #[test]
+    fn parse_ignores_query_and_fragment() {
+        let uri =
+            AtUri::parse("at://did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x?x=1#frag").unwrap();
+        assert_eq!(uri.rkey, "3lbqt2dzzc22x");
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        for bad in [
+            "",
+            "did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x",
+            "https://did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x",
+            "at://did:plc:abc123",
+            "at://did:plc:abc123/app.bsky.feed.post",
+            "at://did:plc:abc123/app.bsky.feed.post/",
+            "at://alice.bsky.social/app.bsky.feed.post/3lbqt2dzzc22x",
+            "at://did:plc:abc123/notansid/3lbqt2dzzc22x",
+            "at://did:plc:abc123/app.bsky.feed.post/..",
+            "at://did:plc:abc123/app.bsky.feed.post/a b",
+            "at://did:plc:abc123/app.bsky.feed.post/3lbqt2dzzc22x/extra",
+        ] {
+            assert_eq!(AtUri::parse(bad), None, "{bad} should not parse");
+        }
+    }
+
+    use crate::bsky::{
+        self,
+        car::parse_repo,
+        did::DidResolver,
+        types::RecNotFound,
+        xrpc::{Bucket, XrpcClient, retry_after},
+    };
+    use axum::{
+        Json, Router,
+        body::Body,
+        extract::{Path, Query, State},
+        http::{HeaderMap, HeaderValue, StatusCode},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use serde_json::{Value, json};
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant, SystemTime},
+    };
+
+    #[derive(Default)]
+    struct Stub {
+        base: String,
+        resolves: AtomicUsize,
+        lists: AtomicUsize,
+    }
+
+    fn did_doc(did: &str, pds: &str) -> Value {
+        json!({
+            "id": did,
+            "service": [
+                {"id": "#bsky_fg", "type": "BskyFeedGenerator", "serviceEndpoint": "https://elsewhere.example"},
+                {"id": "#atproto_pds", "type": "AtprotoPersonalDataServer", "serviceEndpoint": format!("{pds}/")}
+            ]
+        })
+    }
+
+    async fn plc(
+        Path(did): Path<String>,
+        State(stub): State<Arc<Stub>>,
+    ) -> (StatusCode, Json<Value>) {
+        stub.resolves.fetch_add(1, Ordering::SeqCst);
+        match did.as_str() {
+            "did:plc:nobody" => (
+                StatusCode::NOT_FOUND,
+                Json(json!({"message": "DID not registered"})),
+            ),
+            _ => (StatusCode::OK, Json(did_doc(&did, &stub.base))),
+        }
+    }
+
+    async fn web_did(State(stub): State<Arc<Stub>>) -> Json<Value> {
+        stub.resolves.fetch_add(1, Ordering::SeqCst);
+        Json(did_doc("did:web:stub", &stub.base))
+    }
+
+    fn follow(repo: &str, i: usize) -> Value {
+        json!({
+            "uri": format!("at://{repo}/app.bsky.graph.follow/rkey{i}"),
+            "cid": "bafy",
+            "value": {
+                "$type": "app.bsky.graph.follow",
+                "subject": format!("did:plc:friend{i}"),
+                "createdAt": "2024-01-01T00:00:00.000Z"
+            }
+        })
+    }
+
+    fn page(records: Vec<Value>, cursor: Option<&str>) -> Response {
+        Json(json!({"records": records, "cursor": cursor})).into_response()
+    }
+
+    /// Three pages of follows for alice, and a repo per way a PDS can misbehave. Anyone else doesnt exist
+    async fn list_records(
+        Query(params): Query<HashMap<String, String>>,
+        State(stub): State<Arc<Stub>>,
+    ) -> Response {
+        let calls = stub.lists.fetch_add(1, Ordering::SeqCst);
+        let repo = params.get("repo").cloned().unwrap_or_default();
+        match repo.as_str() {
+            "did:plc:alice" => {
+                let n: usize = params
+                    .get("cursor")
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(0);
+                let next = (n + 1).to_string();
+                let cursor = match n < 2 {
+                    true => Some(next.as_str()),
+                    false => None,
+                };
+                page(vec![follow(&repo, n * 2), follow(&repo, n * 2 + 1)], cursor)
+            }
+            // Rate limited twice before it answers
+            "did:plc:busy" if calls < 2 => {
+                (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")]).into_response()
+            }
+            "did:plc:busy" => page(vec![follow(&repo, 0)], None),
+            "did:plc:broken" => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+            // Hands back the same cursor forever
+            "did:plc:looping" => page(vec![follow(&repo, 0)], Some("again")),
+            // Answers, but says the window is used up for the next second
+            "did:plc:exhausted" => {
+                let mut resp = page(vec![follow(&repo, 0)], None);
+                resp.headers_mut()
+                    .insert("ratelimit-remaining", HeaderValue::from_static("0"));
+                resp.headers_mut()
+                    .insert("ratelimit-reset", HeaderValue::from_static("1"));
+                resp
+            }
+            _ => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    json!({"error": "InvalidRequest", "message": format!("Could not find repo: {repo}")}),
+                ),
+            )
+                .into_response(),
+        }
+    }
+
+    /// A signed v3 repo, laid out the way a PDS exports it: the commit, then the MST depth first. Holds 3 follows,
+    /// a block, a list with 2 members & a few records we dont care about
+    const REPO_CAR: &[u8] = include_bytes!("fixtures/repo.car");
+
+    async fn get_repo(Query(params): Query<HashMap<String, String>>) -> Response {
+        match params.get("did").map(|d| d.as_str()) {
+            Some("did:plc:alice") => REPO_CAR.into_response(),
+            // Chunked, so there is no Content-Length to go on
+            Some("did:plc:unsized") => {
+                let chunks = REPO_CAR
+                    .chunks(256)
+                    .map(|c| Ok::<_, std::io::Error>(c.to_vec()));
+                Body::from_stream(futures::stream::iter(chunks)).into_response()
+            }
+            _ => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "RepoNotFound", "message": "Could not find repo"})),
+            )
+                .into_response(),
+        }
+    }
+
+    /// Serves as both the PLC directory & the PDS everyone lives on
+    async fn stub_server() -> Arc<Stub> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let stub = Arc::new(Stub {
+            base,
+            ..Default::default()
+        });
+        let router = Router::new()
+            .route("/.well-known/did.json", get(web_did))
+            .route("/xrpc/com.atproto.repo.listRecords", get(list_records))
+            .route("/xrpc/com.atproto.sync.getRepo", get(get_repo))
+            .route("/:did", get(plc))
+            .with_state(stub.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        stub
+    }
+
+    fn client() -> Arc<XrpcClient> {
+        Arc::new(XrpcClient::new(1000.0, 1000.0, 3).with_backoff(Duration::from_millis(1)))
+    }
+
+    fn resolver(stub: &Stub) -> DidResolver {
+        DidResolver::new(client(), &stub.base, Duration::from_secs(60)).with_web_scheme("http")
+    }
+
+    #[tokio::test]
+    async fn resolves_plc_to_pds_and_caches() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        assert_eq!(resolver.pds("did:plc:alice").await.unwrap(), stub.base);
+        assert_eq!(resolver.pds("did:plc:alice").await.unwrap(), stub.base);
+        assert_eq!(stub.resolves.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cache_expires_after_ttl() {
+        let stub = stub_server().await;
+        let resolver = DidResolver::new(client(), &stub.base, Duration::ZERO);
+
+        resolver.pds("did:plc:alice").await.unwrap();
+        resolver.pds("did:plc:alice").await.unwrap();
+        assert_eq!(stub.resolves.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_resolutions_are_swept() {
+        let stub = stub_server().await;
+        let resolver = DidResolver::new(client(), &stub.base, Duration::from_millis(20));
+
+        resolver.pds("did:plc:alice").await.unwrap();
+        resolver.pds("did:plc:bob").await.unwrap();
+        assert_eq!(resolver.cached(), 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        resolver.pds("did:plc:carol").await.unwrap();
+        assert_eq!(resolver.cached(), 1);
+    }
+
+    #[tokio::test]
+    async fn resolves_did_web() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+        let host = stub.base.trim_start_matches("http://").replace(':', "%3A");
+
+        let pds = resolver.pds(&format!("did:web:{host}")).await.unwrap();
+        assert_eq!(pds, stub.base);
+    }
+
+    #[tokio::test]
+    async fn unknown_did_is_not_found() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        for did in ["did:plc:nobody", "did:key:z6Mk", "did:web:"] {
+            let err = resolver.pds(did).await.unwrap_err();
+            assert!(err.0.is::<RecNotFound>(), "{did} should not resolve");
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_are_listed_from_their_pds() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let follows = bsky::get_follows("did:plc:alice".into(), &client(), &resolver)
+            .await
+            .unwrap();
+        assert_eq!(follows.len(), 6);
+        assert_eq!(
+            follows[0],
+            ("did:plc:friend0".to_owned(), "rkey0".to_owned())
+        );
+        assert_eq!(
+            follows[5],
+            ("did:plc:friend5".to_owned(), "rkey5".to_owned())
+        );
+        assert_eq!(stub.lists.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn missing_repo_is_not_found() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let err = bsky::get_follows("did:plc:gone".into(), &client(), &resolver)
+            .await
+            .unwrap_err();
+        assert!(err.0.is::<RecNotFound>());
+        assert_eq!(err.1, Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn retries_after_429() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let follows = bsky::get_follows("did:plc:busy".into(), &client(), &resolver)
+            .await
+            .unwrap();
+        assert_eq!(follows.len(), 1);
+        assert_eq!(stub.lists.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_failing_pds() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let err = bsky::get_follows("did:plc:broken".into(), &client(), &resolver)
+            .await
+            .unwrap_err();
+        assert_eq!(err.1, Some(StatusCode::SERVICE_UNAVAILABLE));
+        // The first try & 3 retries
+        assert_eq!(stub.lists.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn stops_on_a_repeated_cursor() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let follows = bsky::get_follows("did:plc:looping".into(), &client(), &resolver)
+            .await
+            .unwrap();
+        assert_eq!(follows.len(), 2);
+        assert_eq!(stub.lists.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn waits_out_an_exhausted_rate_limit() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+        let client = client();
+
+        bsky::get_follows("did:plc:exhausted".into(), &client, &resolver)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        bsky::get_follows("did:plc:exhausted".into(), &client, &resolver)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn bucket_refills_at_its_rate() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(2.0);
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), Some(Duration::from_millis(500)));
+        assert_eq!(bucket.take(start + Duration::from_millis(500)), None);
+
+        bucket.pause_until(start + Duration::from_secs(10));
+        assert_eq!(
+            bucket.take(start + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+    }
+
+    #[test]
+    fn bucket_is_idle_once_refilled_and_unpaused() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(2.0);
+        assert!(bucket.idle(start));
+        assert_eq!(bucket.take(start), None);
+        assert!(!bucket.idle(start));
+        assert!(bucket.idle(start + Duration::from_millis(500)));
+
+        bucket.pause_until(start + Duration::from_secs(10));
+        assert!(!bucket.idle(start + Duration::from_secs(5)));
+        assert!(bucket.idle(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_470);
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers, now), None);
+
+        headers.insert("retry-after", HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            "retry-after",
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(10)));
+
+        headers.insert("retry-after", HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers, now), None);
+    }
+
+    fn pairs(p: &[(&str, &str)]) -> Vec<(String, String)> {
+        p.iter()
+            .map(|(s, r)| (s.to_string(), r.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_graph_records_from_car() {
+        let repo = parse_repo(REPO_CAR).unwrap();
+        assert_eq!(
+            repo.follows,
+            pairs(&[
+                ("did:plc:friend1", "3lbep4gmd222b"),
+                ("did:plc:friend2", "3lbep67tes22b"),
+                ("did:plc:friend3", "3lbep7z2gk22b"),
+            ])
+        );
+        assert_eq!(repo.blocks, pairs(&[("did:plc:troll", "3lbep2nfbc22b")]));
+        assert_eq!(
+            repo.list_items,
+            pairs(&[
+                ("did:plc:friend1", "3lbepfepls22b"),
+                ("did:plc:friend4", "3lbeph5wnk22b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_broken_cars() {
+        assert!(parse_repo(&[]).is_err());
+        assert!(parse_repo(b"not a car at all").is_err());
+        for cut in [10, REPO_CAR.len() / 2, REPO_CAR.len() - 1] {
+            assert!(parse_repo(&REPO_CAR[..cut]).is_err(), "cut at {cut}");
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_repo_records_from_their_pds() {
+        let stub = stub_server().await;
+        let resolver = resolver(&stub);
+
+        let repo = bsky::get_repo_records("did:plc:alice", &client(), &resolver, 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(repo.follows.len(), 3);
+        assert_eq!(repo.blocks.len(), 1);
+
+        let err = bsky::get_repo_records("did:plc:alice", &client(), &resolver, 100)
+            .await
+            .unwrap_err();
+        assert!(!err.0.is::<RecNotFound>());
+
+        let repo = bsky::get_repo_records("did:plc:unsized", &client(), &resolver, 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(repo.follows.len(), 3);
+        let err = bsky::get_repo_records("did:plc:unsized", &client(), &resolver, 1000)
+            .await
+            .unwrap_err();
+        assert!(err.0.to_string().contains("over 1000 bytes"));
+
+        let err = bsky::get_repo_records("did:plc:gone", &client(), &resolver, 1 << 20)
+            .await
+            .unwrap_err();
+        assert!(err.0.is::<RecNotFound>());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use ciborium::Value;
+use tracing::warn;
+
+const FOLLOW: &str = "app.bsky.graph.follow";
+const BLOCK: &str = "app.bsky.graph.block";
+const LIST_ITEM: &str = "app.bsky.graph.listitem";
+/// DAG-CBOR's tag for a CID
+const CID_TAG: u64 = 42;
+
+/// Block data by binary CID
+type Blocks<'a> = HashMap<Vec<u8>, &'a [u8]>;
+
+/// The graph records in a repo, each as (subject, rkey) like `bsky::get_follows` returns
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RepoRecords {
+    pub follows: Vec<(String, String)>,
+    pub blocks: Vec<(String, String)>,
+    pub list_items: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct CarError(String);
+
+impl std::fmt::Display for CarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid repo CAR: {}", self.0)
+    }
+}
+
+impl core::error::Error for CarError {}
+
+fn err<T>(msg: impl Into<String>) -> Result<T, CarError> {
+    Err(CarError(msg.into()))
+}
+
+/// Walks a `com.atproto.sync.getRepo` export from its commit, down the MST, to every follow, block & list item
+pub fn parse_repo(car: &[u8]) -> Result<RepoRecords, CarError> {
+    let (root, blocks) = read_car(car)?;
+    let commit = decode(&blocks, &root)?;
+    let data = match map_get(&commit, "data").and_then(as_cid) {
+        Some(d) => d,
+        None => return err("commit has no data"),
+    };
+
+    let mut records = RepoRecords::default();
+    for (key, cid) in walk_mst(&blocks, data)? {
+        let (collection, rkey) = match key.split_once('/') {
+            Some(k) => k,
+            None => continue,
+        };
+        let list = match collection {
+            FOLLOW => &mut records.follows,
+            BLOCK => &mut records.blocks,
+            LIST_ITEM => &mut records.list_items,
+            _ => continue,
+        };
+        // Partial exports can leave out record blocks, which isnt worth failing the whole repo over
+        let record = match decode(&blocks, &cid) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Skipping {}: {}", key, e);
+                continue;
+            }
+        };
+        if let Some(subject) = map_get(&record, "subject").and_then(|s| s.as_text()) {
+            list.push((subject.to_owned(), rkey.to_owned()));
+        }
+    }
+    Ok(records)
+}
+
+/// The root CID & every block by CID
+fn read_car(car: &[u8]) -> Result<(Vec<u8>, Blocks<'_>), CarError> {
+    let mut pos = 0;
+    let header_len = read_varint(car, &mut pos)? as usize;
+    let header: Value = match slice(car, pos, header_len) {
+        Some(h) => match ciborium::from_reader(h) {
+            Ok(v) => v,
+            Err(e) => return err(format!("header: {e}")),
+        },
+        None => return err("truncated header"),
+    };
+    pos += header_len;
+
+    let root = match map_get(&header, "roots")
+        .and_then(|r| r.as_array())
+        .and_then(|r| r.first())
+        .and_then(as_cid)
+    {
+        Some(r) => r,
+        None => return err("no root"),
+    };
+
+    let mut blocks = HashMap::new();
+    while pos < car.len() {
+        let len = read_varint(car, &mut pos)? as usize;
+        let block = match slice(car, pos, len) {
+            Some(b) => b,
+            None => return err("truncated block"),
+        };
+        let cid_len = cid_len(block)?;
+        blocks.insert(block[..cid_len].to_vec(), &block[cid_len..]);
+        pos += len;
+    }
+    Ok((root, blocks))
+}
+
+/// (key, record CID) for every entry, in key order. Keys are stored as a count of bytes shared with the
+/// previous key in the node, & the rest
+fn walk_mst(blocks: &Blocks, root: Vec<u8>) -> Result<Vec<(String, Vec<u8>)>, CarError> {
+    let mut entries = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(cid) = stack.pop() {
+        // A well formed tree never links back, but a bad export shouldnt loop us forever
+        if !visited.insert(cid.clone()) {
+            continue;
+        }
+        let node = decode(blocks, &cid)?;
+        let mut subtrees = Vec::new();
+        if let Some(left) = map_get(&node, "l").and_then(as_cid) {
+            subtrees.push(left);
+        }
+
+        let mut key: Vec<u8> = Vec::new();
+        let items = map_get(&node, "e").and_then(|e| e.as_array());
+        for e in items.into_iter().flatten() {
+            let prefix = map_get(e, "p").and_then(|p| p.as_integer());
+            let suffix = map_get(e, "k").and_then(|k| k.as_bytes());
+            let value = map_get(e, "v").and_then(as_cid);
+            let (prefix, suffix, value) = match (prefix, suffix, value) {
+                (Some(p), Some(s), Some(v)) => (u64::try_from(p).unwrap_or(0) as usize, s, v),
+                _ => return err("malformed MST entry"),
+            };
+            if prefix > key.len() {
+                return err("MST key prefix longer than the previous key");
+            }
+            key.truncate(prefix);
+            key.extend_from_slice(suffix);
+            entries.push((String::from_utf8_lossy(&key).into_owned(), value));
+
+            if let Some(right) = map_get(e, "t").and_then(as_cid) {
+                subtrees.push(right);
+            }
+        }
+        stack.extend(subtrees);
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn decode(blocks: &Blocks, cid: &[u8]) -> Result<Value, CarError> {
+    match blocks.get(cid) {
+        Some(b) => match ciborium::from_reader(*b) {
+            Ok(v) => Ok(v),
+            Err(e) => err(format!("block: {e}")),
+        },
+        None => err("missing block"),
+    }
+}
+
+fn map_get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    value
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+/// The binary CID out of a tag 42, minus the leading multibase 0x00. Null links are None
+fn as_cid(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Tag(CID_TAG, inner) => match inner.as_bytes()?.split_first() {
+            Some((0, cid)) => Some(cid.to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// How many bytes of the block are its CID. v0 is a bare sha256 multihash, v1 is version, codec, then multihash
+fn cid_len(block: &[u8]) -> Result<usize, CarError> {
+    if block.len() >= 34 && block[0] == 0x12 && block[1] == 0x20 {
+        return Ok(34);
+    }
+    let mut pos = 0;
+    let version = read_varint(block, &mut pos)?;
+    if version != 1 {
+        return err(format!("unknown CID version {version}"));
+    }
+    read_varint(block, &mut pos)?; // codec
+    read_varint(block, &mut pos)?; // hash function
+    let digest = read_varint(block, &mut pos)? as usize;
+    match pos.checked_add(digest) {
+        Some(end) if end <= block.len() => Ok(end),
+        _ => err("truncated CID"),
+    }
+}
+
+fn slice(buf: &[u8], pos: usize, len: usize) -> Option<&[u8]> {
+    buf.get(pos..pos.checked_add(len)?)
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64, CarError> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = match buf.get(*pos) {
+            Some(b) => *b,
+            None => return err("truncated varint"),
+        };
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    err("varint too long")
+}
@@ -0,0 +1,143 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use hyper::StatusCode;
+use tracing::info;
+
+use super::{
+    types::{DidDocument, RecNotFound},
+    xrpc::XrpcClient,
+};
+
+const PDS_SERVICE_ID: &str = "#atproto_pds";
+const PDS_SERVICE_TYPE: &str = "AtprotoPersonalDataServer";
+
+/// Finds the PDS hosting a repo from its DID document, caching the answer for `ttl`. Every 2nd degree crawl
+/// resolves a new DID, so expired answers are swept out once a `ttl`
+pub struct DidResolver {
+    client: Arc<XrpcClient>,
+    plc_url: String,
+    web_scheme: String,
+    ttl: Duration,
+    cache: DashMap<String, (String, Instant)>,
+    swept: Mutex<Instant>,
+}
+
+impl DidResolver {
+    pub fn new(client: Arc<XrpcClient>, plc_url: &str, ttl: Duration) -> Self {
+        Self {
+            client,
+            plc_url: plc_url.trim_end_matches('/').to_owned(),
+            web_scheme: "https".to_owned(),
+            ttl,
+            cache: DashMap::new(),
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// `PLC_DIRECTORY` (default https://plc.directory) & `DID_CACHE_TTL_SECS` (default 3600)
+    pub fn from_env(client: Arc<XrpcClient>) -> Self {
+        let plc_url = env::var("PLC_DIRECTORY").unwrap_or("https://plc.directory".into());
+        let ttl = env::var("DID_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        Self::new(client, &plc_url, Duration::from_secs(ttl))
+    }
+
+    /// did:web is always https in the wild, but not on a test stub
+    pub fn with_web_scheme(mut self, scheme: &str) -> Self {
+        self.web_scheme = scheme.to_owned();
+        self
+    }
+
+    /// Base url of the PDS hosting `did`, e.g. `https://morel.us-east.host.bsky.network`.
+    /// A DID that doesnt resolve is a `RecNotFound`
+    pub async fn pds(
+        &self,
+        did: &str,
+    ) -> Result<String, (Box<dyn std::error::Error>, Option<StatusCode>)> {
+        if let Some(hit) = self.cache.get(did)
+            && hit.1.elapsed() < self.ttl
+        {
+            return Ok(hit.0.clone());
+        }
+
+        let url = match self.document_url(did) {
+            Some(u) => u,
+            None => return Err((Box::new(RecNotFound {}), None)),
+        };
+        let resp = self.client.get(&url).await?;
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+            return Err((Box::new(RecNotFound {}), Some(status)));
+        }
+        let doc: DidDocument = match resp.json().await {
+            Ok(d) => d,
+            Err(e) => return Err((Box::new(e), Some(status))),
+        };
+
+        match pds_endpoint(&doc) {
+            Some(pds) => {
+                info!("Resolved {} to {}", did, pds);
+                self.sweep();
+                self.cache
+                    .insert(did.to_owned(), (pds.clone(), Instant::now()));
+                Ok(pds)
+            }
+            None => Err((Box::new(RecNotFound {}), Some(status))),
+        }
+    }
+
+    fn sweep(&self) {
+        {
+            let mut swept = self.swept.lock().unwrap();
+            if swept.elapsed() < self.ttl {
+                return;
+            }
+            *swept = Instant::now();
+        }
+        self.cache.retain(|_, (_, at)| at.elapsed() < self.ttl);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn document_url(&self, did: &str) -> Option<String> {
+        if did.starts_with("did:plc:") {
+            return Some(format!("{}/{}", self.plc_url, did));
+        }
+
+        // did:web:host[:path:segments], where a port is percent-encoded into the host
+        let rest = did.strip_prefix("did:web:")?;
+        let mut parts = rest.split(':');
+        let host = parts.next().filter(|h| !h.is_empty())?.replace("%3A", ":");
+        let path: Vec<&str> = parts.collect();
+        match path.is_empty() {
+            true => Some(format!(
+                "{}://{}/.well-known/did.json",
+                self.web_scheme, host
+            )),
+            false => Some(format!(
+                "{}://{}/{}/did.json",
+                self.web_scheme,
+                host,
+                path.join("/")
+            )),
+        }
+    }
+}
+
+/// The `#atproto_pds` service endpoint, without a trailing slash
+pub fn pds_endpoint(doc: &DidDocument) -> Option<String> {
+    doc.service
+        .iter()
+        .find(|s| s.id.ends_with(PDS_SERVICE_ID) && s.type_field == PDS_SERVICE_TYPE)
+        .map(|s| s.service_endpoint.trim_end_matches('/').to_owned())
+}
@@ -3,14 +3,23 @@ use crate::{
     bsky::types::*,
 };
 use chrono::Utc;
+use did::DidResolver;
 use hyper::StatusCode;
 use once_cell::sync::Lazy;
 use serde::de::DeserializeOwned;
+use std::collections::HashSet;
 use std::{mem, str};
 use tracing::{error, info, warn};
+use uri::AtUri;
+use xrpc::XrpcClient;
 use zstd::bulk::Decompressor;
 
+mod bsky_test;
+pub mod car;
+pub mod did;
 pub mod types;
+pub mod uri;
+pub mod xrpc;
 
 const DICT: &'static [u8; 112640] = include_bytes!("./dictionary");
 static mut DECOMP: Lazy<Decompressor<'static>> =
@@ -71,6 +80,37 @@ pub async fn handle_event_fast(
         };
     }
 
+    // Identity & account events dont have a commit, so handle them before anything else
+    match deser_evt.kind.as_str() {
+        "identity" => {
+            let drift = (Utc::now().timestamp_micros() - deser_evt.time_us) / 1000;
+            return match mem::take(&mut deser_evt.identity) {
+                Some(Identity {
+                    did,
+                    handle: Some(handle),
+                    ..
+                }) => Ok((drift, g.update_handle(did, handle, rec).await)),
+                _ => Ok((0, rec)),
+            };
+        }
+        "account" => {
+            let drift = (Utc::now().timestamp_micros() - deser_evt.time_us) / 1000;
+            let account = match mem::take(&mut deser_evt.account) {
+                Some(a) => a,
+                None => return Ok((0, rec)),
+            };
+            let status = account.status.unwrap_or_default();
+            if !account.active && status == "deleted" {
+                return Ok((drift, g.rm_account(account.did, rec).await));
+            }
+            let recv = g
+                .set_account_status(account.did, account.active, status, rec)
+                .await;
+            return Ok((drift, recv));
+        }
+        _ => {}
+    }
+
     // Missing or unrecognised type
     if deser_evt.commit.get_type() == ATEventType::Unknown {
         return Ok((0, rec));
@@ -111,7 +151,7 @@ pub async fn handle_event_fast(
     let drift = (now - deser_evt.time_us) / 1000;
 
     if commit.operation == "create" {
-        let mut is_reply = false;
+        let mut parent_did = String::new();
         let mut created_at = 0;
         let post_type: String;
 
@@ -129,27 +169,64 @@ pub async fn handle_event_fast(
                         };
                         match &r.reply {
                             Some(r) => {
-                                let did_clone = deser_evt.did.clone();
-                                let rkey_clone = rkey.clone();
-                                let rkey_parent = parse_rkey(&r.parent.uri);
-                                rec = g.add_reply(did_clone, rkey_clone, rkey_parent, rec).await;
-                                is_reply = true;
+                                match (AtUri::parse(&r.parent.uri), AtUri::parse(&r.root.uri)) {
+                                    (Some(parent), Some(root)) => {
+                                        let did_clone = deser_evt.did.clone();
+                                        let rkey_clone = rkey.clone();
+                                        rec = g
+                                            .add_reply(
+                                                did_clone,
+                                                rkey_clone,
+                                                parent.to_string(),
+                                                root.to_string(),
+                                                rec,
+                                            )
+                                            .await;
+                                        parent_did = parent.did;
+                                    }
+                                    _ => warn!(
+                                        "invalid reply uri: {} / {}",
+                                        r.parent.uri, r.root.uri
+                                    ),
+                                }
                             }
                             _ => {}
                         }
 
                         match &r.embed {
                             Some(v) => {
-                                match &v.video {
+                                // Records can also embed lists, feeds etc, but we only care about posts
+                                if let Some(subject) = v
+                                    .record
+                                    .as_ref()
+                                    .and_then(|q| AtUri::parse(q.uri()))
+                                    .filter(|q| q.collection == uri::POST_COLLECTION)
+                                {
+                                    let did_clone = deser_evt.did.clone();
+                                    let rkey_clone = rkey.clone();
+                                    rec = g
+                                        .add_quote(did_clone, rkey_clone, subject.to_string(), rec)
+                                        .await;
+                                }
+
+                                // recordWithMedia keeps its images / video under `media`
+                                let media = match &v.media {
+                                    Some(m) => m.as_ref(),
+                                    None => v,
+                                };
+                                match &media.video {
                                     Some(_) => {
                                         post_type = "v".to_owned();
                                     }
                                     None => {
-                                        match &v.images {
+                                        match &media.images {
                                             Some(_) => {
                                                 post_type = "i".to_owned();
                                             }
-                                            None => post_type = "t".to_owned(),
+                                            None => match &v.record {
+                                                Some(_) => post_type = "q".to_owned(),
+                                                None => post_type = "t".to_owned(),
+                                            },
                                         };
                                     }
                                 };
@@ -161,33 +238,39 @@ pub async fn handle_event_fast(
                 }
 
                 let recv = g
-                    .add_post(deser_evt.did, rkey, &created_at, is_reply, post_type, rec)
+                    .add_post(deser_evt.did, rkey, &created_at, parent_did, post_type, rec)
                     .await;
 
                 return Ok((drift, recv));
             }
 
             ATEventType::Repost => {
-                let rkey_out = get_rkey(&commit);
-
-                if rkey_out.is_empty() {
-                    error!("empty rkey: repost");
-                    return Ok((0, rec));
-                }
+                let subject = match get_subject(&commit) {
+                    Some(s) => s,
+                    None => {
+                        error!("empty or invalid subject: repost");
+                        return Ok((0, rec));
+                    }
+                };
 
-                let recv = g.add_repost(deser_evt.did, rkey_out, rkey, rec).await;
+                let recv = g
+                    .add_repost(deser_evt.did, subject.to_string(), rkey, rec)
+                    .await;
                 return Ok((drift, recv));
             }
 
             ATEventType::Like => {
-                let rkey_out = get_rkey(&commit);
-
-                if rkey_out.is_empty() {
-                    error!("empty rkey: like");
-                    return Ok((0, rec));
-                }
+                let subject = match get_subject(&commit) {
+                    Some(s) => s,
+                    None => {
+                        error!("empty or invalid subject: like");
+                        return Ok((0, rec));
+                    }
+                };
 
-                let recv = g.add_like(deser_evt.did, rkey_out, rkey, rec).await;
+                let recv = g
+                    .add_like(deser_evt.did, subject.to_string(), rkey, rec)
+                    .await;
                 return Ok((drift, recv));
             }
 
@@ -239,6 +322,9 @@ pub async fn handle_event_fast(
     } else if commit.operation == "delete" {
         match commit.get_type() {
             ATEventType::Post => {
+                // We dont know if the post was a quote or reply, so always try to drop their edges
+                rec = g.rm_quote(deser_evt.did.clone(), rkey.clone(), rec).await;
+                rec = g.rm_reply(deser_evt.did.clone(), rkey.clone(), rec).await;
                 let recv = g.rm_post(deser_evt.did, rkey, rec).await;
                 return Ok((drift, recv));
             }
@@ -266,32 +352,14 @@ pub async fn handle_event_fast(
     return Ok((0, rec));
 }
 
-fn parse_rkey(uri: &str) -> String {
-    // the rkey are the last 13 characters
-    uri.chars()
-        .rev()
-        .take(13)
-        .collect::<Vec<_>>()
-        .iter()
-        .rev()
-        .collect()
-}
-
-fn get_rkey(commit: &Commit) -> String {
-    let rkey_out;
+fn get_subject(commit: &Commit) -> Option<AtUri> {
     match &commit.record {
-        Some(r) => {
-            rkey_out = match &r.subject {
-                Some(s) => match s {
-                    Subj::T1(_) => "".to_owned(),
-                    Subj::T2(subject) => parse_rkey(&subject.uri),
-                },
-                None => "".to_owned(),
-            };
-        }
-        None => rkey_out = "".to_owned(),
+        Some(r) => match &r.subject {
+            Some(Subj::T2(subject)) => AtUri::parse(&subject.uri),
+            _ => None,
+        },
+        None => None,
     }
-    rkey_out
 }
 
 pub trait Recordable<V: Subjectable> {
@@ -307,118 +375,179 @@ pub trait Subjectable {
 async fn get<V: Subjectable, T: DeserializeOwned + Recordable<V>>(
     uri: &str,
     did: String,
-    client: reqwest::Client,
+    client: &XrpcClient,
 ) -> Result<Vec<(String, String)>, (Box<dyn std::error::Error>, Option<StatusCode>)> {
     let mut res: Vec<(String, String)> = Vec::new();
-    let mut req = match client.get(uri).build() {
-        Ok(r) => r,
-        Err(e) => {
-            return Err((Box::new(e), None));
-        }
-    };
-    let mut resp: T = match client.execute(req).await {
-        Ok(resp) => {
-            let status = resp.status();
-            let body = match resp.bytes().await {
-                Ok(b) => b,
-                Err(e) => {
-                    warn!(
-                        "unable to marshal body, where the request returned returned {}: {:?}",
-                        status, e
-                    );
-                    return Err((Box::new(e), Some(status)));
-                }
-            };
-
-            let res: T = match serde_json::from_slice(&body) {
-                Ok(r) => r,
-                Err(e) => {
-                    warn!(
-                        "resp returned {} :: {:?} : {:?}",
-                        status,
-                        str::from_utf8(&body).unwrap(),
-                        e
-                    );
-                    return Err((Box::new(e), Some(status)));
-                }
-            };
-
-            res
-        }
-        Err(e) => {
-            let status = e.status();
-            return Err((Box::new(e), status));
-        }
-    };
+    let mut cursor: Option<String> = None;
+    let mut seen_cursors: HashSet<String> = HashSet::new();
 
     loop {
+        let url = match &cursor {
+            Some(c) => format!("{uri}&cursor={c}"),
+            None => uri.to_owned(),
+        };
+        // Retries already happened in the client, so a page that still fails fails the whole listing.
+        // Half a follow list is worse than none
+        let resp: T = get_page(&url, &did, client).await?;
+
         for f in resp.records() {
             let subject = f.subject();
-            let rkey = parse_rkey(&f.uri());
-            res.push((subject.to_owned(), rkey));
+            match AtUri::parse(f.uri()) {
+                Some(u) => res.push((subject.to_owned(), u.rkey)),
+                None => warn!("invalid record uri for {}: {}", &did, f.uri()),
+            };
         }
-        match &resp.cursor() {
-            Some(c) => {
-                let url = uri.to_owned() + format!("&cursor={}", c).as_str();
-                req = match client.get(&url).build() {
-                    Ok(r) => r,
-                    Err(ee) => return Err((Box::new(ee), None)),
-                };
-                let r = match client.execute(req).await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        warn!(
-                            "Error fetching {} for {} {:?}",
-                            std::any::type_name::<T>(),
-                            &did,
-                            e
-                        );
-                        continue;
-                    }
-                };
-
-                let rr = r.status();
-                resp = match r.json().await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        warn!(
-                            "Error getting {} for {}: {} : {:?}",
-                            std::any::type_name::<T>(),
-                            did,
-                            rr,
-                            e
-                        );
-                        break;
-                    }
-                };
-            }
-            None => {
+        match resp.cursor() {
+            Some(c) if !seen_cursors.insert(c.clone()) => {
+                warn!(
+                    "Got cursor {} twice listing {} for {}, stopping",
+                    c,
+                    std::any::type_name::<T>(),
+                    did
+                );
                 break;
             }
+            Some(c) => cursor = Some(c.clone()),
+            None => break,
         }
     }
 
     Ok(res)
 }
 
+async fn get_page<V: Subjectable, T: DeserializeOwned + Recordable<V>>(
+    url: &str,
+    did: &str,
+    client: &XrpcClient,
+) -> Result<T, (Box<dyn std::error::Error>, Option<StatusCode>)> {
+    let resp = client.get(url).await?;
+    let status = resp.status();
+    let body = match resp.bytes().await {
+        Ok(b) => b,
+        Err(e) => {
+            warn!(
+                "unable to marshal body, where the request returned returned {}: {:?}",
+                status, e
+            );
+            return Err((Box::new(e), Some(status)));
+        }
+    };
+
+    if !status.is_success()
+        && let Ok(err) = serde_json::from_slice::<XrpcError>(&body)
+        && is_repo_not_found(&err)
+    {
+        return Err((Box::new(RecNotFound {}), Some(status)));
+    }
+
+    match serde_json::from_slice(&body) {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            warn!(
+                "resp for {} returned {} :: {:?} : {:?}",
+                did,
+                status,
+                String::from_utf8_lossy(&body),
+                e
+            );
+            Err((Box::new(e), Some(status)))
+        }
+    }
+}
+
+/// A repo that was deleted, taken down, or never existed on the PDS we asked
+fn is_repo_not_found(err: &XrpcError) -> bool {
+    err.error == "RepoNotFound"
+        || err
+            .message
+            .as_ref()
+            .is_some_and(|m| m.contains("Could not find repo"))
+}
+
+/// listRecords against the PDS hosting `did`, rather than whichever one bsky.social proxies to
+async fn list_records_url(
+    did: &str,
+    collection: &str,
+    resolver: &DidResolver,
+) -> Result<String, (Box<dyn std::error::Error>, Option<StatusCode>)> {
+    let pds = resolver.pds(did).await?;
+    Ok(format!(
+        "{pds}/xrpc/com.atproto.repo.listRecords?repo={did}&collection={collection}&limit=100"
+    ))
+}
+
 pub async fn get_follows(
     did: String,
-    client: reqwest::Client,
+    client: &XrpcClient,
+    resolver: &DidResolver,
 ) -> Result<Vec<(String, String)>, (Box<dyn std::error::Error>, Option<StatusCode>)> {
     info!("Getting follows for {:?}", did);
-    let base_url = format!(
-        "https://bsky.social/xrpc/com.atproto.repo.listRecords?repo={did}&collection=app.bsky.graph.follow&limit=100"
-    );
+    let base_url = list_records_url(&did, "app.bsky.graph.follow", resolver).await?;
     get::<Follow, FollowsResp>(&base_url, did, client).await
 }
 
 pub async fn get_blocks(
     did: String,
-    client: reqwest::Client,
+    client: &XrpcClient,
+    resolver: &DidResolver,
 ) -> Result<Vec<(String, String)>, (Box<dyn std::error::Error>, Option<StatusCode>)> {
     info!("Getting blocks for {:?}", did);
-    let base_url = format!(
-        "https://bsky.social/xrpc/com.atproto.repo.listRecords?repo={did}&collection=app.bsky.graph.block&limit=100"
-    );
+    let base_url = list_records_url(&did, "app.bsky.graph.block", resolver).await?;
     get::<Block, BlocksResp>(&base_url, did, client).await
 }
+
+/// The viewer's whole repo in one request, rather than paging through each collection.
+/// Anything over `max_bytes` is an error, so the caller can fall back to `get_follows` & `get_blocks`
+pub async fn get_repo_records(
+    did: &str,
+    client: &XrpcClient,
+    resolver: &DidResolver,
+    max_bytes: u64,
+) -> Result<car::RepoRecords, (Box<dyn std::error::Error>, Option<StatusCode>)> {
+    info!("Getting repo for {:?}", did);
+    let pds = resolver.pds(did).await?;
+    let mut resp = client
+        .get(&format!("{pds}/xrpc/com.atproto.sync.getRepo?did={did}"))
+        .await?;
+    let status = resp.status();
+    let too_big = || {
+        Err((
+            format!("repo for {did} is over {max_bytes} bytes").into(),
+            Some(status),
+        ))
+    };
+    if resp.content_length().is_some_and(|l| l > max_bytes) {
+        return too_big();
+    }
+    // Content-Length is optional, so the cap is enforced as the body comes in too
+    let mut body = Vec::new();
+    loop {
+        match resp.chunk().await {
+            Ok(Some(chunk)) => {
+                if (body.len() + chunk.len()) as u64 > max_bytes {
+                    return too_big();
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(None) => break,
+            Err(e) => return Err((Box::new(e), Some(status))),
+        }
+    }
+
+    if !status.is_success() {
+        if let Ok(err) = serde_json::from_slice::<XrpcError>(&body)
+            && is_repo_not_found(&err)
+        {
+            return Err((Box::new(RecNotFound {}), Some(status)));
+        }
+        return Err((
+            format!("getRepo for {did} returned {status}").into(),
+            Some(status),
+        ));
+    }
+
+    match car::parse_repo(&body) {
+        Ok(r) => Ok(r),
+        Err(e) => Err((Box::new(e), Some(status))),
+    }
+}
@@ -92,6 +92,28 @@ pub struct BskyEvent {
     #[serde(rename = "type")]
     pub type_field: Option<String>,
     pub commit: Option<Commit>,
+    pub identity: Option<Identity>,
+    pub account: Option<Account>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    pub did: String,
+    pub handle: Option<String>,
+    pub seq: Option<i64>,
+    pub time: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub did: String,
+    pub active: bool,
+    /// Only set when the account is inactive, e.g. `deactivated`, `takendown`, `suspended` or `deleted`
+    pub status: Option<String>,
+    pub seq: Option<i64>,
+    pub time: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -218,6 +240,34 @@ pub struct Embed {
     pub aspect_ratio: Option<Aspct>,
     pub images: Option<Vec<MediaInternal>>,
     pub video: Option<MediaInternal>,
+    pub record: Option<EmbedRec>,
+    pub media: Option<Box<Embed>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbedRecord {
+    #[serde(rename = "$type")]
+    pub type_field: Option<String>,
+    pub record: Subject,
+}
+
+/// `app.bsky.embed.record` holds the quoted subject directly, whereas
+/// `app.bsky.embed.recordWithMedia` wraps it in another `record`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbedRec {
+    T1(Subject),
+    T2(EmbedRecord),
+}
+
+impl EmbedRec {
+    pub fn uri(&self) -> &str {
+        match self {
+            EmbedRec::T1(s) => &s.uri,
+            EmbedRec::T2(r) => &r.record.uri,
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -299,3 +349,29 @@ pub enum ATEventType {
     Global,
     Unknown,
 }
+
+/// The parts of a DID document we use, from plc.directory or a did:web's `.well-known/did.json`
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocument {
+    pub id: String,
+    #[serde(default)]
+    pub service: Vec<DidService>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidService {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_field: String,
+    pub service_endpoint: String,
+}
+
+/// The body of any failed XRPC call
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XrpcError {
+    pub error: String,
+    #[serde(default)]
+    pub message: Option<String>,
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+pub const POST_COLLECTION: &str = "app.bsky.feed.post";
+const TID_CHARS: &[u8] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// A record AT-URI, i.e. `at://<did>/<collection>/<rkey>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AtUri {
+    pub did: String,
+    pub collection: String,
+    pub rkey: String,
+}
+
+impl AtUri {
+    /// Parses a record AT-URI, returning None if any of the three parts are missing or malformed.
+    /// Any query string or fragment is ignored
+    pub fn parse(uri: &str) -> Option<Self> {
+        let rest = uri.strip_prefix("at://")?;
+        let rest = match rest.find(['?', '#']) {
+            Some(idx) => &rest[..idx],
+            None => rest,
+        };
+
+        let mut parts = rest.split('/');
+        let did = parts.next()?;
+        let collection = parts.next()?;
+        let rkey = parts.next()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        if !is_valid_did(did) || !is_valid_nsid(collection) || !is_valid_rkey(rkey) {
+            return None;
+        }
+
+        Some(Self {
+            did: did.to_owned(),
+            collection: collection.to_owned(),
+            rkey: rkey.to_owned(),
+        })
+    }
+
+    pub fn post(did: &str, rkey: &str) -> Self {
+        Self {
+            did: did.to_owned(),
+            collection: POST_COLLECTION.to_owned(),
+            rkey: rkey.to_owned(),
+        }
+    }
+}
+
+/// Microseconds since the epoch a TID record key was minted at. None for anything that isnt a TID
+pub fn tid_micros(rkey: &str) -> Option<u64> {
+    // 13 chars is 65 bits, & the top one has to be 0
+    if rkey.len() != 13 || rkey.as_bytes()[0] > b'j' {
+        return None;
+    }
+    let mut n: u64 = 0;
+    for c in rkey.bytes() {
+        let v = TID_CHARS.iter().position(|t| *t == c)? as u64;
+        n = (n << 5) | v;
+    }
+    // The low 10 bits are a clock id
+    Some(n >> 10)
+}
+
+impl fmt::Display for AtUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at://{}/{}/{}", self.did, self.collection, self.rkey)
+    }
+}
+
+fn is_valid_did(did: &str) -> bool {
+    let mut parts = did.splitn(3, ':');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some("did"), Some(method), Some(id))
+            if !method.is_empty()
+                && method.chars().all(|c| c.is_ascii_lowercase())
+                && !id.is_empty()
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || "._:%-".contains(c))
+    )
+}
+
+fn is_valid_nsid(nsid: &str) -> bool {
+    let segments: Vec<&str> = nsid.split('.').collect();
+    segments.len() >= 3
+        && segments
+            .iter()
+            .all(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+fn is_valid_rkey(rkey: &str) -> bool {
+    (1..=512).contains(&rkey.len())
+        && rkey != "."
+        && rkey != ".."
+        && rkey
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._:~-".contains(c))
+}
@@ -0,0 +1,262 @@
+use std::{
+    env,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use dashmap::DashMap;
+use hyper::{HeaderMap, StatusCode};
+use tracing::warn;
+
+/// Longest we will sit out a 429 or an exhausted `RateLimit-*` window before trying again
+const MAX_PAUSE: Duration = Duration::from_secs(300);
+/// How often buckets for hosts we have stopped talking to are dropped
+const SWEEP_EVERY: Duration = Duration::from_secs(60);
+
+/// Requests per second, allowing bursts of up to a second's worth
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl Bucket {
+    pub(crate) fn new(rate: f64) -> Self {
+        let rate = rate.max(0.001);
+        Self {
+            rate,
+            tokens: rate.max(1.0),
+            last: Instant::now(),
+            paused_until: None,
+        }
+    }
+
+    /// Takes a token if there is one, otherwise how long until there will be
+    pub(crate) fn take(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.paused_until
+            && until > now
+        {
+            return Some(until - now);
+        }
+
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate.max(1.0));
+        self.last = now;
+        match self.tokens >= 1.0 {
+            true => {
+                self.tokens -= 1.0;
+                None
+            }
+            false => Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)),
+        }
+    }
+
+    pub(crate) fn pause_until(&mut self, until: Instant) {
+        self.paused_until = Some(self.paused_until.map_or(until, |p| p.max(until)));
+    }
+
+    /// Refilled & not paused, so no different to a new bucket
+    pub(crate) fn idle(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.paused_until.is_none_or(|until| until <= now)
+            && self.tokens + elapsed * self.rate >= self.rate.max(1.0)
+    }
+}
+
+/// The one HTTP client every outbound XRPC call goes through. Rate limited globally & per host, backs off on
+/// 429s & 5xxs, and gives up after `max_retries`
+pub struct XrpcClient {
+    client: reqwest::Client,
+    global: Mutex<Bucket>,
+    hosts: DashMap<String, Bucket>,
+    swept: Mutex<Instant>,
+    host_rate: f64,
+    max_retries: u32,
+    backoff: Duration,
+}
+
+impl XrpcClient {
+    pub fn new(global_rate: f64, host_rate: f64, max_retries: u32) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        Self {
+            client,
+            global: Mutex::new(Bucket::new(global_rate)),
+            hosts: DashMap::new(),
+            swept: Mutex::new(Instant::now()),
+            host_rate,
+            max_retries,
+            backoff: Duration::from_millis(500),
+        }
+    }
+
+    /// `XRPC_GLOBAL_RPS` (default 50), `XRPC_HOST_RPS` (default 10) & `XRPC_MAX_RETRIES` (default 3)
+    pub fn from_env() -> Self {
+        let rate = |key: &str, default: f64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let max_retries = env::var("XRPC_MAX_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+        Self::new(
+            rate("XRPC_GLOBAL_RPS", 50.0),
+            rate("XRPC_HOST_RPS", 10.0),
+            max_retries,
+        )
+    }
+
+    /// First wait between retries, doubling each attempt, when the server doesnt say how long
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// GETs `url` once there is room under both limits. 429s & 5xxs are retried, and are only an error once
+    /// we run out of attempts; anything else is handed back for the caller to deal with
+    pub async fn get(
+        &self,
+        url: &str,
+    ) -> Result<reqwest::Response, (Box<dyn std::error::Error>, Option<StatusCode>)> {
+        let host = match reqwest::Url::parse(url) {
+            Ok(u) => format!(
+                "{}:{}",
+                u.host_str().unwrap_or(""),
+                u.port_or_known_default().unwrap_or(0)
+            ),
+            Err(e) => return Err((Box::new(e), None)),
+        };
+
+        let mut attempt = 0;
+        loop {
+            self.acquire(&host).await;
+            let backoff = self.backoff * 2u32.saturating_pow(attempt);
+            attempt += 1;
+            let out_of_attempts = attempt > self.max_retries;
+
+            let resp = match self.client.get(url).send().await {
+                Ok(r) => r,
+                Err(e) if out_of_attempts || !(e.is_timeout() || e.is_connect()) => {
+                    let status = e.status();
+                    return Err((Box::new(e), status));
+                }
+                Err(e) => {
+                    warn!("Retrying {} in {:?}: {}", url, backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    continue;
+                }
+            };
+
+            let status = resp.status();
+            self.observe(&host, resp.headers());
+            if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                return Ok(resp);
+            }
+            if out_of_attempts {
+                return Err((
+                    format!("{url} still returning {status} after {attempt} attempts").into(),
+                    Some(status),
+                ));
+            }
+
+            let wait = retry_after(resp.headers(), SystemTime::now())
+                .unwrap_or(backoff)
+                .min(MAX_PAUSE);
+            warn!("{} returned {}, retrying in {:?}", url, status, wait);
+            match status == StatusCode::TOO_MANY_REQUESTS {
+                // Everyone else hitting this host has to wait too
+                true => self.pause(&host, wait),
+                false => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    fn pause(&self, host: &str, wait: Duration) {
+        self.hosts
+            .entry(host.to_owned())
+            .or_insert_with(|| Bucket::new(self.host_rate))
+            .pause_until(Instant::now() + wait);
+    }
+
+    /// Stops sending to a host once it says we have used up its window
+    fn observe(&self, host: &str, headers: &HeaderMap) {
+        let remaining = header_u64(headers, "ratelimit-remaining");
+        if remaining != Some(0) {
+            return;
+        }
+        if let Some(reset) = header_u64(headers, "ratelimit-reset") {
+            let wait = reset_wait(reset, SystemTime::now()).min(MAX_PAUSE);
+            warn!("Rate limit used up on {}, pausing {:?}", host, wait);
+            self.pause(host, wait);
+        }
+    }
+
+    async fn acquire(&self, host: &str) {
+        self.sweep();
+        loop {
+            let wait = self
+                .hosts
+                .entry(host.to_owned())
+                .or_insert_with(|| Bucket::new(self.host_rate))
+                .take(Instant::now());
+            match wait {
+                Some(w) => tokio::time::sleep(w).await,
+                None => break,
+            }
+        }
+        loop {
+            let wait = self.global.lock().unwrap().take(Instant::now());
+            match wait {
+                Some(w) => tokio::time::sleep(w).await,
+                None => break,
+            }
+        }
+    }
+
+    /// Every PDS we ever crawl gets a bucket, so the idle ones are dropped now & then
+    fn sweep(&self) {
+        let now = Instant::now();
+        {
+            let mut swept = self.swept.lock().unwrap();
+            if now.saturating_duration_since(*swept) < SWEEP_EVERY {
+                return;
+            }
+            *swept = now;
+        }
+        self.hosts.retain(|_, bucket| !bucket.idle(now));
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `RateLimit-Reset` is a unix timestamp from atproto PDSes, but seconds from now in the IETF draft
+fn reset_wait(reset: u64, now: SystemTime) -> Duration {
+    let now_secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match reset > 1_000_000_000 {
+        true => Duration::from_secs(reset.saturating_sub(now_secs)),
+        false => Duration::from_secs(reset),
+    }
+}
+
+/// `Retry-After` as either delay-seconds or an HTTP date
+pub(crate) fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at: SystemTime = chrono::DateTime::parse_from_rfc2822(value).ok()?.into();
+    Some(at.duration_since(now).unwrap_or_default())
+}
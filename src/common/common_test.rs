@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod common_test {
+    use std::collections::HashSet;
+
+    use base64::{Engine as _, engine::general_purpose};
+
+    use crate::{
+        common::{
+            PostMsg,
+            cursor::{self, Cursor},
+            feed::parse_flag,
+            stats::{IngestStats, PurgeImpact, rate},
+        },
+        ranking::{Ranked, best_first},
+        server::types,
+    };
+
+    const HORIZON: u64 = 1_732_000_000_000_000;
+
+    /// Strictly decreasing scores, with a run of ties in the middle
+    fn ranked(n: usize) -> Vec<Ranked> {
+        let mut ranked: Vec<Ranked> = (0..n)
+            .map(|i| Ranked {
+                score: match i {
+                    10..20 => 50.0,
+                    _ => 100.0 - i as f64,
+                },
+                post: PostMsg {
+                    uri: format!("at://did:plc:user{i}/app.bsky.feed.post/3k{i:03}"),
+                    timestamp: HORIZON - 1_000 * (i as u64 % 3),
+                    ..Default::default()
+                },
+            })
+            .collect();
+        ranked.sort_by(best_first);
+        ranked
+    }
+
+    fn drain(all: &[Ranked], limit: usize) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut cursor: Option<Cursor> = None;
+        loop {
+            // Round trip through the wire format like a client would
+            let (page, next) = cursor::page(all.to_vec(), cursor.as_ref(), limit, HORIZON);
+            assert!(page.len() <= limit);
+            seen.extend(page.into_iter().map(|r| r.post.uri));
+            match next {
+                Some(c) => cursor = Some(Cursor::decode(&c.encode()).unwrap()),
+                None => return seen,
+            }
+        }
+    }
+
+    #[test]
+    fn skeleton_carries_repost_reason_and_context() {
+        let post = PostMsg {
+            uri: "at://did:plc:author/app.bsky.feed.post/3k".to_owned(),
+            reason: "GET_BEST_2ND_DEG_REPOSTS".to_owned(),
+            repost: Some("at://did:plc:friend/app.bsky.feed.repost/3j".to_owned()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(types::Post::from(&post)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "post": "at://did:plc:author/app.bsky.feed.post/3k",
+                "reason": {
+                    "$type": "app.bsky.feed.defs#skeletonReasonRepost",
+                    "repost": "at://did:plc:friend/app.bsky.feed.repost/3j",
+                },
+                "feedContext": "GET_BEST_2ND_DEG_REPOSTS",
+            })
+        );
+    }
+
+    #[test]
+    fn skeleton_omits_missing_reason() {
+        let post = PostMsg {
+            uri: "at://did:plc:author/app.bsky.feed.post/3k".to_owned(),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(types::Post::from(&post)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"post": "at://did:plc:author/app.bsky.feed.post/3k"})
+        );
+    }
+
+    #[test]
+    fn pages_have_no_gaps_or_duplicates() {
+        let all = ranked(95);
+        let expected: Vec<String> = all.iter().map(|r| r.post.uri.clone()).collect();
+        for limit in [1, 7, 30, 100] {
+            assert_eq!(drain(&all, limit), expected, "limit {limit}");
+        }
+    }
+
+    #[test]
+    fn resumes_after_the_cursor_post_disappears() {
+        let all = ranked(60);
+        let (first, next) = cursor::page(all.clone(), None, 15, HORIZON);
+        let next = next.unwrap();
+
+        // The last post served got deleted before the next page was asked for
+        let remaining: Vec<Ranked> = all.into_iter().filter(|r| r.post.uri != next.uri).collect();
+        let (second, _) = cursor::page(remaining, Some(&next), 100, HORIZON);
+
+        let uris: HashSet<&String> = first.iter().map(|r| &r.post.uri).collect();
+        assert_eq!(second.len(), 45);
+        assert!(second.iter().all(|r| !uris.contains(&r.post.uri)));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let valid = Cursor::after(&ranked(1)[0], HORIZON).encode();
+        assert!(Cursor::decode(&valid).is_some());
+
+        let forged = |json: &str| general_purpose::URL_SAFE_NO_PAD.encode(json);
+        for bad in [
+            "".to_owned(),
+            "1732000000000000".to_owned(),
+            "not base64!".to_owned(),
+            forged("{}"),
+            forged(
+                r#"{"v":2,"score":1.0,"timestamp":1,"uri":"at://did:plc:a/app.bsky.feed.post/3k","horizon":1}"#,
+            ),
+            forged(r#"{"v":1,"score":1.0,"timestamp":1,"uri":"x\" OR 1=1 //","horizon":1}"#),
+            forged(
+                r#"{"v":1,"score":1.0,"timestamp":1,"uri":"at://did:plc:a/app.bsky.feed.post/3k","horizon":0}"#,
+            ),
+        ] {
+            assert!(Cursor::decode(&bad).is_none(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn cypher_in_cursors_never_decodes() {
+        // What used to be spliced straight into the feed queries as `ts < {cursor}`
+        let forged = |json: &str| general_purpose::URL_SAFE_NO_PAD.encode(json);
+        for bad in [
+            "0 MATCH (n) DETACH DELETE n //".to_owned(),
+            "1732000000000000 OR true RETURN 1 //".to_owned(),
+            "0}) CALL db.drop() //".to_owned(),
+            forged(
+                r#"{"v":1,"score":1.0,"timestamp":1,"uri":"at://did:plc:a/app.bsky.feed.post/3k","horizon":"0 MATCH (n) DETACH DELETE n"}"#,
+            ),
+            forged(
+                r#"{"v":1,"score":1.0,"timestamp":"1 OR true","uri":"at://did:plc:a/app.bsky.feed.post/3k","horizon":1}"#,
+            ),
+            forged(
+                r#"{"v":1,"score":1.0,"timestamp":1,"uri":"at://did:plc:a/app.bsky.feed.post/3k' DETACH DELETE p //","horizon":1}"#,
+            ),
+        ] {
+            assert!(Cursor::decode(&bad).is_none(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn ingest_stats_track_drift_and_purges() {
+        let stats = IngestStats::default();
+        for _ in 0..200 {
+            stats.record(1600);
+        }
+        assert_eq!(stats.events(), 200);
+        // Settles on a steady drift, give or take the integer division
+        assert!((1500..=1600).contains(&stats.drift_ms()));
+
+        assert_eq!(stats.last_purge(), None);
+        let impact = PurgeImpact {
+            took_ms: 5,
+            ..Default::default()
+        };
+        stats.set_purge(impact.clone());
+        assert_eq!(stats.last_purge(), Some(impact));
+
+        assert_eq!(rate(500, 2000), 250.0);
+        assert_eq!(rate(500, 0), 0.0);
+    }
+
+    #[test]
+    fn feed_flags_read_0_and_false_as_off() {
+        for off in ["0", "false", "FALSE"] {
+            assert!(!parse_flag(Some(off), true), "{off}");
+        }
+        for on in ["1", "true", "yes"] {
+            assert!(parse_flag(Some(on), false), "{on}");
+        }
+        assert!(parse_flag(None, true));
+        assert!(!parse_flag(Some(""), false));
+    }
+}
@@ -0,0 +1,96 @@
+use base64::{Engine as _, engine::general_purpose};
+use serde_derive::{Deserialize, Serialize};
+
+use super::PostMsg;
+use crate::bsky::uri::AtUri;
+use crate::ranking::{Ranked, best_first};
+
+const VERSION: u8 = 1;
+pub const DEFAULT_LIMIT: usize = 30;
+pub const MAX_LIMIT: usize = 100;
+
+/// Where the previous page stopped. Clients get it back as an opaque, versioned base64 string
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    v: u8,
+    /// Score of the last post served
+    pub score: f64,
+    /// Timestamp & uri of the last post served, to break ties between equal scores
+    pub timestamp: u64,
+    pub uri: String,
+    /// The `now` the first page was ranked at. Later pages reuse it, so candidates & scores dont shift under the client
+    pub horizon: u64,
+}
+
+impl Cursor {
+    pub fn after(last: &Ranked, horizon: u64) -> Self {
+        Self {
+            v: VERSION,
+            score: last.score,
+            timestamp: last.post.timestamp,
+            uri: last.post.uri.clone(),
+            horizon,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    /// None for anything we didnt hand out ourselves
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).ok()?;
+
+        match cursor.v == VERSION
+            && cursor.score.is_finite()
+            && cursor.horizon > 0
+            && AtUri::parse(&cursor.uri).is_some()
+        {
+            true => Some(cursor),
+            false => None,
+        }
+    }
+
+    /// Whether `r` sorts strictly after the post this cursor points at
+    fn is_before(&self, r: &Ranked) -> bool {
+        let at = Ranked {
+            score: self.score,
+            post: PostMsg {
+                uri: self.uri.clone(),
+                timestamp: self.timestamp,
+                ..Default::default()
+            },
+        };
+        best_first(&at, r).is_lt()
+    }
+}
+
+/// Cuts the next `limit` posts out of a best-first list. If the cursor's post is still in the list we resume
+/// right after it, otherwise after wherever it would sort. The cursor is None once there is nothing left
+pub fn page(
+    ranked: Vec<Ranked>,
+    cursor: Option<&Cursor>,
+    limit: usize,
+    horizon: u64,
+) -> (Vec<Ranked>, Option<Cursor>) {
+    let start = match cursor {
+        Some(c) => match ranked.iter().position(|r| r.post.uri == c.uri) {
+            Some(idx) => idx + 1,
+            None => ranked
+                .iter()
+                .position(|r| c.is_before(r))
+                .unwrap_or(ranked.len()),
+        },
+        None => 0,
+    };
+
+    let more = ranked.len() > start + limit;
+    let page: Vec<Ranked> = ranked.into_iter().skip(start).take(limit).collect();
+    let next = match (more, page.last()) {
+        (true, Some(last)) => Some(Cursor::after(last, horizon)),
+        _ => None,
+    };
+
+    (page, next)
+}
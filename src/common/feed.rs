@@ -0,0 +1,115 @@
+use std::env;
+
+use crate::ranking::{Scoring, diversity::Diversity};
+
+pub const DEFAULT_FEED: &str = "following_plus";
+
+/// Per-feed behaviour, keyed off the rkey of the feed generator record (e.g. `following_plus`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedConfig {
+    pub name: String,
+    /// When false, replies are dropped entirely rather than filtered by the thread rules
+    pub include_replies: bool,
+    pub scoring: Scoring,
+    pub diversity: Diversity,
+}
+
+impl FeedConfig {
+    /// Settings are read from the env, prefixed with the upper-cased feed name,
+    /// so `FOLLOWING_PLUS_EXCLUDE_REPLIES=1` drops replies from `following_plus`
+    pub fn for_feed(feed: &str) -> Self {
+        let name = parse_feed_name(feed);
+        let prefix = name.to_uppercase();
+        let scoring = scoring_from_env(&prefix);
+        let diversity = diversity_from_env(&prefix, &scoring);
+
+        Self {
+            include_replies: !env_flag(&prefix, "EXCLUDE_REPLIES", false),
+            scoring,
+            diversity,
+            name,
+        }
+    }
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_FEED.to_owned(),
+            include_replies: true,
+            scoring: Scoring::default(),
+            diversity: Diversity::default(),
+        }
+    }
+}
+
+/// The `feed` param is the full AT-URI of the generator; we only care about its rkey
+fn parse_feed_name(feed: &str) -> String {
+    match feed.rsplit('/').next() {
+        Some(n) if !n.is_empty() => n.to_owned(),
+        _ => DEFAULT_FEED.to_owned(),
+    }
+}
+
+/// `<FEED>_SCORING=recency` restores newest-first, otherwise gravity scoring is used,
+/// with each of its knobs overridable, e.g. `<FEED>_GRAVITY=1.5`
+fn scoring_from_env(prefix: &str) -> Scoring {
+    if env::var(format!("{prefix}_SCORING")).unwrap_or("".into()) == "recency" {
+        return Scoring::Recency;
+    }
+
+    match Scoring::default() {
+        Scoring::Gravity {
+            gravity,
+            like_weight,
+            repost_weight,
+            reply_weight,
+            quote_weight,
+        } => Scoring::Gravity {
+            gravity: env_f64(prefix, "GRAVITY", gravity),
+            like_weight: env_f64(prefix, "LIKE_WEIGHT", like_weight),
+            repost_weight: env_f64(prefix, "REPOST_WEIGHT", repost_weight),
+            reply_weight: env_f64(prefix, "REPLY_WEIGHT", reply_weight),
+            quote_weight: env_f64(prefix, "QUOTE_WEIGHT", quote_weight),
+        },
+        s => s,
+    }
+}
+
+/// `<FEED>_MAX_PER_AUTHOR`, `<FEED>_AUTHOR_VOLUME_EXP` and `<FEED>_INTERLEAVE_REASONS=0`.
+/// Volume normalisation is off for recency feeds, since there the score is the timestamp cursor
+fn diversity_from_env(prefix: &str, scoring: &Scoring) -> Diversity {
+    let default = Diversity::default();
+    let volume_exponent = match scoring {
+        Scoring::Recency => 0.0,
+        _ => default.volume_exponent,
+    };
+
+    Diversity {
+        max_per_author: env::var(format!("{prefix}_MAX_PER_AUTHOR"))
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default.max_per_author),
+        volume_exponent: env_f64(prefix, "AUTHOR_VOLUME_EXP", volume_exponent),
+        interleave_reasons: env_flag(prefix, "INTERLEAVE_REASONS", true),
+    }
+}
+
+fn env_flag(prefix: &str, key: &str, default: bool) -> bool {
+    parse_flag(env::var(format!("{prefix}_{key}")).ok().as_deref(), default)
+}
+
+/// `0` & `false` are off, anything else set is on, & unset or empty is `default`
+pub(crate) fn parse_flag(value: Option<&str>, default: bool) -> bool {
+    match value.map(str::trim) {
+        None | Some("") => default,
+        Some(v) => !(v == "0" || v.eq_ignore_ascii_case("false")),
+    }
+}
+
+fn env_f64(prefix: &str, key: &str, default: f64) -> f64 {
+    env::var(format!("{prefix}_{key}"))
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
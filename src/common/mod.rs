@@ -1,11 +1,27 @@
+use crate::backfill::BackfillMessage;
 use crate::server::types;
+use cursor::Cursor;
+use feed::FeedConfig;
 use serde_derive::Deserialize;
 use tokio::sync::mpsc;
 
+mod common_test;
+pub mod cursor;
+pub mod feed;
+pub mod stats;
+
+/// Everything the web server hands over to the graph side
+pub struct RequestChannels {
+    pub feed: mpsc::Receiver<FetchMessage>,
+    pub backfill: mpsc::Receiver<BackfillMessage>,
+}
+
 #[derive(Debug)]
 pub struct FetchMessage {
     pub did: String,
-    pub cursor: Option<String>,
+    pub cursor: Option<Cursor>,
+    pub limit: usize,
+    pub feed: FeedConfig,
     pub resp: mpsc::Sender<PostResp>,
 }
 
@@ -14,8 +30,15 @@ pub struct PostMsg {
     pub uri: String,
     pub reason: String,
     pub timestamp: u64,
+    pub likes: u64,
+    pub reposts: u64,
+    pub replies: u64,
+    pub quotes: u64,
+    /// AT-URI of a followed account's repost that surfaced this post, if any
+    pub repost: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct PostResp {
     pub posts: Vec<PostMsg>,
     pub cursor: Option<String>,
@@ -25,6 +48,16 @@ impl From<&PostMsg> for types::Post {
     fn from(value: &PostMsg) -> Self {
         types::Post {
             post: value.uri.clone(),
+            reason: value
+                .repost
+                .as_ref()
+                .map(|repost| types::SkeletonReason::Repost {
+                    repost: repost.clone(),
+                }),
+            feed_context: match value.reason.is_empty() {
+                true => None,
+                false => Some(value.reason.clone()),
+            },
         }
     }
 }
@@ -0,0 +1,71 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Shared between the ingest loop, which records every event, & anything that wants to know what it is costing it
+#[derive(Default)]
+pub struct IngestStats {
+    events: AtomicU64,
+    /// Moving average of the last ~16 events, in ms
+    drift_ms: AtomicI64,
+    last_purge: Mutex<Option<PurgeImpact>>,
+}
+
+impl IngestStats {
+    /// Only the ingest loop calls this, so the read then write of the average doesnt race
+    pub fn record(&self, drift_ms: i64) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+        let avg = self.drift_ms.load(Ordering::Relaxed);
+        self.drift_ms
+            .store(avg + (drift_ms - avg) / 16, Ordering::Relaxed);
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    pub fn drift_ms(&self) -> i64 {
+        self.drift_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn set_purge(&self, impact: PurgeImpact) {
+        if let Ok(mut last) = self.last_purge.lock() {
+            *last = Some(impact);
+        }
+    }
+
+    pub fn last_purge(&self) -> Option<PurgeImpact> {
+        self.last_purge.lock().ok().and_then(|l| l.clone())
+    }
+}
+
+/// What the last retention pass deleted (or would have, on a dry run), & how ingestion did before & during it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PurgeImpact {
+    /// Unix micros
+    pub started_at: u64,
+    pub took_ms: u64,
+    pub dry_run: bool,
+    pub deleted: BTreeMap<String, u64>,
+    pub batches: u64,
+    /// Batches put off because ingestion was already behind
+    pub throttled: u64,
+    pub drift_before_ms: i64,
+    pub drift_during_ms: i64,
+    pub events_per_sec_before: f64,
+    pub events_per_sec_during: f64,
+}
+
+/// Events per second, for a count over `ms`
+pub fn rate(events: u64, ms: u64) -> f64 {
+    match ms {
+        0 => 0.0,
+        ms => events as f64 * 1000.0 / ms as f64,
+    }
+}
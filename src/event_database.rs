@@ -1,34 +1,105 @@
+use neo4rs::BoltType;
 use serde::de::DeserializeOwned;
 use std::{collections::HashMap, error::Error};
 
+/// A query parameter, which ends up in the graph as the matching native type rather than a string
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    List(Vec<Param>),
+    Map(Params),
+}
+
+pub type Params = HashMap<String, Param>;
+
+impl From<String> for Param {
+    fn from(value: String) -> Self {
+        Param::Str(value)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(value: &str) -> Self {
+        Param::Str(value.to_owned())
+    }
+}
+
+impl From<i64> for Param {
+    fn from(value: i64) -> Self {
+        Param::Int(value)
+    }
+}
+
+/// Bolt ints are signed, so anything past i64::MAX is clamped to it
+impl From<u64> for Param {
+    fn from(value: u64) -> Self {
+        Param::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<bool> for Param {
+    fn from(value: bool) -> Self {
+        Param::Bool(value)
+    }
+}
+
+impl<T: Into<Param>> From<Vec<T>> for Param {
+    fn from(value: Vec<T>) -> Self {
+        Param::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<Params> for Param {
+    fn from(value: Params) -> Self {
+        Param::Map(value)
+    }
+}
+
+impl From<Param> for BoltType {
+    fn from(value: Param) -> Self {
+        match value {
+            Param::Str(s) => s.into(),
+            Param::Int(i) => i.into(),
+            Param::Bool(b) => b.into(),
+            Param::List(l) => l.into(),
+            Param::Map(m) => m.into(),
+        }
+    }
+}
+
+/// So tests can compare string params against literals
+impl PartialEq<str> for Param {
+    fn eq(&self, other: &str) -> bool {
+        matches!(self, Param::Str(s) if s == other)
+    }
+}
+
 #[trait_variant::make(Send)]
 pub trait EventDatabase<T: DeserializeOwned> {
     async fn read(
         &self,
         query_name: &str,
         query: &str,
-        params: Option<HashMap<String, String>>,
+        params: Option<Params>,
     ) -> Result<T, Box<dyn Error>>;
-    async fn write(
-        &self,
-        query: &str,
-        params: Option<HashMap<String, String>>,
-    ) -> Option<Box<dyn Error>>;
+    async fn write(&self, query: &str, params: Option<Params>) -> Option<Box<dyn Error>>;
     async fn batch_write(
         &self,
         queries: Vec<&str>,
-        params: Vec<Option<HashMap<String, String>>>,
+        params: Vec<Option<Params>>,
     ) -> Option<Box<dyn Error>>;
     async fn chunk_write(
         &self,
         query: &str,
-        params: Vec<HashMap<String, String>>,
+        params: Vec<Params>,
         chunk_size: usize,
         param_name: &str,
     ) -> Option<Box<dyn Error>>;
     async fn batch_read(
         &self,
         queries: Vec<&str>,
-        params: Vec<Option<HashMap<String, String>>>,
+        params: Vec<Option<Params>>,
     ) -> Result<Vec<T>, Box<dyn Error>>;
 }
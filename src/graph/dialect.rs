@@ -0,0 +1,136 @@
+use std::{borrow::Cow, env};
+
+/// Which database is on the other end of the bolt connection. Queries are written for Memgraph, & rewritten
+/// where Neo4j 5 differs. Anything only one of them can do comes back as `None` for the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Memgraph,
+    Neo4j,
+}
+
+impl Dialect {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "memgraph" => Some(Self::Memgraph),
+            "neo4j" => Some(Self::Neo4j),
+            _ => None,
+        }
+    }
+
+    /// `GRAPH_DIALECT`, `memgraph` (default) or `neo4j`
+    pub fn from_env() -> Self {
+        match env::var("GRAPH_DIALECT") {
+            Ok(name) => Self::parse(&name).unwrap_or_else(|| {
+                panic!("Unknown GRAPH_DIALECT {name}, expected memgraph or neo4j")
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The database to connect to, unless `GRAPH_DB` says otherwise
+    pub fn db(&self) -> String {
+        env::var("GRAPH_DB").unwrap_or_else(|_| {
+            match self {
+                Self::Memgraph => "memgraph",
+                Self::Neo4j => "neo4j",
+            }
+            .to_owned()
+        })
+    }
+
+    /// Indexes & constraints, by what they are for. Safe to run again on every start, bar Memgraph's constraint
+    /// which errors if it is already there
+    pub fn schema(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Self::Memgraph => vec![
+                ("User did index", "CREATE INDEX ON :User(did)"),
+                ("Post uri index", "CREATE INDEX ON :Post(uri)"),
+                ("Post timestamp index", "CREATE INDEX ON :Post(timestamp)"),
+                (
+                    "Post uri constraint",
+                    "CREATE CONSTRAINT ON (p:Post) ASSERT p.uri IS UNIQUE",
+                ),
+            ],
+            // A uniqueness constraint brings its own index, & one on the same property as well is an error
+            Self::Neo4j => vec![
+                (
+                    "User did index",
+                    "CREATE INDEX user_did IF NOT EXISTS FOR (u:User) ON (u.did)",
+                ),
+                (
+                    "Post timestamp index",
+                    "CREATE INDEX post_timestamp IF NOT EXISTS FOR (p:Post) ON (p.timestamp)",
+                ),
+                (
+                    "Post uri constraint",
+                    "CREATE CONSTRAINT post_uri IF NOT EXISTS FOR (p:Post) REQUIRE p.uri IS UNIQUE",
+                ),
+            ],
+        }
+    }
+
+    /// Run on the replica, to make it one. Neo4j clusters are set up in the server config instead
+    pub fn replica_role(&self, port: u16) -> Option<String> {
+        match self {
+            Self::Memgraph => Some(format!("SET REPLICATION ROLE TO REPLICA WITH PORT {port};")),
+            Self::Neo4j => None,
+        }
+    }
+
+    /// Run on main, to start replicating to `host`
+    pub fn register_replica(&self, name: &str, host: &str) -> Option<String> {
+        match self {
+            Self::Memgraph => Some(format!("REGISTER REPLICA {name} ASYNC TO \"{host}\";")),
+            Self::Neo4j => None,
+        }
+    }
+
+    /// `query` as this database needs it
+    pub fn adapt<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Memgraph => Cow::Borrowed(query),
+            // Neo4j's timestamp() is in millis where Memgraph's is micros, & pattern predicates are subqueries
+            Self::Neo4j => {
+                Cow::Owned(exists_subqueries(query).replace("timestamp()", "(timestamp() * 1000)"))
+            }
+        }
+    }
+
+    pub fn query(&self, query: &str) -> neo4rs::Query {
+        neo4rs::query(&self.adapt(query))
+    }
+}
+
+/// `exists((a)-[:R]->(b))` to `EXISTS { (a)-[:R]->(b) }`, which Neo4j 5 wants instead
+fn exists_subqueries(query: &str) -> String {
+    const OPEN: &str = "exists((";
+    let mut out = String::with_capacity(query.len());
+    let mut rest = query;
+    while let Some(start) = rest.find(OPEN) {
+        out.push_str(&rest[..start]);
+        // The pattern runs from the second paren to just before the one that closes `exists(`
+        let pattern = &rest[start + OPEN.len() - 1..];
+        let mut depth = 1;
+        let end = pattern.char_indices().find_map(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            };
+            (depth == 0).then_some(i)
+        });
+        match end {
+            Some(end) => {
+                out.push_str(&format!("EXISTS {{ {} }}", &pattern[..end]));
+                rest = &pattern[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -1,15 +1,178 @@
 #[cfg(test)]
 mod graph_test {
+    use std::borrow::Cow;
     use std::collections::{HashMap, VecDeque};
 
+    use neo4rs::BoltType;
     use tokio::select;
 
     use crate::{
         at_event_processor::{ATEventProcessor, MaybeSemaphore},
-        bsky::types::ATEventType,
+        bsky::{self, types::ATEventType},
+        common::PostMsg,
+        event_database::{EventDatabase, Param, Params},
         filter::Filter,
-        graph::queries,
+        graph::{
+            dialect::Dialect,
+            memory::MemoryGraph,
+            queries,
+            retention::{Rule, Target, parse_rules, throttle},
+        },
+        server::listen::now,
     };
+    use std::time::Duration;
+
+    // Client supplied cursors must only ever reach the feed queries as a bound param
+    #[test]
+    fn feed_queries_bind_their_timestamp() {
+        for (name, query) in queries::FEED_QUERIES {
+            assert!(!query.contains("{}"), "{name} still has a template slot");
+            assert!(query.contains("$time"), "{name} doesnt use $time");
+            // Timestamps are stored as ints, so converting them would only get in the way of the index
+            assert!(!query.contains("toInteger"), "{name} still converts types");
+        }
+    }
+
+    #[test]
+    fn memgraph_queries_go_as_written() {
+        let q = Dialect::Memgraph.adapt(queries::GET_FOLLOWING_PLUS_LIKES);
+        assert!(matches!(q, Cow::Borrowed(_)));
+        assert_eq!(Dialect::parse("Neo4j"), Some(Dialect::Neo4j));
+        assert_eq!(Dialect::parse("postgres"), None);
+    }
+
+    #[test]
+    fn neo4j_gets_subqueries_and_micros() {
+        let q = Dialect::Neo4j.adapt(queries::GET_FOLLOWING_PLUS_LIKES);
+        assert!(!q.contains("exists(("));
+        assert!(q.contains("EXISTS { (og)-[:FOLLOWS]->(:User {did: p.replyParent}) }"));
+        assert!(q.contains("EXISTS { (og)-[:FOLLOWS]->(u) } AND"));
+
+        let retention = Target::Users.matcher(true);
+        assert!(
+            Dialect::Neo4j
+                .adapt(&retention)
+                .ends_with("AND NOT EXISTS { (:User {feed_user: true})-[:FOLLOWS]->(n) }")
+        );
+
+        // Neo4j counts millis, everything else here micros
+        let poke = Dialect::Neo4j.adapt(queries::POKE);
+        assert!(poke.contains("SET og.last_seen = (timestamp() * 1000)"));
+        assert!(!poke.contains("= timestamp()"));
+
+        for (_, statement) in Dialect::Neo4j.schema() {
+            assert!(statement.contains("IF NOT EXISTS FOR"));
+        }
+        assert!(Dialect::Neo4j.replica_role(10000).is_none());
+        assert!(Dialect::Neo4j.register_replica("REP1", "h").is_none());
+        assert!(Dialect::Memgraph.register_replica("REP1", "h").is_some());
+    }
+
+    #[test]
+    fn params_map_to_native_bolt_types() {
+        assert_eq!(
+            BoltType::from(Param::from(1732000000000000u64)),
+            BoltType::from(1732000000000000i64)
+        );
+        assert_eq!(
+            BoltType::from(Param::from(u64::MAX)),
+            BoltType::from(i64::MAX)
+        );
+        assert_eq!(BoltType::from(Param::from(true)), BoltType::from(true));
+        assert_eq!(
+            BoltType::from(Param::from("did:plc:a")),
+            BoltType::from("did:plc:a")
+        );
+        assert_eq!(
+            BoltType::from(Param::from(vec!["a", "b"])),
+            BoltType::from(vec!["a", "b"])
+        );
+
+        let nested: Params = HashMap::from([("n".to_owned(), Param::from(2i64))]);
+        assert_eq!(
+            BoltType::from(Param::from(vec![nested])),
+            BoltType::from(vec![HashMap::from([("n".to_owned(), 2i64)])])
+        );
+    }
+
+    #[test]
+    fn retention_rules_parse() {
+        let rules =
+            parse_rules("Post=7200, User=14400,LIKES=60,Nope=1,FOLLOWS=abc,BLOCKED,Post=3600");
+        assert_eq!(
+            rules,
+            vec![
+                Rule {
+                    target: Target::Users,
+                    ttl: Duration::from_secs(14400)
+                },
+                Rule {
+                    target: Target::Likes,
+                    ttl: Duration::from_secs(60)
+                },
+                Rule {
+                    target: Target::Posts,
+                    ttl: Duration::from_secs(3600)
+                },
+            ]
+        );
+        assert!(parse_rules("").is_empty());
+    }
+
+    #[test]
+    fn retention_protects_viewers_and_their_follows() {
+        let users = Target::Users.matcher(true);
+        assert!(users.contains("NOT coalesce(n.feed_user, false)"));
+        assert!(users.contains("-[:FOLLOWS]->(n)"));
+        assert!(!Target::Users.matcher(false).contains("feed_user"));
+        assert!(!Target::Posts.matcher(true).contains("feed_user"));
+
+        let likes = Target::Likes.matcher(true);
+        assert!(likes.starts_with("MATCH (a)-[n:LIKES]->()"));
+        assert!(likes.contains("n.created_at < $before"));
+        assert!(likes.contains("a.feed_user"));
+        assert!(likes.contains("-[:FOLLOWS]->(a)"));
+
+        for template in [
+            queries::RETENTION_COUNT,
+            queries::RETENTION_DELETE_NODES,
+            queries::RETENTION_DELETE_EDGES,
+        ] {
+            assert!(template.contains("{target}"));
+        }
+        assert!(queries::RETENTION_DELETE_NODES.contains("LIMIT $batch"));
+        assert!(queries::MIGRATE_POST_PROPERTIES.contains("LIMIT $batch"));
+    }
+
+    #[test]
+    fn feeds_tolerate_unmigrated_posts() {
+        for qry in [
+            queries::GET_FOLLOWING_PLUS_LIKES,
+            queries::GET_FOLLOWING_PLUS_REPOSTS,
+            queries::GET_BEST_2ND_DEG_REPOSTS,
+            queries::GET_BEST_2ND_DEG_LIKES,
+            queries::GET_BEST_2ND_DEG_QUOTES,
+            queries::GET_BEST_FOLLOWED,
+        ] {
+            assert!(qry.contains(r#"CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER""#));
+            assert!(qry.contains(r#"NOT (p.isReply IN [true, "y"])"#));
+            assert!(!qry.contains("NOT p.isReply OR"));
+        }
+    }
+
+    #[test]
+    fn reply_counts_never_go_null_or_negative() {
+        let floored = "CASE WHEN coalesce(p.replies, 0) > 0 THEN p.replies - 1 ELSE 0 END";
+        assert!(queries::REMOVE_REPLY.contains(floored));
+        assert!(queries::REMOVE_ACCOUNT.contains(floored));
+    }
+
+    #[test]
+    fn retention_backs_off_while_ingest_is_behind() {
+        assert!(throttle(2500, 2000));
+        assert!(!throttle(2000, 2000));
+        assert!(!throttle(90000, 0));
+    }
 
     // These just check the calls call enqueue_query properly
     #[tokio::test]
@@ -93,14 +256,351 @@ mod graph_test {
         }
     }
 
+    #[tokio::test]
+    async fn check_quote_with_media() {
+        let mut tg = TestGraph::new();
+        let evt = r#"{"did":"did:user1","time_us":1732000000000000,"kind":"commit","commit":{"rev":"r","operation":"create","collection":"app.bsky.feed.post","rkey":"3lbquoterkey1","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-19T07:06:40.000Z","text":"look","embed":{"$type":"app.bsky.embed.recordWithMedia","record":{"$type":"app.bsky.embed.record","record":{"cid":"c","uri":"at://did:plc:user2/app.bsky.feed.post/3lbquotedrkey"}},"media":{"$type":"app.bsky.embed.images","images":[]}}}}}"#;
+
+        bsky::handle_event_fast(evt.as_bytes(), &mut tg, None, false)
+            .await
+            .unwrap();
+
+        let queue = tg.get_queue();
+        assert_eq!(queue.len(), 2);
+        let (name, quote) = queue[0].values().next().unwrap();
+        assert_eq!(name, "quotes");
+        assert_eq!(
+            quote[0].get("subject").unwrap(),
+            "at://did:plc:user2/app.bsky.feed.post/3lbquotedrkey"
+        );
+        assert_eq!(quote[0].get("rkey").unwrap(), "3lbquoterkey1");
+        assert_eq!(quote[0].get("did").unwrap(), "did:user1");
+
+        let (name, post) = queue[1].values().next().unwrap();
+        assert_eq!(name, "posts");
+        assert_eq!(post[0].get("type").unwrap(), "i");
+    }
+
+    #[tokio::test]
+    async fn check_reply_parent_and_root() {
+        let mut tg = TestGraph::new();
+        let evt = r#"{"did":"did:user1","time_us":1732000000000000,"kind":"commit","commit":{"rev":"r","operation":"create","collection":"app.bsky.feed.post","rkey":"3lbreplyrkey1","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-19T07:06:40.000Z","text":"same","reply":{"parent":{"cid":"c","uri":"at://did:plc:user2/app.bsky.feed.post/3lbparentrkey"},"root":{"cid":"c","uri":"at://did:plc:user3/app.bsky.feed.post/3lbrootrkey11"}}}}}"#;
+
+        bsky::handle_event_fast(evt.as_bytes(), &mut tg, None, false)
+            .await
+            .unwrap();
+
+        let queue = tg.get_queue();
+        assert_eq!(queue.len(), 2);
+        let (name, reply) = queue[0].values().next().unwrap();
+        assert_eq!(name, "replies");
+        assert_eq!(
+            reply[0].get("parent").unwrap(),
+            "at://did:plc:user2/app.bsky.feed.post/3lbparentrkey"
+        );
+        assert_eq!(
+            reply[0].get("root").unwrap(),
+            "at://did:plc:user3/app.bsky.feed.post/3lbrootrkey11"
+        );
+
+        let (name, post) = queue[1].values().next().unwrap();
+        assert_eq!(name, "posts");
+        assert_eq!(post[0].get("parent_did").unwrap(), "did:plc:user2");
+    }
+
+    #[tokio::test]
+    async fn check_identity_and_account() {
+        let mut tg = TestGraph::new();
+        let events = [
+            r#"{"did":"did:plc:user1","time_us":1732000000000000,"kind":"identity","identity":{"did":"did:plc:user1","handle":"new.bsky.social","seq":1,"time":"2024-11-19T07:06:40.000Z"}}"#,
+            r#"{"did":"did:plc:user1","time_us":1732000000000000,"kind":"account","account":{"active":false,"did":"did:plc:user1","seq":2,"status":"takendown","time":"2024-11-19T07:06:40.000Z"}}"#,
+            r#"{"did":"did:plc:user1","time_us":1732000000000000,"kind":"account","account":{"active":true,"did":"did:plc:user1","seq":3,"time":"2024-11-19T07:06:40.000Z"}}"#,
+            r#"{"did":"did:plc:user1","time_us":1732000000000000,"kind":"account","account":{"active":false,"did":"did:plc:user1","seq":4,"status":"deleted","time":"2024-11-19T07:06:40.000Z"}}"#,
+        ];
+        for evt in events {
+            bsky::handle_event_fast(evt.as_bytes(), &mut tg, None, false)
+                .await
+                .unwrap();
+        }
+
+        let queue = tg.get_queue();
+        assert_eq!(queue.len(), 4);
+
+        let handle = queue[0]
+            .get(&format!("{}_0", queries::UPDATE_HANDLE))
+            .unwrap();
+        assert_eq!(handle.1[0].get("handle").unwrap(), "new.bsky.social");
+
+        let takedown = queue[1]
+            .get(&format!("{}_0", queries::UPDATE_ACCOUNT))
+            .unwrap();
+        assert_eq!(takedown.1[0]["active"], Param::Bool(false));
+        assert_eq!(takedown.1[0].get("status").unwrap(), "takendown");
+
+        let reactivate = queue[2]
+            .get(&format!("{}_1", queries::UPDATE_ACCOUNT))
+            .unwrap();
+        assert_eq!(reactivate.1[0]["active"], Param::Bool(true));
+        assert_eq!(reactivate.1[0].get("status").unwrap(), "");
+
+        let deleted = queue[3]
+            .get(&format!("{}_0", queries::REMOVE_ACCOUNT))
+            .unwrap();
+        assert_eq!(deleted.1[0].get("did").unwrap(), "did:plc:user1");
+    }
+
+    fn post_uri(did: &str, rkey: &str) -> String {
+        format!("at://{did}/app.bsky.feed.post/{rkey}")
+    }
+
+    /// viewer -> friend -> author, & the author has one post with `likes` likes, `hours` old
+    fn memory_network(likes: usize, hours: i64) -> (MemoryGraph, String) {
+        let g = MemoryGraph::default();
+        g.add_edge(Target::Follows, "did:viewer", "did:friend", "f1");
+        g.add_edge(Target::Follows, "did:friend", "did:author", "f2");
+        let uri = post_uri("did:author", "p1");
+        g.add_post("did:author", &uri, now() as i64 - hours * 3600000000, "");
+        for i in 0..likes {
+            g.add_edge(
+                Target::Likes,
+                &format!("did:fan{i}"),
+                &uri,
+                &format!("l{i}"),
+            );
+        }
+        (g, uri)
+    }
+
+    async fn read_feed(g: &MemoryGraph, name: &str) -> HashMap<String, PostMsg> {
+        let query = queries::FEED_QUERIES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .unwrap()
+            .1;
+        let params: Params = HashMap::from([
+            ("did".to_owned(), "did:viewer".into()),
+            ("replies".to_owned(), false.into()),
+            ("time".to_owned(), now().into()),
+        ]);
+        g.read(name, query, Some(params)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn memory_graph_serves_2nd_degree_posts_over_threshold() {
+        let (g, uri) = memory_network(3, 0);
+        // Defaults want 75 likes
+        assert!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.is_empty());
+
+        let target = HashMap::from([
+            ("did".to_owned(), "did:viewer".into()),
+            ("target".to_owned(), 1i64.into()),
+        ]);
+        assert!(
+            g.write(queries::SET_THRESHOLDS, Some(target))
+                .await
+                .is_none()
+        );
+        let feed = read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await;
+        assert_eq!(feed[&uri].likes, 3);
+        assert_eq!(feed[&uri].reason, "GET_FOLLOWING_PLUS_LIKES");
+
+        // Unliking drops it back under the threshold
+        g.remove_edge(Target::Likes, "did:fan0", "l0");
+        assert!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.is_empty());
+        g.add_edge(Target::Likes, "did:fan0", &uri, "l0");
+
+        g.add_edge(Target::Blocks, "did:viewer", "did:author", "b1");
+        assert!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.is_empty());
+        g.remove_edge(Target::Blocks, "did:viewer", "b1");
+
+        g.set_account("did:author", false);
+        assert!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.is_empty());
+        g.set_account("did:author", true);
+        assert_eq!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.len(), 1);
+
+        g.rm_post("did:author", &uri);
+        assert!(read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn memory_graph_sets_a_threshold_per_candidate_set() {
+        // The author's post has 3 likes, & the post they liked only 2
+        let (g, uri) = memory_network(3, 0);
+        let liked = post_uri("did:stranger", "p2");
+        g.add_post("did:stranger", &liked, now() as i64 - 1, "");
+        g.add_edge(Target::Likes, "did:author", &liked, "l-author");
+        g.add_edge(Target::Likes, "did:fan0", &liked, "l-fan");
+
+        g.set_thresholds("did:viewer", 1);
+        let following = read_feed(&g, "GET_FOLLOWING_PLUS_LIKES").await;
+        assert_eq!(following.keys().collect::<Vec<_>>(), vec![&uri]);
+        // Going by what the 2nd degree posted, it would have needed 3
+        let liked_feed = read_feed(&g, "GET_BEST_2ND_DEG_LIKES").await;
+        assert_eq!(liked_feed.keys().collect::<Vec<_>>(), vec![&liked]);
+
+        // Nothing reposted, so that feed keeps its default
+        g.add_edge(Target::Reposts, "did:author", &liked, "r-author");
+        assert!(read_feed(&g, "GET_BEST_2ND_DEG_REPOSTS").await.is_empty());
+        g.set_thresholds("did:viewer", 1);
+        assert_eq!(read_feed(&g, "GET_BEST_2ND_DEG_REPOSTS").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn memory_graph_attributes_reposts_to_who_we_follow() {
+        let (g, uri) = memory_network(0, 0);
+        g.populate(Target::Follows, "did:viewer", "did:friend", "f1");
+        for i in 0..60 {
+            g.add_edge(
+                Target::Reposts,
+                &format!("did:fan{i}"),
+                &uri,
+                &format!("r{i}"),
+            );
+        }
+        g.add_edge(Target::Reposts, "did:friend", &uri, "r-friend");
+
+        let feed = read_feed(&g, "GET_FOLLOWING_PLUS_REPOSTS").await;
+        assert_eq!(feed[&uri].reposts, 61);
+        assert_eq!(
+            feed[&uri].repost.as_deref(),
+            Some("at://did:friend/app.bsky.feed.repost/r-friend")
+        );
+
+        // Unreposting takes from reposts, not likes
+        g.remove_edge(Target::Reposts, "did:fan0", "r0");
+        assert_eq!(
+            read_feed(&g, "GET_FOLLOWING_PLUS_REPOSTS").await[&uri].reposts,
+            60
+        );
+        assert_eq!(
+            read_feed(&g, "GET_FOLLOWING_PLUS_REPOSTS").await[&uri].likes,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_graph_runs_write_queries_by_their_params() {
+        let g = MemoryGraph::default();
+        let follows: Vec<Params> = vec![HashMap::from([
+            ("did".to_owned(), "did:viewer".into()),
+            ("out".to_owned(), "did:friend".into()),
+            ("rkey".to_owned(), "f1".into()),
+        ])];
+        assert!(
+            g.chunk_write(queries::POPULATE_FOLLOW, follows.clone(), 20, "follows")
+                .await
+                .is_none()
+        );
+        let post: Params = HashMap::from([
+            ("did".to_owned(), "did:friend".into()),
+            ("uri".to_owned(), post_uri("did:friend", "p1").into()),
+            ("timestamp".to_owned(), (now() as i64 - 1).into()),
+            ("parent_did".to_owned(), "".into()),
+        ]);
+        let posts = HashMap::from([("posts".to_owned(), Param::from(vec![post]))]);
+        assert!(g.write(queries::ADD_POST, Some(posts)).await.is_none());
+        for i in 0..11 {
+            g.add_edge(
+                Target::Likes,
+                "did:fan",
+                &post_uri("did:friend", "p1"),
+                &format!("l{i}"),
+            );
+        }
+        assert_eq!(read_feed(&g, "GET_BEST_FOLLOWED").await.len(), 1);
+
+        assert!(
+            g.chunk_write(queries::REMOVE_FOLLOW, follows, 60, "follows")
+                .await
+                .is_none()
+        );
+        assert!(read_feed(&g, "GET_BEST_FOLLOWED").await.is_empty());
+
+        // Anything it cant do is an error rather than silently nothing
+        assert!(g.write(queries::MARK_SYNCED, None).await.is_some());
+        assert!(g.read("x", queries::GET_CRAWL, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_graph_takes_removed_accounts_engagement_off_counts() {
+        let g = MemoryGraph::default();
+        g.populate(Target::Follows, "did:viewer", "did:friend", "f1");
+        let uri = post_uri("did:friend", "p1");
+        g.add_post("did:friend", &uri, now() as i64 - 1, "");
+        for i in 0..11 {
+            g.add_edge(Target::Likes, "did:fan", &uri, &format!("l{i}"));
+        }
+        assert_eq!(read_feed(&g, "GET_BEST_FOLLOWED").await.len(), 1);
+
+        g.rm_account("did:fan");
+        assert!(read_feed(&g, "GET_BEST_FOLLOWED").await.is_empty());
+    }
+
+    #[test]
+    fn memory_graph_purges_like_retention() {
+        let (g, _) = memory_network(2, 3);
+        g.poke("did:viewer");
+        let keep = post_uri("did:friend", "fresh");
+        g.add_post("did:friend", &keep, now() as i64, "");
+
+        std::thread::sleep(Duration::from_millis(1));
+        let rules = parse_rules("LIKES=0,Post=7200");
+        let dry = g.purge(&rules, true, true);
+        assert_eq!(dry, vec![(Target::Likes, 2), (Target::Posts, 1)]);
+        assert_eq!(g.purge(&rules, true, false), dry);
+        assert_eq!(
+            g.purge(&rules, true, true),
+            vec![(Target::Likes, 0), (Target::Posts, 0)]
+        );
+
+        // Everyone was just seen, so only with no ttl do users go, & never the viewer or who they follow
+        let users = parse_rules("User=0");
+        std::thread::sleep(Duration::from_millis(1));
+        let protected = g.purge(&users, true, true)[0].1;
+        let all = g.purge(&users, false, true)[0].1;
+        assert_eq!(all - protected, 2);
+    }
+
+    #[test]
+    fn memory_graph_keeps_the_follows_of_who_viewers_follow() {
+        let (g, _) = memory_network(0, 1);
+        g.add_edge(Target::Follows, "did:stranger", "did:author", "f3");
+        g.poke("did:viewer");
+
+        std::thread::sleep(Duration::from_millis(1));
+        let rules = parse_rules("FOLLOWS=0");
+        assert_eq!(g.purge(&rules, false, true), vec![(Target::Follows, 3)]);
+        // Only the stranger's goes, the friend's is the viewer's 2nd degree
+        assert_eq!(g.purge(&rules, true, false), vec![(Target::Follows, 1)]);
+        assert_eq!(g.purge(&rules, false, true), vec![(Target::Follows, 2)]);
+    }
+
+    #[test]
+    fn memory_graph_merges_follows_written_twice() {
+        let g = MemoryGraph::default();
+        g.populate(Target::Follows, "did:viewer", "did:friend", "f1");
+        g.add_edge(Target::Follows, "did:viewer", "did:friend", "f1");
+        g.add_edge(Target::Blocks, "did:viewer", "did:troll", "b1");
+        g.add_edge(Target::Blocks, "did:viewer", "did:troll", "b1");
+
+        std::thread::sleep(Duration::from_millis(1));
+        let rules = parse_rules("FOLLOWS=0,BLOCKED=0");
+        assert_eq!(
+            g.purge(&rules, false, true),
+            vec![(Target::Follows, 1), (Target::Blocks, 1)]
+        );
+        assert!(queries::ADD_FOLLOW.contains("MERGE (u)-[r:FOLLOWS"));
+        assert!(queries::ADD_BLOCK.contains("MERGE (u)-[r:BLOCKED"));
+    }
+
     struct TestGraph {
         filters: HashMap<ATEventType, VecDeque<Box<dyn Filter + Send>>>,
-        queue: VecDeque<HashMap<String, (String, Vec<HashMap<String, String>>)>>,
+        queue: VecDeque<HashMap<String, (String, Vec<Params>)>>,
         query_counter: HashMap<String, usize>,
     }
 
     impl TestGraph {
-        fn get_queue(&self) -> VecDeque<HashMap<String, (String, Vec<HashMap<String, String>>)>> {
+        fn get_queue(&self) -> VecDeque<HashMap<String, (String, Vec<Params>)>> {
             self.queue.clone()
         }
 
@@ -115,7 +615,7 @@ mod graph_test {
         async fn enqueue_query(
             &mut self,
             query_script: &str,
-            params: (&str, Vec<HashMap<String, String>>),
+            params: (&str, Vec<Params>),
             sem: MaybeSemaphore,
         ) -> MaybeSemaphore {
             let script = params.0.to_owned();
@@ -155,6 +655,7 @@ mod graph_test {
             did: String,
             rkey: String,
             parent: String,
+            root: String,
             sem: MaybeSemaphore,
         ) -> MaybeSemaphore {
             self.enqueue_query(
@@ -162,9 +663,32 @@ mod graph_test {
                 (
                     "replies",
                     vec![HashMap::from([
-                        ("parent".to_owned(), parent),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("parent".to_owned(), parent.into()),
+                        ("root".to_owned(), root.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
+                    ])],
+                ),
+                sem,
+            )
+            .await
+        }
+
+        async fn add_quote(
+            &mut self,
+            did: String,
+            rkey: String,
+            subject: String,
+            sem: MaybeSemaphore,
+        ) -> MaybeSemaphore {
+            self.enqueue_query(
+                queries::ADD_QUOTE,
+                (
+                    "quotes",
+                    vec![HashMap::from([
+                        ("subject".to_owned(), subject.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -177,7 +701,7 @@ mod graph_test {
             did: String,
             rkey: String,
             timestamp: &i64,
-            is_reply: bool,
+            parent_did: String,
             post_type: String,
             sem: MaybeSemaphore,
         ) -> MaybeSemaphore {
@@ -186,11 +710,11 @@ mod graph_test {
                 (
                     "posts",
                     vec![HashMap::from([
-                        ("timestamp".to_owned(), format!("{timestamp}")),
-                        ("is_reply".to_owned(), format!("{is_reply}")),
-                        ("type".to_owned(), format!("{post_type}")),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("timestamp".to_owned(), (*timestamp).into()),
+                        ("parent_did".to_owned(), parent_did.into()),
+                        ("type".to_owned(), post_type.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -201,7 +725,7 @@ mod graph_test {
         async fn add_repost(
             &mut self,
             did: String,
-            rkey_parent: String,
+            subject: String,
             rkey: String,
             sem: MaybeSemaphore,
         ) -> MaybeSemaphore {
@@ -210,9 +734,9 @@ mod graph_test {
                 (
                     "reposts",
                     vec![HashMap::from([
-                        ("rkey_parent".to_owned(), rkey_parent),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("subject".to_owned(), subject.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -232,9 +756,9 @@ mod graph_test {
                 (
                     "follows",
                     vec![HashMap::from([
-                        ("out".to_owned(), out),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("out".to_owned(), out.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -245,7 +769,7 @@ mod graph_test {
         async fn add_like(
             &mut self,
             did: String,
-            rkey_parent: String,
+            subject: String,
             rkey: String,
             sem: MaybeSemaphore,
         ) -> MaybeSemaphore {
@@ -254,9 +778,9 @@ mod graph_test {
                 (
                     "likes",
                     vec![HashMap::from([
-                        ("rkey_parent".to_owned(), rkey_parent),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("subject".to_owned(), subject.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -276,9 +800,51 @@ mod graph_test {
                 (
                     "blocks",
                     vec![HashMap::from([
-                        ("blockee".to_owned(), blockee),
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("blockee".to_owned(), blockee.into()),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
+                    ])],
+                ),
+                sem,
+            )
+            .await
+        }
+
+        async fn update_handle(
+            &mut self,
+            did: String,
+            handle: String,
+            sem: MaybeSemaphore,
+        ) -> MaybeSemaphore {
+            self.enqueue_query(
+                queries::UPDATE_HANDLE,
+                (
+                    "handles",
+                    vec![HashMap::from([
+                        ("handle".to_owned(), handle.into()),
+                        ("did".to_owned(), did.into()),
+                    ])],
+                ),
+                sem,
+            )
+            .await
+        }
+
+        async fn set_account_status(
+            &mut self,
+            did: String,
+            active: bool,
+            status: String,
+            sem: MaybeSemaphore,
+        ) -> MaybeSemaphore {
+            self.enqueue_query(
+                queries::UPDATE_ACCOUNT,
+                (
+                    "accounts",
+                    vec![HashMap::from([
+                        ("active".to_owned(), active.into()),
+                        ("status".to_owned(), status.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -297,8 +863,8 @@ mod graph_test {
                 (
                     "posts",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -317,8 +883,8 @@ mod graph_test {
                 (
                     "reposts",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -337,8 +903,8 @@ mod graph_test {
                 (
                     "follows",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -357,8 +923,8 @@ mod graph_test {
                 (
                     "likes",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -377,8 +943,8 @@ mod graph_test {
                 (
                     "blocks",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
@@ -397,8 +963,40 @@ mod graph_test {
                 (
                     "replies",
                     vec![HashMap::from([
-                        ("rkey".to_owned(), rkey),
-                        ("did".to_owned(), did),
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
+                    ])],
+                ),
+                sem,
+            )
+            .await
+        }
+
+        async fn rm_account(&mut self, did: String, sem: MaybeSemaphore) -> MaybeSemaphore {
+            self.enqueue_query(
+                queries::REMOVE_ACCOUNT,
+                (
+                    "accounts",
+                    vec![HashMap::from([("did".to_owned(), did.into())])],
+                ),
+                sem,
+            )
+            .await
+        }
+
+        async fn rm_quote(
+            &mut self,
+            did: String,
+            rkey: String,
+            sem: MaybeSemaphore,
+        ) -> MaybeSemaphore {
+            self.enqueue_query(
+                queries::REMOVE_QUOTE,
+                (
+                    "quotes",
+                    vec![HashMap::from([
+                        ("rkey".to_owned(), rkey.into()),
+                        ("did".to_owned(), did.into()),
                     ])],
                 ),
                 sem,
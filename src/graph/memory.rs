@@ -0,0 +1,777 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    error::Error,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{
+    common::PostMsg,
+    event_database::{EventDatabase, Param, Params},
+    graph::{
+        queries,
+        retention::{Rule, Target},
+    },
+    server::listen::now,
+};
+
+/// Every feed query is `LIMIT 600`
+const FEED_LIMIT: usize = 600;
+
+/// The graph kept in process, for running & testing without Memgraph. Writes do what the Cypher query of the
+/// same name does, & reads answer the feed queries, so it stands in for both `MemgraphWrapper` & `GraphFetcher`.
+/// Handles, statuses & anything else no query reads back arent kept
+#[derive(Clone, Default)]
+pub struct MemoryGraph {
+    state: Arc<RwLock<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    users: HashMap<String, User>,
+    posts: HashMap<String, Post>,
+}
+
+#[derive(Default)]
+struct User {
+    active: Option<bool>,
+    last_seen: Option<i64>,
+    feed_user: bool,
+    like_threshold: Option<i64>,
+    repost_threshold: Option<i64>,
+    liked_threshold: Option<i64>,
+    reposted_threshold: Option<i64>,
+    quote_threshold: Option<i64>,
+    /// Edges this user made, to users for FOLLOWS & BLOCKED & to posts for the rest
+    edges: Vec<Edge>,
+    /// Who has an edge to this user
+    incoming: HashSet<String>,
+    posts: HashSet<String>,
+}
+
+struct Edge {
+    kind: Target,
+    rkey: String,
+    to: String,
+    created_at: i64,
+}
+
+struct Post {
+    uri: String,
+    /// Gone if the author was purged, which leaves the post without a POSTED edge
+    author: Option<String>,
+    timestamp: i64,
+    is_reply: bool,
+    reply_parent: String,
+    likes: i64,
+    reposts: i64,
+    replies: i64,
+    quotes: i64,
+    /// Who has an edge to this post
+    engaged: HashSet<String>,
+}
+
+impl Post {
+    fn counter(&mut self, kind: Target) -> Option<&mut i64> {
+        match kind {
+            Target::Likes => Some(&mut self.likes),
+            Target::Reposts => Some(&mut self.reposts),
+            Target::Replies => Some(&mut self.replies),
+            Target::Quotes => Some(&mut self.quotes),
+            _ => None,
+        }
+    }
+}
+
+fn to_user(kind: Target) -> bool {
+    matches!(kind, Target::Follows | Target::Blocks)
+}
+
+impl User {
+    fn out(&self, kind: Target) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |e| e.kind == kind)
+    }
+
+    fn active(&self) -> bool {
+        self.active.unwrap_or(true)
+    }
+}
+
+impl MemoryGraph {
+    fn read_state(&self) -> RwLockReadGuard<'_, State> {
+        match self.state.read() {
+            Ok(s) => s,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn write_state(&self) -> RwLockWriteGuard<'_, State> {
+        match self.state.write() {
+            Ok(s) => s,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// ADD_FOLLOW, ADD_BLOCK, ADD_LIKE, ADD_REPOST, ADD_REPLY & ADD_QUOTE. Engagement on a post that isnt here
+    /// does nothing
+    pub fn add_edge(&self, kind: Target, did: &str, to: &str, rkey: &str) {
+        self.write_state().link(kind, did, to, rkey, false);
+    }
+
+    /// POPULATE_FOLLOW & POPULATE_BLOCK, which only create the edge if it isnt there already
+    pub fn populate(&self, kind: Target, did: &str, to: &str, rkey: &str) {
+        self.write_state().link(kind, did, to, rkey, true);
+    }
+
+    /// REMOVE_FOLLOW, REMOVE_BLOCK, REMOVE_LIKE, REMOVE_REPOST, REMOVE_REPLY & REMOVE_QUOTE
+    pub fn remove_edge(&self, kind: Target, did: &str, rkey: &str) {
+        self.write_state().unlink(kind, did, rkey);
+    }
+
+    /// ADD_POST, which leaves a post that is already here as it is
+    pub fn add_post(&self, did: &str, uri: &str, timestamp: i64, parent_did: &str) {
+        self.write_state().add_post(did, uri, timestamp, parent_did);
+    }
+
+    /// REMOVE_POST
+    pub fn rm_post(&self, did: &str, uri: &str) {
+        let mut state = self.write_state();
+        if state.posts.get(uri).and_then(|p| p.author.as_deref()) != Some(did) {
+            return;
+        }
+        state.touch(did);
+        state.drop_post(uri);
+    }
+
+    /// REMOVE_ACCOUNT, which takes their posts with it & their engagement off everyone else's counts
+    pub fn rm_account(&self, did: &str) {
+        let mut state = self.write_state();
+        let (posts, engaged) = match state.users.get(did) {
+            Some(u) => (
+                u.posts.clone(),
+                u.edges
+                    .iter()
+                    .map(|e| (e.kind, e.to.clone()))
+                    .collect::<Vec<_>>(),
+            ),
+            None => return,
+        };
+        for (kind, to) in engaged {
+            if let Some(n) = state.posts.get_mut(&to).and_then(|p| p.counter(kind)) {
+                *n -= 1;
+            }
+        }
+        for uri in posts {
+            state.drop_post(&uri);
+        }
+        state.drop_user(did);
+    }
+
+    /// UPDATE_HANDLE
+    pub fn update_handle(&self, did: &str) {
+        self.write_state().users.entry(did.to_owned()).or_default();
+    }
+
+    /// UPDATE_ACCOUNT
+    pub fn set_account(&self, did: &str, active: bool) {
+        self.write_state()
+            .users
+            .entry(did.to_owned())
+            .or_default()
+            .active = Some(active);
+    }
+
+    /// POKE
+    pub fn poke(&self, did: &str) {
+        if let Some(u) = self.write_state().users.get_mut(did) {
+            u.last_seen = Some(now() as i64);
+            u.feed_user = true;
+        }
+    }
+
+    /// SET_THRESHOLDS
+    pub fn set_thresholds(&self, did: &str, target: i64) {
+        self.write_state().set_thresholds(did, target);
+    }
+
+    /// One of the FEED_QUERIES by name, as `query_name` says it was asked for
+    pub fn feed(
+        &self,
+        name: &str,
+        reason: &str,
+        did: &str,
+        replies: bool,
+        time: i64,
+    ) -> HashMap<String, PostMsg> {
+        self.read_state().feed(name, reason, did, replies, time)
+    }
+
+    /// What retention would do with `rules`, all in one go as nothing else is locked out for long.
+    /// Returns how many of each target went, or would have
+    pub fn purge(&self, rules: &[Rule], protect: bool, dry_run: bool) -> Vec<(Target, u64)> {
+        let mut state = self.write_state();
+        rules
+            .iter()
+            .map(|rule| {
+                let before = now() as i64 - rule.ttl.as_micros() as i64;
+                (
+                    rule.target,
+                    state.expire(rule.target, before, protect, dry_run),
+                )
+            })
+            .collect()
+    }
+
+    /// Runs a write query with its params, a row at a time for the ones that `UNWIND` a list
+    fn run(&self, query: &str, params: &Params) -> Result<(), Box<dyn Error>> {
+        match query {
+            queries::POKE => self.poke(string(params, "did")),
+            queries::SET_THRESHOLDS => {
+                self.set_thresholds(string(params, "did"), int(params, "target"))
+            }
+            _ => {
+                let rows = match params.values().find_map(|p| match p {
+                    Param::List(rows) => Some(rows),
+                    _ => None,
+                }) {
+                    Some(rows) => rows,
+                    None => return Err(unsupported(query)),
+                };
+                for row in rows {
+                    if let Param::Map(row) = row {
+                        self.apply(query, row)?;
+                    }
+                }
+            }
+        };
+        Ok(())
+    }
+
+    /// One row of an `UNWIND` write query
+    fn apply(&self, query: &str, row: &Params) -> Result<(), Box<dyn Error>> {
+        let did = string(row, "did");
+        let rkey = string(row, "rkey");
+        match query {
+            queries::ADD_FOLLOW => self.add_edge(Target::Follows, did, string(row, "out"), rkey),
+            // & POPULATE_BLOCK, which is the same query
+            queries::ADD_BLOCK => self.add_edge(Target::Blocks, did, string(row, "blockee"), rkey),
+            queries::POPULATE_FOLLOW => {
+                self.populate(Target::Follows, did, string(row, "out"), rkey)
+            }
+            queries::ADD_LIKE => self.add_edge(Target::Likes, did, string(row, "subject"), rkey),
+            queries::ADD_REPOST => {
+                self.add_edge(Target::Reposts, did, string(row, "subject"), rkey)
+            }
+            queries::ADD_QUOTE => self.add_edge(Target::Quotes, did, string(row, "subject"), rkey),
+            queries::ADD_REPLY => self.add_edge(Target::Replies, did, string(row, "parent"), rkey),
+            queries::ADD_POST => self.add_post(
+                did,
+                string(row, "uri"),
+                int(row, "timestamp"),
+                string(row, "parent_did"),
+            ),
+            queries::UPDATE_HANDLE => self.update_handle(did),
+            queries::UPDATE_ACCOUNT => self.set_account(did, flag(row, "active")),
+            queries::REMOVE_FOLLOW => self.remove_edge(Target::Follows, did, rkey),
+            queries::REMOVE_BLOCK => self.remove_edge(Target::Blocks, did, rkey),
+            queries::REMOVE_LIKE => self.remove_edge(Target::Likes, did, rkey),
+            queries::REMOVE_REPOST => self.remove_edge(Target::Reposts, did, rkey),
+            queries::REMOVE_REPLY => self.remove_edge(Target::Replies, did, rkey),
+            queries::REMOVE_QUOTE => self.remove_edge(Target::Quotes, did, rkey),
+            queries::REMOVE_POST => self.rm_post(did, string(row, "uri")),
+            queries::REMOVE_ACCOUNT => self.rm_account(did),
+            _ => return Err(unsupported(query)),
+        };
+        Ok(())
+    }
+}
+
+impl State {
+    /// The `MERGE (u:User {did: ...}) SET u.last_seen = timestamp()` most writes start with
+    fn touch(&mut self, did: &str) -> &mut User {
+        let user = self.users.entry(did.to_owned()).or_default();
+        user.last_seen = Some(now() as i64);
+        user
+    }
+
+    fn link(&mut self, kind: Target, did: &str, to: &str, rkey: &str, populate: bool) {
+        match to_user(kind) {
+            true => {
+                for d in [did, to] {
+                    let user = self.touch(d);
+                    // POPULATE_FOLLOW marks both ends as feed users
+                    if populate && kind == Target::Follows {
+                        user.feed_user = true;
+                    }
+                }
+                self.touch(to).incoming.insert(did.to_owned());
+            }
+            false => match self.posts.get_mut(to) {
+                Some(post) => {
+                    if let Some(n) = post.counter(kind) {
+                        *n += 1;
+                    }
+                    post.engaged.insert(did.to_owned());
+                    self.touch(did);
+                }
+                None => return,
+            },
+        }
+
+        let user = self.users.entry(did.to_owned()).or_default();
+        // Follows & blocks are merged, engagement is created every time
+        let exists = user.out(kind).any(|e| e.rkey == rkey && e.to == to);
+        if !((populate || to_user(kind)) && exists) {
+            user.edges.push(Edge {
+                kind,
+                rkey: rkey.to_owned(),
+                to: to.to_owned(),
+                created_at: now() as i64,
+            });
+        }
+    }
+
+    fn unlink(&mut self, kind: Target, did: &str, rkey: &str) {
+        let removed = self.remove_edges(did, |e| e.kind == kind && e.rkey == rkey);
+        if removed.is_empty() {
+            return;
+        }
+        // Only REMOVE_BLOCK leaves last_seen alone
+        if kind != Target::Blocks {
+            self.touch(did);
+        }
+        for edge in removed {
+            if let Some(n) = self.posts.get_mut(&edge.to).and_then(|p| p.counter(kind)) {
+                *n -= 1;
+            }
+        }
+    }
+
+    /// Takes the edges `did` made that match, keeping who points at what in step. Counters are left to the caller,
+    /// as `DELETE` & `DETACH DELETE` dont touch them
+    fn remove_edges(&mut self, did: &str, matches: impl Fn(&Edge) -> bool) -> Vec<Edge> {
+        let user = match self.users.get_mut(did) {
+            Some(u) => u,
+            None => return Vec::new(),
+        };
+        let (removed, kept): (Vec<Edge>, Vec<Edge>) =
+            user.edges.drain(..).partition(|e| matches(e));
+        user.edges = kept;
+        let still: HashSet<(bool, String)> = user
+            .edges
+            .iter()
+            .map(|e| (to_user(e.kind), e.to.clone()))
+            .collect();
+
+        for edge in &removed {
+            if still.contains(&(to_user(edge.kind), edge.to.clone())) {
+                continue;
+            }
+            match to_user(edge.kind) {
+                true => {
+                    if let Some(u) = self.users.get_mut(&edge.to) {
+                        u.incoming.remove(did);
+                    }
+                }
+                false => {
+                    if let Some(p) = self.posts.get_mut(&edge.to) {
+                        p.engaged.remove(did);
+                    }
+                }
+            }
+        }
+        removed
+    }
+
+    fn add_post(&mut self, did: &str, uri: &str, timestamp: i64, parent_did: &str) {
+        self.touch(did).posts.insert(uri.to_owned());
+        self.posts
+            .entry(uri.to_owned())
+            .or_insert_with(|| Post {
+                uri: uri.to_owned(),
+                author: None,
+                timestamp,
+                is_reply: !parent_did.is_empty(),
+                reply_parent: parent_did.to_owned(),
+                likes: 0,
+                reposts: 0,
+                replies: 0,
+                quotes: 0,
+                engaged: HashSet::new(),
+            })
+            .author = Some(did.to_owned());
+    }
+
+    /// `DETACH DELETE` on a post
+    fn drop_post(&mut self, uri: &str) {
+        let post = match self.posts.remove(uri) {
+            Some(p) => p,
+            None => return,
+        };
+        for did in &post.engaged {
+            self.remove_edges(did, |e| !to_user(e.kind) && e.to == uri);
+        }
+        if let Some(u) = post.author.and_then(|a| self.users.get_mut(&a)) {
+            u.posts.remove(uri);
+        }
+    }
+
+    /// `DETACH DELETE` on a user. Their posts stay, without an author
+    fn drop_user(&mut self, did: &str) {
+        let user = match self.users.remove(did) {
+            Some(u) => u,
+            None => return,
+        };
+        for edge in &user.edges {
+            match to_user(edge.kind) {
+                true => {
+                    if let Some(u) = self.users.get_mut(&edge.to) {
+                        u.incoming.remove(did);
+                    }
+                }
+                false => {
+                    if let Some(p) = self.posts.get_mut(&edge.to) {
+                        p.engaged.remove(did);
+                    }
+                }
+            }
+        }
+        for src in &user.incoming {
+            if let Some(u) = self.users.get_mut(src) {
+                u.edges.retain(|e| !(to_user(e.kind) && e.to == did));
+            }
+        }
+        for uri in &user.posts {
+            if let Some(p) = self.posts.get_mut(uri) {
+                p.author = None;
+            }
+        }
+    }
+
+    /// Same as `Target::matcher`, for everything older than `before`
+    fn expire(&mut self, target: Target, before: i64, protect: bool, dry_run: bool) -> u64 {
+        match target {
+            Target::Posts => {
+                let old: Vec<String> = self
+                    .posts
+                    .iter()
+                    .filter(|(_, p)| p.timestamp < before)
+                    .map(|(uri, _)| uri.clone())
+                    .collect();
+                if !dry_run {
+                    for uri in &old {
+                        self.drop_post(uri);
+                    }
+                }
+                old.len() as u64
+            }
+            Target::Users => {
+                let old: Vec<String> = self
+                    .users
+                    .iter()
+                    .filter(|(did, u)| {
+                        u.last_seen.is_some_and(|t| t < before)
+                            && !(protect && (u.feed_user || self.followed_by_viewer(did)))
+                    })
+                    .map(|(did, _)| did.clone())
+                    .collect();
+                if !dry_run {
+                    for did in &old {
+                        self.drop_user(did);
+                    }
+                }
+                old.len() as u64
+            }
+            kind => {
+                let mut n = 0;
+                let dids: Vec<String> = self.users.keys().cloned().collect();
+                for did in dids {
+                    let user = &self.users[&did];
+                    if protect && (user.feed_user || self.followed_by_viewer(&did)) {
+                        continue;
+                    }
+                    let old = |e: &Edge| e.kind == kind && e.created_at < before;
+                    n += match dry_run {
+                        true => user.edges.iter().filter(|e| old(e)).count(),
+                        false => self.remove_edges(&did, old).len(),
+                    } as u64;
+                }
+                n
+            }
+        }
+    }
+
+    fn followed_by_viewer(&self, did: &str) -> bool {
+        self.users[did].incoming.iter().any(|src| {
+            self.users
+                .get(src)
+                .is_some_and(|u| u.feed_user && u.out(Target::Follows).any(|e| e.to == did))
+        })
+    }
+
+    fn set_thresholds(&mut self, did: &str, target: i64) {
+        let og = match self.users.get(did) {
+            Some(u) => u,
+            None => return,
+        };
+        let second = self.second_degree(og);
+        // The `target`th most of `signal`, or nothing to go on
+        let nth = |posts: Vec<&Post>, signal: fn(&Post) -> i64| {
+            let mut v: Vec<i64> = posts.into_iter().map(signal).collect();
+            v.sort();
+            let idx = (v.len() as i64 - target).max(0) as usize;
+            v.get(idx).map(|n| (*n).max(1))
+        };
+        let thresholds = (
+            nth(self.posted_by(&second), |p| p.likes),
+            nth(self.posted_by(&second), |p| p.reposts),
+            nth(self.engaged_by(&second, Target::Likes), |p| p.likes),
+            nth(self.engaged_by(&second, Target::Reposts), |p| p.likes),
+            nth(self.engaged_by(&second, Target::Quotes), |p| p.quotes),
+        );
+        if let Some(og) = self.users.get_mut(did) {
+            (
+                og.like_threshold,
+                og.repost_threshold,
+                og.liked_threshold,
+                og.reposted_threshold,
+                og.quote_threshold,
+            ) = thresholds;
+        }
+    }
+
+    /// `(og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(u)`
+    fn second_degree(&self, og: &User) -> HashSet<&str> {
+        og.out(Target::Follows)
+            .filter_map(|e| self.users.get(&e.to))
+            .flat_map(|u| u.out(Target::Follows).map(|e| e.to.as_str()))
+            .collect()
+    }
+
+    fn posted_by<'a>(&'a self, dids: &HashSet<&str>) -> Vec<&'a Post> {
+        dids.iter()
+            .filter_map(|d| self.users.get(*d))
+            .flat_map(|u| u.posts.iter().filter_map(|uri| self.posts.get(uri)))
+            .collect()
+    }
+
+    /// Posts active users in `dids` have a `kind` edge to
+    fn engaged_by<'a>(&'a self, dids: &HashSet<&str>, kind: Target) -> Vec<&'a Post> {
+        let uris: HashSet<&str> = dids
+            .iter()
+            .filter_map(|d| self.users.get(*d))
+            .filter(|u| u.active())
+            .flat_map(|u| u.out(kind).map(|e| e.to.as_str()))
+            .collect();
+        uris.into_iter()
+            .filter_map(|uri| self.posts.get(uri))
+            .collect()
+    }
+
+    fn feed(
+        &self,
+        name: &str,
+        reason: &str,
+        did: &str,
+        replies: bool,
+        time: i64,
+    ) -> HashMap<String, PostMsg> {
+        let og = match self.users.get(did) {
+            Some(u) => u,
+            None => return HashMap::new(),
+        };
+        let follows: HashSet<&str> = og.out(Target::Follows).map(|e| e.to.as_str()).collect();
+        let blocked: HashSet<&str> = og.out(Target::Blocks).map(|e| e.to.as_str()).collect();
+        let second = self.second_degree(og);
+
+        let (candidates, attribute): (Vec<&Post>, bool) = match name {
+            "GET_FOLLOWING_PLUS_LIKES" => {
+                let min = og.like_threshold.unwrap_or(75);
+                let posts = self.posted_by(&second);
+                (
+                    posts.into_iter().filter(|p| p.likes >= min).collect(),
+                    false,
+                )
+            }
+            "GET_FOLLOWING_PLUS_REPOSTS" => {
+                let min = og.repost_threshold.unwrap_or(60);
+                let posts = self.posted_by(&second);
+                (
+                    posts.into_iter().filter(|p| p.reposts >= min).collect(),
+                    true,
+                )
+            }
+            "GET_BEST_2ND_DEG_LIKES" => {
+                let min = og.liked_threshold.unwrap_or(100);
+                let posts = self.engaged_by(&second, Target::Likes);
+                (
+                    posts.into_iter().filter(|p| p.likes >= min).collect(),
+                    false,
+                )
+            }
+            "GET_BEST_2ND_DEG_REPOSTS" => {
+                let min = og.reposted_threshold.unwrap_or(50);
+                let posts = self.engaged_by(&second, Target::Reposts);
+                (posts.into_iter().filter(|p| p.likes >= min).collect(), true)
+            }
+            "GET_BEST_2ND_DEG_QUOTES" => {
+                let min = og.quote_threshold.unwrap_or(10);
+                let posts = self.engaged_by(&second, Target::Quotes);
+                (
+                    posts.into_iter().filter(|p| p.quotes >= min).collect(),
+                    false,
+                )
+            }
+            "GET_BEST_FOLLOWED" => {
+                let posts = self.posted_by(&follows);
+                let recent =
+                    |p: &&Post| (p.likes > 10 || p.reposts > 5) && p.timestamp - time <= 120000000;
+                (posts.into_iter().filter(recent).collect(), false)
+            }
+            _ => return HashMap::new(),
+        };
+        // GET_BEST_FOLLOWED only has posts from who we follow, & no cutoff
+        let followed = name == "GET_BEST_FOLLOWED";
+
+        let mut rows: Vec<&Post> = candidates
+            .into_iter()
+            .filter(|p| {
+                let author = match p.author.as_deref() {
+                    Some(a) => a,
+                    None => return false,
+                };
+                self.users.get(author).is_some_and(User::active)
+                    && (followed || (!blocked.contains(author) && p.timestamp < time))
+                    && (!p.is_reply
+                        || (replies
+                            && follows.contains(author)
+                            && follows.contains(p.reply_parent.as_str())))
+            })
+            .collect();
+        rows.sort_by_key(|p| Reverse(p.timestamp));
+        rows.truncate(FEED_LIMIT);
+
+        rows.into_iter()
+            .map(|p| {
+                let repost = match attribute {
+                    true => self.repost_by(og, &p.uri),
+                    false => None,
+                };
+                let msg = PostMsg {
+                    uri: p.uri.clone(),
+                    reason: reason.to_owned(),
+                    timestamp: p.timestamp.max(0) as u64,
+                    likes: p.likes.max(0) as u64,
+                    reposts: p.reposts.max(0) as u64,
+                    replies: p.replies.max(0) as u64,
+                    quotes: p.quotes.max(0) as u64,
+                    repost,
+                };
+                (p.uri.clone(), msg)
+            })
+            .collect()
+    }
+
+    /// A repost of `uri` by someone `og` follows
+    fn repost_by(&self, og: &User, uri: &str) -> Option<String> {
+        og.out(Target::Follows).find_map(|f| {
+            self.users.get(&f.to).and_then(|r| {
+                r.out(Target::Reposts)
+                    .find(|e| e.to == uri)
+                    .map(|e| format!("at://{}/app.bsky.feed.repost/{}", f.to, e.rkey))
+            })
+        })
+    }
+}
+
+fn string<'a>(params: &'a Params, key: &str) -> &'a str {
+    match params.get(key) {
+        Some(Param::Str(s)) => s,
+        _ => "",
+    }
+}
+
+fn int(params: &Params, key: &str) -> i64 {
+    match params.get(key) {
+        Some(Param::Int(i)) => *i,
+        _ => 0,
+    }
+}
+
+fn flag(params: &Params, key: &str) -> bool {
+    matches!(params.get(key), Some(Param::Bool(true)))
+}
+
+fn unsupported(query: &str) -> Box<dyn Error> {
+    format!(
+        "Query not supported by the in memory graph: {}",
+        query.trim()
+    )
+    .into()
+}
+
+impl EventDatabase<HashMap<String, PostMsg>> for MemoryGraph {
+    async fn read(
+        &self,
+        query_name: &str,
+        query: &str,
+        params: Option<Params>,
+    ) -> Result<HashMap<String, PostMsg>, Box<dyn Error>> {
+        let params = params.unwrap_or_default();
+        match queries::FEED_QUERIES.iter().find(|(_, q)| *q == query) {
+            Some((name, _)) => Ok(self.feed(
+                name,
+                query_name,
+                string(&params, "did"),
+                flag(&params, "replies"),
+                int(&params, "time"),
+            )),
+            None => Err(unsupported(query)),
+        }
+    }
+
+    async fn write(&self, query: &str, params: Option<Params>) -> Option<Box<dyn Error>> {
+        self.run(query, &params.unwrap_or_default()).err()
+    }
+
+    async fn batch_write(
+        &self,
+        queries: Vec<&str>,
+        params: Vec<Option<Params>>,
+    ) -> Option<Box<dyn Error>> {
+        for (query, params) in queries.into_iter().zip(params) {
+            if let Err(e) = self.run(query, &params.unwrap_or_default()) {
+                return Some(e);
+            }
+        }
+        None
+    }
+
+    /// Chunking only matters for a real transaction, so every row goes in one after the other
+    async fn chunk_write(
+        &self,
+        query: &str,
+        params: Vec<Params>,
+        _chunk_size: usize,
+        _param_name: &str,
+    ) -> Option<Box<dyn Error>> {
+        for row in &params {
+            if let Err(e) = self.apply(query, row) {
+                return Some(e);
+            }
+        }
+        None
+    }
+
+    async fn batch_read(
+        &self,
+        queries: Vec<&str>,
+        params: Vec<Option<Params>>,
+    ) -> Result<Vec<HashMap<String, PostMsg>>, Box<dyn Error>> {
+        let mut res = Vec::new();
+        for (query, params) in queries.into_iter().zip(params) {
+            res.push(self.read(query, query, params).await?);
+        }
+        Ok(res)
+    }
+}
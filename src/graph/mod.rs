@@ -4,30 +4,42 @@ use backoff::{ExponentialBackoffBuilder, future::retry};
 use neo4rs::Graph;
 use tracing::{error, info, warn};
 
-use crate::{common::PostMsg, event_database::EventDatabase};
+use crate::{
+    common::PostMsg,
+    event_database::{EventDatabase, Params},
+};
+use dialect::Dialect;
 
+pub mod dialect;
 mod graph_test;
+pub mod memory;
 pub mod queries;
-
-fn get_post_uri(did: String, rkey: String) -> String {
-    format!("at://{did}/app.bsky.feed.post/{rkey}")
-}
+pub mod retention;
 
 macro_rules! process_next {
     ($next_expr:expr_2021, $posts_expr:expr_2021, $reason:expr_2021) => {
         match $next_expr {
             Ok(v) => match v {
                 Some(v) => {
-                    let uri: String = v.get("url").unwrap();
-                    let user: String = v.get("user").unwrap();
-                    let uri = crate::graph::get_post_uri(user, uri);
-                    let timestamp: u64 = v.get("ts").unwrap();
+                    // Posts not yet migrated off string timestamps are left out, rather than take the reader down
+                    let (uri, timestamp): (String, u64) = match (v.get("uri"), v.get("ts")) {
+                        (Ok(uri), Ok(ts)) => (uri, ts),
+                        (uri, ts) => {
+                            warn!("Skipping a {} row: {:?} {:?}", $reason, uri.err(), ts.err());
+                            continue;
+                        }
+                    };
                     $posts_expr.insert(
                         uri.clone(),
                         PostMsg {
                             reason: $reason.to_string(),
                             uri,
                             timestamp,
+                            likes: v.get("likes").unwrap_or_default(),
+                            reposts: v.get("reposts").unwrap_or_default(),
+                            replies: v.get("replies").unwrap_or_default(),
+                            quotes: v.get("quotes").unwrap_or_default(),
+                            repost: v.get("repost").ok(),
                         },
                     );
                 }
@@ -46,6 +58,7 @@ macro_rules! process_next {
 #[derive(Clone)]
 pub struct GraphFetcher {
     conn: Graph,
+    dialect: Dialect,
 }
 
 impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
@@ -53,9 +66,9 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
         &self,
         query_name: &str,
         query: &str,
-        params: Option<HashMap<String, String>>,
+        params: Option<Params>,
     ) -> Result<HashMap<String, PostMsg>, Box<dyn std::error::Error>> {
-        let mut qry = neo4rs::query(query);
+        let mut qry = self.dialect.query(query);
         match params {
             Some(p) => {
                 qry = qry.params(p);
@@ -78,9 +91,9 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
     async fn write(
         &self,
         query: &str,
-        params: Option<HashMap<String, String>>,
+        params: Option<Params>,
     ) -> Option<Box<dyn std::error::Error>> {
-        let mut qry = neo4rs::query(query);
+        let mut qry = self.dialect.query(query);
         match params {
             Some(p) => {
                 qry = qry.params(p);
@@ -98,7 +111,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
     async fn chunk_write(
         &self,
         query: &str,
-        params: Vec<HashMap<String, String>>,
+        params: Vec<Params>,
         chunk_size: usize,
         param_name: &str,
     ) -> Option<Box<dyn std::error::Error>> {
@@ -112,7 +125,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
 
         let mut qrys = Vec::new();
         for follow_chunk in chunks {
-            let qry = neo4rs::query(query).param(param_name, follow_chunk);
+            let qry = self.dialect.query(query).param(param_name, follow_chunk);
             qrys.push(qry);
         }
 
@@ -154,7 +167,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
     async fn batch_write(
         &self,
         queries: Vec<&str>,
-        params: Vec<Option<HashMap<String, String>>>,
+        params: Vec<Option<Params>>,
     ) -> Option<Box<dyn std::error::Error>> {
         if queries.len() != params.len() {
             error!("Queries and params must be the same length");
@@ -164,7 +177,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
 
         let mut qrys = Vec::new();
         for (i, q) in queries.iter().enumerate() {
-            let mut qry = neo4rs::query(q);
+            let mut qry = self.dialect.query(q);
             match params.get(i) {
                 Some(p) => match p {
                     Some(p) => qry = qry.params(p.clone()),
@@ -212,7 +225,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
     async fn batch_read(
         &self,
         queries: Vec<&str>,
-        params: Vec<Option<HashMap<String, String>>>,
+        params: Vec<Option<Params>>,
     ) -> Result<Vec<HashMap<String, PostMsg>>, Box<dyn std::error::Error>> {
         let mut res = Vec::new();
         if queries.len() != params.len() {
@@ -235,7 +248,7 @@ impl EventDatabase<HashMap<String, PostMsg>> for GraphFetcher {
 }
 
 impl GraphFetcher {
-    pub fn new(conn: Graph) -> Self {
-        Self { conn }
+    pub fn new(conn: Graph, dialect: Dialect) -> Self {
+        Self { conn, dialect }
     }
 }
@@ -1,64 +1,12 @@
-use std::{sync::Arc, time::Duration};
-
-use backoff::{Error, ExponentialBackoffBuilder, future::retry};
-use neo4rs::Graph;
-use tokio::sync::RwLock;
-use tracing::{info, warn};
-
-const PURGE_TIME: u64 = 5 * 60;
-
-pub async fn kickoff_purge(lock: Arc<RwLock<()>>, conn: Graph) -> Result<(), neo4rs::Error> {
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(PURGE_TIME)).await;
-        info!("Purging");
-        let lock = lock.write().await;
-        match retry(
-            ExponentialBackoffBuilder::default()
-                .with_initial_interval(Duration::from_millis(50))
-                .with_max_elapsed_time(Some(Duration::from_millis(10000)))
-                .build(),
-            || async {
-                let qry = neo4rs::query(PURGE_OLD_POSTS);
-                let qry3: neo4rs::Query = neo4rs::query(PURGE_DISCONNECTED);
-
-                let mut tx = conn.start_txn().await.unwrap();
-                match tx.run_queries(vec![qry, qry3]).await {
-                    Ok(_) => {
-                        let res = match tx.commit().await {
-                            Ok(_) => Ok(()),
-                            Err(e) => Err(Error::Transient {
-                                err: e,
-                                retry_after: None,
-                            }),
-                        };
-                        res
-                    }
-                    Err(e) => Err(Error::Transient {
-                        err: e,
-                        retry_after: None,
-                    }),
-                }
-            },
-        )
-        .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                warn!("Error on purge query: {}", e);
-            }
-        };
-        drop(lock);
-        info!("Done!");
-    }
-}
-
+/// Merged on the rkey, as a reconcile can have written the same follow or block already
 pub(crate) const ADD_FOLLOW: &str = r#"
 UNWIND $follows as follow
 MERGE (u:User {did: follow.did})
     SET u.last_seen = timestamp()
 MERGE (v:User {did: follow.out})
     SET v.last_seen = timestamp()
-CREATE (u)-[r:FOLLOWS {rkey: follow.rkey }]->(v)
+MERGE (u)-[r:FOLLOWS {rkey: follow.rkey }]->(v)
+    ON CREATE SET r.created_at = timestamp()
 "#;
 
 pub(crate) const POPULATE_FOLLOW: &str = r#"
@@ -70,58 +18,81 @@ MERGE (v:User {did: follow.out})
     SET v.last_seen = timestamp()
     SET v.feed_user = true
 MERGE (u)-[r:FOLLOWS { rkey: follow.rkey }]->(v)
+    ON CREATE SET r.created_at = timestamp()
 "#;
 
 pub(crate) const ADD_BLOCK: &str = r#"
 UNWIND $blocks as block
-MERGE (u:User {did: block.did})
-    SET u.last_seen = timestamp()
-MERGE (v:User {did: block.blockee})
-    SET v.last_seen = timestamp()
-CREATE (u)-[r:BLOCKED {rkey: block.rkey }]->(v)
-"#;
-
-pub(crate) const POPULATE_BLOCK: &str = r#"
-UNWIND $blocks as block
 MERGE (u:User {did: block.did})
     SET u.last_seen = timestamp()
 MERGE (v:User {did: block.blockee})
     SET v.last_seen = timestamp()
 MERGE (u)-[r:BLOCKED {rkey: block.rkey }]->(v)
+    ON CREATE SET r.created_at = timestamp()
 "#;
 
+/// The same as ADD_BLOCK, which already merges on the rkey
+pub(crate) const POPULATE_BLOCK: &str = ADD_BLOCK;
+
 pub(crate) const ADD_LIKE: &str = r#"
 UNWIND $likes as like
-MATCH (p:Post) WHERE p.rkey = like.rkey_parent
+MATCH (p:Post {uri: like.subject})
 SET p.likes = p.likes + 1
 MERGE (u:User {did: like.did})
     SET u.last_seen = timestamp()
 
-CREATE (u)-[r:LIKES {rkey: like.rkey }]->(p)
+CREATE (u)-[r:LIKES {rkey: like.rkey, created_at: timestamp() }]->(p)
 "#;
 
 pub(crate) const ADD_POST: &str = r#"
 UNWIND $posts as post
 MERGE (u:User {did: post.did})
     SET u.last_seen = timestamp()
-CREATE (u)-[:POSTED]->(p: Post { timestamp: post.timestamp, rkey: post.rkey, isReply: post.is_reply, type: post.post_type, likes: 0, reposts: 0} )
+// Posts are unique on uri, so replayed events must not try to create them again
+MERGE (p:Post {uri: post.uri})
+    ON CREATE SET p.timestamp = post.timestamp, p.rkey = post.rkey, p.isReply = post.is_reply, p.replyParent = post.parent_did, p.type = post.post_type, p.likes = 0, p.reposts = 0, p.replies = 0, p.quotes = 0
+MERGE (u)-[:POSTED]->(p)
 "#;
 
 pub(crate) const ADD_REPOST: &str = r#"
 UNWIND $reposts as repost
-MATCH (p:Post) WHERE p.rkey = repost.rkey_parent
+MATCH (p:Post {uri: repost.subject})
 SET p.reposts = p.reposts + 1
 MERGE (u:User {did: repost.did})
     SET u.last_seen = timestamp()
-CREATE (u)-[r:REPOSTED {rkey: repost.rkey}]->(p)
+CREATE (u)-[r:REPOSTED {rkey: repost.rkey, created_at: timestamp() }]->(p)
 "#;
 
 pub(crate) const ADD_REPLY: &str = r#"
 UNWIND $replies as reply
-MATCH (p:Post) WHERE p.rkey = reply.parent
+MATCH (p:Post {uri: reply.parent})
+SET p.replies = coalesce(p.replies, 0) + 1
 MERGE (u:User {did: reply.did})
     SET u.last_seen = timestamp()
-CREATE (u)-[r:REPLIED_TO {rkey: reply.rkey }]->(p)
+CREATE (u)-[r:REPLIED_TO {rkey: reply.rkey, root: reply.root, created_at: timestamp() }]->(p)
+"#;
+
+pub(crate) const ADD_QUOTE: &str = r#"
+UNWIND $quotes as quote
+MATCH (p:Post {uri: quote.subject})
+SET p.quotes = p.quotes + 1
+MERGE (u:User {did: quote.did})
+    SET u.last_seen = timestamp()
+CREATE (u)-[r:QUOTED {rkey: quote.rkey, created_at: timestamp() }]->(p)
+"#;
+
+pub(crate) const UPDATE_HANDLE: &str = r#"
+UNWIND $handles as handle
+MERGE (u:User {did: handle.did})
+    SET u.handle = handle.handle
+"#;
+
+// Inactive users are kept, but hidden from feeds until they're reactivated
+pub(crate) const UPDATE_ACCOUNT: &str = r#"
+UNWIND $accounts as account
+MERGE (u:User {did: account.did})
+    SET u.active = account.active
+    SET u.status = account.status
 "#;
 
 /////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -149,7 +120,7 @@ DELETE r
 
 pub(crate) const REMOVE_POST: &str = r#"
 UNWIND $posts as post
-MATCH (u:User {did: post.did})-[:POSTED]->(p:Post {rkey: post.rkey})
+MATCH (u:User {did: post.did})-[:POSTED]->(p:Post {uri: post.uri})
 SET u.last_seen = timestamp()
 DETACH DELETE p
 "#;
@@ -158,8 +129,7 @@ pub(crate) const REMOVE_REPLY: &str = r#"
 UNWIND $replies as reply
 MATCH (u:User {did: reply.did})-[r:REPLIED_TO {rkey: reply.rkey }]->(p:Post)
 SET u.last_seen = timestamp()
-WITH p, r
-WHERE p.is_reply == "y"
+SET p.replies = CASE WHEN coalesce(p.replies, 0) > 0 THEN p.replies - 1 ELSE 0 END
 DELETE r
 "#;
 
@@ -167,26 +137,92 @@ pub(crate) const REMOVE_REPOST: &str = r#"
 UNWIND $reposts as repost
 MATCH (u:User {did: repost.did})-[r:REPOSTED {rkey: repost.rkey }]->(p:Post)
 SET u.last_seen = timestamp()
-SET p.likes = p.reposts - 1
+SET p.reposts = p.reposts - 1
 DELETE r
 "#;
 
-//////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+pub(crate) const REMOVE_QUOTE: &str = r#"
+UNWIND $quotes as quote
+MATCH (u:User {did: quote.did})-[r:QUOTED {rkey: quote.rkey }]->(p:Post)
+SET u.last_seen = timestamp()
+SET p.quotes = p.quotes - 1
+DELETE r
+"#;
 
-pub(crate) const PURGE_OLD_POSTS: &str = r#"
-MATCH (p:Post) WHERE toInteger(p.timestamp) < (timestamp() - 7200000000) // 2 hours
+/// Takes back the account's likes, reposts, quotes & replies from the counts on what they engaged with, as
+/// DETACH DELETE leaves them be
+pub(crate) const REMOVE_ACCOUNT: &str = r#"
+UNWIND $accounts as account
+MATCH (u:User {did: account.did})
+OPTIONAL MATCH (u)-[:LIKES]->(liked:Post)
+WITH u, collect(liked) AS liked
+FOREACH (p IN liked | SET p.likes = p.likes - 1)
+WITH u
+OPTIONAL MATCH (u)-[:REPOSTED]->(reposted:Post)
+WITH u, collect(reposted) AS reposted
+FOREACH (p IN reposted | SET p.reposts = p.reposts - 1)
+WITH u
+OPTIONAL MATCH (u)-[:QUOTED]->(quoted:Post)
+WITH u, collect(quoted) AS quoted
+FOREACH (p IN quoted | SET p.quotes = p.quotes - 1)
+WITH u
+OPTIONAL MATCH (u)-[:REPLIED_TO]->(replied:Post)
+WITH u, collect(replied) AS replied
+FOREACH (p IN replied | SET p.replies = CASE WHEN coalesce(p.replies, 0) > 0 THEN p.replies - 1 ELSE 0 END)
+WITH u
+OPTIONAL MATCH (u)-[:POSTED]->(p:Post)
 DETACH DELETE p
+WITH DISTINCT u
+DETACH DELETE u
 "#;
 
-pub(crate) const PURGE_DISCONNECTED: &str = r#"
- MATCH (p:User)
-    WHERE p.last_seen < (timestamp() - 14400000000) // 4 hours AND !p.feed_user
- DETACH DELETE p
- "#;
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Posts written before params were typed had their timestamp & reply flag stored as strings. Converted ones
+/// no longer match, so this runs until a batch comes back short.
+/// Neo4j adds whether it can be null to the type name, Memgraph doesnt
+/// Feeds skip posts still waiting on this rather than fail on comparing their string timestamp
+pub(crate) const MIGRATE_POST_PROPERTIES: &str = r#"
+MATCH (p:Post)
+WHERE valueType(p.timestamp) STARTS WITH "STRING"
+WITH p LIMIT $batch
+SET p.timestamp = toInteger(p.timestamp), p.isReply = p.isReply = "y"
+RETURN count(*) AS n
+"#;
+
+/// Retention runs one of these per rule, with `{target}` swapped for `retention::Target::matcher`
+pub(crate) const RETENTION_COUNT: &str = r#"
+{target}
+RETURN count(n) AS n
+"#;
+
+pub(crate) const RETENTION_DELETE_NODES: &str = r#"
+{target}
+WITH n LIMIT $batch
+DETACH DELETE n
+RETURN count(*) AS n
+"#;
+
+pub(crate) const RETENTION_DELETE_EDGES: &str = r#"
+{target}
+WITH n LIMIT $batch
+DELETE n
+RETURN count(*) AS n
+"#;
 
 /////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
-/// Sorting by timestamp happens in RustLand, as it seems to be signigicantly faster than in memgraphLand (~2.3s for each query -> 300ms), given that we sort by ts again anyway once the results are combined
+/// Scoring & sorting happens in RustLand, as it seems to be signigicantly faster than in memgraphLand (~2.3s for each query -> 300ms), given that we rank again anyway once the results are combined
 ///
+/// Every candidate query for a feed, by name. Each takes `$did`, `$replies` & `$time` (microseconds) as params
+pub(crate) const FEED_QUERIES: [(&str, &str); 6] = [
+    ("GET_BEST_2ND_DEG_LIKES", GET_BEST_2ND_DEG_LIKES),
+    ("GET_BEST_2ND_DEG_REPOSTS", GET_BEST_2ND_DEG_REPOSTS),
+    ("GET_FOLLOWING_PLUS_LIKES", GET_FOLLOWING_PLUS_LIKES),
+    ("GET_FOLLOWING_PLUS_REPOSTS", GET_FOLLOWING_PLUS_REPOSTS),
+    ("GET_BEST_FOLLOWED", GET_BEST_FOLLOWED),
+    ("GET_BEST_2ND_DEG_QUOTES", GET_BEST_2ND_DEG_QUOTES),
+];
+
 pub(crate) const GET_FOLLOWING_PLUS_LIKES: &str = r#"
 MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(u:User)-[:POSTED]->(p:Post)
 
@@ -194,16 +230,18 @@ MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(u:User)-[:POSTED]->
 
 WITH og, u, p AS post
 
-OPTIONAL MATCH (og)-[b:BLOCKS]->(u)
-with u,b, post, CASE WHEN b IS NULL 
+OPTIONAL MATCH (og)-[b:BLOCKED]->(u)
+with og, u,b, post, CASE WHEN b IS NULL 
   THEN post ELSE NULL END as p
-WHERE p IS NOT NULL AND p.likes >= 75
+WHERE p IS NOT NULL AND p.likes >= coalesce(og.like_threshold, 75) AND coalesce(u.active, true)
 // Filter off posts from blocked users
 
-WITH p, u, toInteger(p.timestamp) AS ts
-WHERE ts < {}
+WITH og, p, u, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts
+WHERE ts < $time
+// Only keep replies where we follow both the replier & who they replied to
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(u)) AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
 
-RETURN u.did AS user, p.rkey AS url, ts ORDER BY ts DESC LIMIT 600
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes ORDER BY ts DESC LIMIT 600
 "#;
 
 pub(crate) const GET_FOLLOWING_PLUS_REPOSTS: &str = r#"
@@ -211,59 +249,246 @@ MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(u:User)-[:POSTED]->
 WITH og, u, p AS post
 
 
-OPTIONAL MATCH (og)-[b:BLOCKS]->(u)
-WITH u,b, post, CASE WHEN b IS NULL 
+OPTIONAL MATCH (og)-[b:BLOCKED]->(u)
+WITH og, u,b, post, CASE WHEN b IS NULL 
   THEN post ELSE NULL END as p
-WHERE p IS NOT NULL AND p.reposts >= 60
+WHERE p IS NOT NULL AND p.reposts >= coalesce(og.repost_threshold, 60) AND coalesce(u.active, true)
 // Filter off posts from blocked users
-WITH p, u, toInteger(p.timestamp) AS ts
+WITH og, p, u, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts
+
+WHERE ts < $time
+// Only keep replies where we follow both the replier & who they replied to
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(u)) AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
 
-WHERE ts < {}
+// Attribute the post to a repost from someone we follow, if there is one
+OPTIONAL MATCH (og)-[:FOLLOWS]->(r:User)-[rp:REPOSTED]->(p)
+WITH p, ts, head(collect("at://" + r.did + "/app.bsky.feed.repost/" + rp.rkey)) AS repost
 
-RETURN u.did AS user, p.rkey AS url, ts ORDER BY ts DESC LIMIT 600
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes, repost ORDER BY ts DESC LIMIT 600
 "#;
 
 pub(crate) const GET_BEST_2ND_DEG_REPOSTS: &str = r#"
 MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(u:User)-[:REPOSTED]->(p:Post)
+WHERE coalesce(u.active, true) // dont count engagement from inactive users
 WITH p,og
-WHERE p.likes >= 50
+WHERE p.likes >= coalesce(og.reposted_threshold, 50)
  MATCH (p)<-[a:POSTED]-(u:User)
 WITH DISTINCT p, a, u, og
 
-OPTIONAL MATCH (og)-[b:BLOCKS]->(u)
-WITH u, b, p, toInteger(p.timestamp) AS ts, CASE WHEN b IS NULL 
+OPTIONAL MATCH (og)-[b:BLOCKED]->(u)
+WITH og, u, b, p, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts, CASE WHEN b IS NULL 
   THEN p ELSE NULL END as post
-WHERE post IS NOT NULL AND ts < {}
+WHERE post IS NOT NULL AND coalesce(u.active, true) AND ts < $time
+// Only keep replies where we follow both the replier & who they replied to
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(u)) AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
 
-RETURN u.did AS user, p.rkey AS url, ts ORDER BY ts DESC LIMIT 600
+// Attribute the post to a repost from someone we follow, if there is one
+OPTIONAL MATCH (og)-[:FOLLOWS]->(r:User)-[rp:REPOSTED]->(p)
+WITH p, ts, head(collect("at://" + r.did + "/app.bsky.feed.repost/" + rp.rkey)) AS repost
+
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes, repost ORDER BY ts DESC LIMIT 600
 "#;
 
 pub(crate) const GET_BEST_2ND_DEG_LIKES: &str = r#"
-MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(:User)-[:LIKES]->(p:Post)
+MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(e:User)-[:LIKES]->(p:Post)
+WHERE coalesce(e.active, true) // dont count engagement from inactive users
 WITH p,og
-WHERE p.likes >= 100
+WHERE p.likes >= coalesce(og.liked_threshold, 100)
 
 MATCH (p)<-[a:POSTED]-(u:User)
 WITH DISTINCT p, a, u, og
 
-OPTIONAL MATCH (og)-[b:BLOCKS]->(u)
-WITH u, b, p, toInteger(p.timestamp) AS ts,  CASE WHEN b IS NULL 
+OPTIONAL MATCH (og)-[b:BLOCKED]->(u)
+WITH og, u, b, p, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts,  CASE WHEN b IS NULL 
   THEN p ELSE NULL END as post
-WHERE post IS NOT NULL AND ts < {}
+WHERE post IS NOT NULL AND coalesce(u.active, true) AND ts < $time
+// Only keep replies where we follow both the replier & who they replied to
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(u)) AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
 
-RETURN u.did AS user, p.rkey AS url, ts ORDER BY ts DESC LIMIT 600
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes ORDER BY ts DESC LIMIT 600
+"#;
+
+pub(crate) const GET_BEST_2ND_DEG_QUOTES: &str = r#"
+MATCH (og:User {did: $did})-[:FOLLOWS]->(:User)-[:FOLLOWS]->(e:User)-[:QUOTED]->(p:Post)
+WHERE coalesce(e.active, true) // dont count engagement from inactive users
+WITH p,og
+WHERE p.quotes >= coalesce(og.quote_threshold, 10)
+
+MATCH (p)<-[a:POSTED]-(u:User)
+WITH DISTINCT p, a, u, og
+
+OPTIONAL MATCH (og)-[b:BLOCKED]->(u)
+WITH og, u, b, p, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts,  CASE WHEN b IS NULL 
+  THEN p ELSE NULL END as post
+WHERE post IS NOT NULL AND coalesce(u.active, true) AND ts < $time
+// Only keep replies where we follow both the replier & who they replied to
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(u)) AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
+
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes ORDER BY ts DESC LIMIT 600
 "#;
 
 pub(crate) const GET_BEST_FOLLOWED: &str = r#"
 MATCH (og:User {did: $did})-[:FOLLOWS]->(u:User)-[:POSTED]->(p:Post)
-WITH og, p, u, toInteger(p.timestamp) AS ts
-WHERE (p.likes > 10 OR p.reposts > 5) AND (ts - {}) <= 120000000 // last 2 mins
+WITH og, p, u, CASE WHEN valueType(p.timestamp) STARTS WITH "INTEGER" THEN p.timestamp END AS ts
+WHERE (p.likes > 10 OR p.reposts > 5) AND (ts - $time) <= 120000000 // last 2 mins
+AND coalesce(u.active, true)
+// We already follow the replier, so only the parent author needs checking
+AND (NOT (p.isReply IN [true, "y"]) OR ($replies AND exists((og)-[:FOLLOWS]->(:User {did: p.replyParent}))))
 
-RETURN u.did AS user, p.rkey AS url, ts ORDER BY ts DESC LIMIT 600
+RETURN p.uri AS uri, ts, p.likes AS likes, p.reposts AS reposts, coalesce(p.replies, 0) AS replies, coalesce(p.quotes, 0) AS quotes ORDER BY ts DESC LIMIT 600
+"#;
+
+/// Thresholds are the engagement of the `$target`th most popular of each feed's candidates in the viewer's 2nd
+/// degree network, so small & large networks end up with a similar number of them. Each signal is sorted on its
+/// own, & the defaults in each feed query are only used until this has run or when there is nothing to go on
+pub(crate) const SET_THRESHOLDS: &str = r#"
+MATCH (og:User {did: $did})
+
+// Posted by the 2nd degree, for GET_FOLLOWING_PLUS_LIKES & GET_FOLLOWING_PLUS_REPOSTS
+OPTIONAL MATCH (og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(:User)-[:POSTED]->(p:Post)
+WITH DISTINCT og, p
+WITH og, p.likes AS n ORDER BY n
+WITH og, collect(n) AS ns
+WITH og, ns[CASE WHEN size(ns) > $target THEN size(ns) - $target ELSE 0 END] AS n
+SET og.like_threshold = CASE WHEN n < 1 THEN 1 ELSE n END
+
+WITH og
+OPTIONAL MATCH (og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(:User)-[:POSTED]->(p:Post)
+WITH DISTINCT og, p
+WITH og, p.reposts AS n ORDER BY n
+WITH og, collect(n) AS ns
+WITH og, ns[CASE WHEN size(ns) > $target THEN size(ns) - $target ELSE 0 END] AS n
+SET og.repost_threshold = CASE WHEN n < 1 THEN 1 ELSE n END
+
+// Liked by the 2nd degree, for GET_BEST_2ND_DEG_LIKES
+WITH og
+OPTIONAL MATCH (og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(e:User)-[:LIKES]->(p:Post)
+WHERE coalesce(e.active, true)
+WITH DISTINCT og, p
+WITH og, p.likes AS n ORDER BY n
+WITH og, collect(n) AS ns
+WITH og, ns[CASE WHEN size(ns) > $target THEN size(ns) - $target ELSE 0 END] AS n
+SET og.liked_threshold = CASE WHEN n < 1 THEN 1 ELSE n END
+
+// Reposted by the 2nd degree, for GET_BEST_2ND_DEG_REPOSTS, which goes by likes
+WITH og
+OPTIONAL MATCH (og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(e:User)-[:REPOSTED]->(p:Post)
+WHERE coalesce(e.active, true)
+WITH DISTINCT og, p
+WITH og, p.likes AS n ORDER BY n
+WITH og, collect(n) AS ns
+WITH og, ns[CASE WHEN size(ns) > $target THEN size(ns) - $target ELSE 0 END] AS n
+SET og.reposted_threshold = CASE WHEN n < 1 THEN 1 ELSE n END
+
+// Quoted by the 2nd degree, for GET_BEST_2ND_DEG_QUOTES
+WITH og
+OPTIONAL MATCH (og)-[:FOLLOWS]->(:User)-[:FOLLOWS]->(e:User)-[:QUOTED]->(p:Post)
+WHERE coalesce(e.active, true)
+WITH DISTINCT og, p
+WITH og, p.quotes AS n ORDER BY n
+WITH og, collect(n) AS ns
+WITH og, ns[CASE WHEN size(ns) > $target THEN size(ns) - $target ELSE 0 END] AS n
+SET og.quote_threshold = CASE WHEN n < 1 THEN 1 ELSE n END
+
+SET og.thresholds_at = timestamp()
 "#;
 
 pub(crate) const POKE: &str = r#"
 MATCH (og:User {did: $did})
 SET og.last_seen = timestamp()
+SET og.last_requested = timestamp()
 SET og.feed_user = true
 "#;
+
+/// Onboarding crawl jobs live on the viewer's User node, so they survive restarts.
+/// Only new viewers are queued, unless `$force`d by an admin, and never while a job is already pending
+pub(crate) const ENQUEUE_CRAWL: &str = r#"
+MERGE (u:User {did: $did})
+WITH u
+WHERE u.crawl_state IS NULL OR ($force AND u.crawl_state IN ["done", "failed"])
+SET u.crawl_state = "queued"
+SET u.crawl_attempts = 0
+SET u.crawl_total = 0
+SET u.crawl_done = 0
+SET u.crawl_last = null
+SET u.crawl_error = null
+SET u.crawl_updated = timestamp()
+RETURN u.did AS did
+"#;
+
+pub(crate) const START_CRAWL: &str = r#"
+MATCH (u:User {did: $did})
+SET u.crawl_state = "running"
+SET u.crawl_attempts = coalesce(u.crawl_attempts, 0) + 1
+SET u.crawl_updated = timestamp()
+RETURN u.did AS did, u.crawl_state AS state, coalesce(u.crawl_total, 0) AS total, coalesce(u.crawl_done, 0) AS done, u.crawl_last AS last, u.crawl_attempts AS attempts, u.crawl_error AS error, u.crawl_updated AS updated_at
+"#;
+
+pub(crate) const CRAWL_PROGRESS: &str = r#"
+MATCH (u:User {did: $did})
+SET u.crawl_total = $total
+SET u.crawl_done = $done
+SET u.crawl_last = $last
+SET u.crawl_updated = timestamp()
+"#;
+
+/// `$state` is "done", "failed", or "queued" again if it is going to be retried
+pub(crate) const END_CRAWL: &str = r#"
+MATCH (u:User {did: $did})
+SET u.crawl_state = $state
+SET u.crawl_error = CASE WHEN $error = "" THEN null ELSE $error END
+SET u.crawl_updated = timestamp()
+SET u.synced_at = CASE WHEN $state = "done" THEN timestamp() ELSE u.synced_at END
+"#;
+
+pub(crate) const GET_CRAWL: &str = r#"
+MATCH (u:User {did: $did})
+WHERE u.crawl_state IS NOT NULL
+RETURN u.did AS did, u.crawl_state AS state, coalesce(u.crawl_total, 0) AS total, coalesce(u.crawl_done, 0) AS done, u.crawl_last AS last, coalesce(u.crawl_attempts, 0) AS attempts, u.crawl_error AS error, u.crawl_updated AS updated_at
+"#;
+
+/// Jobs that were queued or mid-crawl when we last stopped
+pub(crate) const GET_PENDING_CRAWLS: &str = r#"
+MATCH (u:User)
+WHERE u.crawl_state IN ["queued", "running"]
+RETURN u.did AS did ORDER BY u.crawl_updated
+"#;
+
+/// Viewers who asked for a feed since `$active_since` & whose follows & blocks were last synced before
+/// `$stale_before` (both unix micros, like `timestamp()`), longest out of date first
+pub(crate) const GET_STALE_VIEWERS: &str = r#"
+MATCH (u:User)
+WHERE u.last_requested >= $active_since
+AND u.crawl_state = "done"
+AND coalesce(u.synced_at, 0) < $stale_before
+RETURN u.did AS did ORDER BY coalesce(u.synced_at, 0) LIMIT $limit
+"#;
+
+pub(crate) const GET_FOLLOW_EDGES: &str = r#"
+MATCH (:User {did: $did})-[r:FOLLOWS]->(v:User)
+RETURN v.did AS subject, r.rkey AS rkey
+"#;
+
+pub(crate) const GET_BLOCK_EDGES: &str = r#"
+MATCH (:User {did: $did})-[r:BLOCKED]->(v:User)
+RETURN v.did AS subject, r.rkey AS rkey
+"#;
+
+pub(crate) const MARK_SYNCED: &str = r#"
+MATCH (u:User {did: $did})
+SET u.synced_at = timestamp()
+"#;
+
+/// When each of `$dids` last had its own follows crawled, if ever
+pub(crate) const GET_FOLLOWS_CRAWLED: &str = r#"
+UNWIND $dids AS did
+MATCH (u:User {did: did})
+WHERE u.follows_crawled_at IS NOT NULL
+RETURN u.did AS did, u.follows_crawled_at AS crawled_at
+"#;
+
+pub(crate) const MARK_FOLLOWS_CRAWLED: &str = r#"
+UNWIND $dids AS did
+MERGE (u:User {did: did})
+SET u.follows_crawled_at = timestamp()
+"#;
@@ -0,0 +1,417 @@
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use neo4rs::Graph;
+use tracing::{info, warn};
+
+use crate::common::stats::{IngestStats, PurgeImpact, rate};
+use crate::graph::{dialect::Dialect, queries};
+use crate::server::listen::now;
+
+/// What used to be hard coded: posts go after 2 hours, users after 4 hours unseen
+const DEFAULT_RULES: &str = "Post=7200,User=14400";
+/// How many batches in a row can be put off for drift, before one goes regardless so the graph still shrinks
+const MAX_THROTTLED: u32 = 20;
+
+/// Everything a retention rule can expire, by the label or edge type it has in the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Posts,
+    Users,
+    Likes,
+    Reposts,
+    Replies,
+    Quotes,
+    Follows,
+    Blocks,
+}
+
+impl Target {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "Post" => Some(Self::Posts),
+            "User" => Some(Self::Users),
+            "LIKES" => Some(Self::Likes),
+            "REPOSTED" => Some(Self::Reposts),
+            "REPLIED_TO" => Some(Self::Replies),
+            "QUOTED" => Some(Self::Quotes),
+            "FOLLOWS" => Some(Self::Follows),
+            "BLOCKED" => Some(Self::Blocks),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Posts => "Post",
+            Self::Users => "User",
+            Self::Likes => "LIKES",
+            Self::Reposts => "REPOSTED",
+            Self::Replies => "REPLIED_TO",
+            Self::Quotes => "QUOTED",
+            Self::Follows => "FOLLOWS",
+            Self::Blocks => "BLOCKED",
+        }
+    }
+
+    pub fn is_edge(&self) -> bool {
+        !matches!(self, Self::Posts | Self::Users)
+    }
+
+    /// Matches everything older than `$before` as `n`. Protected, that leaves out feed users & who they follow,
+    /// & any edge either of them made, as a follow's own follows are a feed's 2nd degree. Edges are aged by
+    /// `created_at`, so ones from before it was set never expire
+    pub fn matcher(&self, protect: bool) -> String {
+        let (pattern, age, guard) = match self {
+            Self::Posts => ("(n:Post)", "n.timestamp", ""),
+            Self::Users => (
+                "(n:User)",
+                "n.last_seen",
+                " AND NOT coalesce(n.feed_user, false) AND NOT exists((:User {feed_user: true})-[:FOLLOWS]->(n))",
+            ),
+            _ => (
+                "",
+                "n.created_at",
+                " AND NOT coalesce(a.feed_user, false) AND NOT exists((:User {feed_user: true})-[:FOLLOWS]->(a))",
+            ),
+        };
+        let pattern = match self.is_edge() {
+            true => format!("(a)-[n:{}]->()", self.name()),
+            false => pattern.to_owned(),
+        };
+        let guard = match protect {
+            true => guard,
+            false => "",
+        };
+        format!("MATCH {pattern} WHERE {age} < $before{guard}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub target: Target,
+    pub ttl: Duration,
+}
+
+/// `<label or edge type>=<ttl secs>`, comma separated, e.g. `Post=7200,User=14400,LIKES=3600`.
+/// Anything unparseable is left out, & a later rule for the same target replaces an earlier one
+pub fn parse_rules(spec: &str) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let rule = match entry.split_once('=') {
+            Some((name, secs)) => match (Target::parse(name.trim()), secs.trim().parse()) {
+                (Some(target), Ok(secs)) => Rule {
+                    target,
+                    ttl: Duration::from_secs(secs),
+                },
+                _ => {
+                    warn!("Ignoring retention rule {}", entry);
+                    continue;
+                }
+            },
+            None => {
+                warn!("Ignoring retention rule {}", entry);
+                continue;
+            }
+        };
+        rules.retain(|r| r.target != rule.target);
+        rules.push(rule);
+    }
+    rules
+}
+
+/// What to expire & how often, whichever graph it is expired from
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+    pub protect: bool,
+    pub dry_run: bool,
+    pub interval: Duration,
+}
+
+impl Policy {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules,
+            protect: true,
+            dry_run: false,
+            interval: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// `RETENTION_RULES` (see `parse_rules`, default `Post=7200,User=14400`), `RETENTION_PROTECT_VIEWERS`
+    /// (default true), `RETENTION_DRY_RUN` (default false) & `RETENTION_INTERVAL_SECS` (default 300)
+    pub fn from_env() -> Self {
+        let var = |key: &str| env::var(key).ok();
+        let flag = |key: &str, default: bool| match var(key).as_deref() {
+            Some("true") | Some("1") | Some("y") => true,
+            Some("false") | Some("0") | Some("n") => false,
+            _ => default,
+        };
+        Self {
+            protect: flag("RETENTION_PROTECT_VIEWERS", true),
+            dry_run: flag("RETENTION_DRY_RUN", false),
+            interval: Duration::from_secs(
+                var("RETENTION_INTERVAL_SECS")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(5 * 60),
+            ),
+            ..Self::new(parse_rules(
+                &var("RETENTION_RULES").unwrap_or(DEFAULT_RULES.to_owned()),
+            ))
+        }
+    }
+}
+
+/// Expires whatever the rules say is too old, a small batch at a time with a pause in between, alongside
+/// ingestion rather than locking it out. Batches wait while ingestion is more than `max_drift` behind.
+/// A dry run only counts & logs what would go
+pub struct Retention {
+    conn: Graph,
+    dialect: Dialect,
+    stats: Arc<IngestStats>,
+    policy: Policy,
+    batch: usize,
+    pause: Duration,
+    max_drift_ms: i64,
+}
+
+/// Running totals for a pass
+#[derive(Default)]
+struct Progress {
+    batches: u64,
+    throttled: u64,
+    drift_samples: Vec<i64>,
+}
+
+impl Retention {
+    pub fn new(conn: Graph, dialect: Dialect, stats: Arc<IngestStats>, policy: Policy) -> Self {
+        Self {
+            conn,
+            dialect,
+            stats,
+            policy,
+            batch: 1000,
+            pause: Duration::from_millis(250),
+            max_drift_ms: 2000,
+        }
+    }
+
+    /// `Policy::from_env`, plus `RETENTION_BATCH` (default 1000), `RETENTION_BATCH_PAUSE_MS` (default 250) &
+    /// `RETENTION_MAX_DRIFT_MS` (default 2000)
+    pub fn from_env(conn: Graph, dialect: Dialect, stats: Arc<IngestStats>) -> Self {
+        let num = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            batch: num("RETENTION_BATCH", 1000).max(1) as usize,
+            pause: Duration::from_millis(num("RETENTION_BATCH_PAUSE_MS", 250)),
+            max_drift_ms: num("RETENTION_MAX_DRIFT_MS", 2000) as i64,
+            ..Self::new(conn, dialect, stats, Policy::from_env())
+        }
+    }
+
+    pub async fn run(self) {
+        if self.policy.rules.is_empty() {
+            info!("No retention rules, nothing will be purged");
+            return;
+        }
+        let mut since = Instant::now();
+        let mut events_since = self.stats.events();
+        loop {
+            tokio::time::sleep(self.policy.interval).await;
+            let idle_ms = since.elapsed().as_millis() as u64;
+            let idle_events = self.stats.events().saturating_sub(events_since);
+            let drift_before_ms = self.stats.drift_ms();
+
+            info!("Purging");
+            let started_at = now();
+            let started = Instant::now();
+            let events_before = self.stats.events();
+            let mut progress = Progress::default();
+            let report = self.pass(&mut progress).await;
+            let took_ms = started.elapsed().as_millis() as u64;
+
+            let impact = PurgeImpact {
+                started_at,
+                took_ms,
+                dry_run: self.policy.dry_run,
+                deleted: report
+                    .iter()
+                    .map(|(target, n)| (target.name().to_owned(), *n))
+                    .collect(),
+                batches: progress.batches,
+                throttled: progress.throttled,
+                drift_before_ms,
+                drift_during_ms: match progress.drift_samples.len() {
+                    0 => self.stats.drift_ms(),
+                    n => progress.drift_samples.iter().sum::<i64>() / n as i64,
+                },
+                events_per_sec_before: rate(idle_events, idle_ms),
+                events_per_sec_during: rate(
+                    self.stats.events().saturating_sub(events_before),
+                    took_ms,
+                ),
+            };
+            let summary: Vec<String> = report
+                .iter()
+                .map(|(target, n)| format!("{} {}", n, target.name()))
+                .collect();
+            match self.policy.dry_run {
+                true => info!("Retention dry run, would delete {}", summary.join(", ")),
+                false => info!(
+                    "Done! Deleted {} in {}ms over {} batches ({} put off), drift {}ms -> {}ms, {:.0} -> {:.0} events/s",
+                    summary.join(", "),
+                    took_ms,
+                    impact.batches,
+                    impact.throttled,
+                    impact.drift_before_ms,
+                    impact.drift_during_ms,
+                    impact.events_per_sec_before,
+                    impact.events_per_sec_during
+                ),
+            }
+            self.stats.set_purge(impact);
+
+            since = Instant::now();
+            events_since = self.stats.events();
+        }
+    }
+
+    /// How many of each target went, or would have
+    async fn pass(&self, progress: &mut Progress) -> Vec<(Target, u64)> {
+        let mut report = Vec::new();
+        for rule in &self.policy.rules {
+            // Graph timestamps are micros, same as `now`
+            let before = now() as i64 - rule.ttl.as_micros() as i64;
+            let res = match self.policy.dry_run {
+                true => self.count(rule, before).await,
+                false => self.expire(rule, before, progress).await,
+            };
+            match res {
+                Ok(n) => report.push((rule.target, n)),
+                Err((n, e)) => {
+                    warn!("Error purging {}: {}", rule.target.name(), e);
+                    report.push((rule.target, n));
+                }
+            }
+        }
+        report
+    }
+
+    async fn count(&self, rule: &Rule, before: i64) -> Result<u64, (u64, neo4rs::Error)> {
+        let qry =
+            queries::RETENTION_COUNT.replace("{target}", &rule.target.matcher(self.policy.protect));
+        self.run_counted(self.dialect.query(&qry).param("before", before))
+            .await
+            .map_err(|e| (0, e))
+    }
+
+    /// Deletes a batch at a time, until one comes back short. Errors carry how many went before it
+    async fn expire(
+        &self,
+        rule: &Rule,
+        before: i64,
+        progress: &mut Progress,
+    ) -> Result<u64, (u64, neo4rs::Error)> {
+        let template = match rule.target.is_edge() {
+            true => queries::RETENTION_DELETE_EDGES,
+            false => queries::RETENTION_DELETE_NODES,
+        };
+        let qry = template.replace("{target}", &rule.target.matcher(self.policy.protect));
+
+        let mut deleted = 0;
+        loop {
+            self.wait_for_ingest(progress).await;
+            let res = self
+                .run_counted(
+                    self.dialect
+                        .query(&qry)
+                        .param("before", before)
+                        .param("batch", self.batch as i64),
+                )
+                .await;
+            progress.batches += 1;
+            match res {
+                Ok(n) => {
+                    deleted += n;
+                    if n < self.batch as u64 {
+                        return Ok(deleted);
+                    }
+                }
+                Err(e) => return Err((deleted, e)),
+            }
+            tokio::time::sleep(self.pause).await;
+        }
+    }
+
+    /// Converts posts left with string properties, see `MIGRATE_POST_PROPERTIES`, in batches like a purge
+    pub async fn migrate(&self) {
+        let mut progress = Progress::default();
+        let mut converted = 0;
+        loop {
+            self.wait_for_ingest(&mut progress).await;
+            let res = self
+                .run_counted(
+                    self.dialect
+                        .query(queries::MIGRATE_POST_PROPERTIES)
+                        .param("batch", self.batch as i64),
+                )
+                .await;
+            match res {
+                Ok(n) => {
+                    converted += n;
+                    if n < self.batch as u64 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    // Feeds skip posts until they are converted, so keep at it rather than leave them hidden
+                    warn!(
+                        "Unable to convert old string post properties, retrying: {}",
+                        e
+                    );
+                    tokio::time::sleep(self.policy.interval).await;
+                    continue;
+                }
+            }
+            tokio::time::sleep(self.pause).await;
+        }
+        if converted > 0 {
+            info!("Converted {} posts off string properties", converted);
+        }
+    }
+
+    /// Holds off while ingestion is behind, so a purge never makes drift worse than it already is
+    async fn wait_for_ingest(&self, progress: &mut Progress) {
+        for _ in 0..MAX_THROTTLED {
+            let drift = self.stats.drift_ms();
+            progress.drift_samples.push(drift);
+            if !throttle(drift, self.max_drift_ms) {
+                return;
+            }
+            progress.throttled += 1;
+            tokio::time::sleep(self.pause * 4).await;
+        }
+    }
+
+    async fn run_counted(&self, qry: neo4rs::Query) -> Result<u64, neo4rs::Error> {
+        let mut res = self.conn.execute(qry).await?;
+        let mut n = 0;
+        while let Some(row) = res.next().await? {
+            n += row.get::<i64>("n").unwrap_or_default().max(0) as u64;
+        }
+        Ok(n)
+    }
+}
+
+/// 0 turns throttling off
+pub fn throttle(drift_ms: i64, max_drift_ms: i64) -> bool {
+    max_drift_ms > 0 && drift_ms > max_drift_ms
+}
@@ -1,9 +1,10 @@
-use at_event_processor::MaybeSemaphore;
+use at_event_processor::{ATEventProcessor, MaybeSemaphore};
+use backfill::BackfillMessage;
 use bsky::types::ATEventType;
-use common::FetchMessage;
+use common::{FetchMessage, RequestChannels, stats::IngestStats};
 use filter::FilterList;
 use pprof::protos::Message;
-use processor::MemgraphWrapper;
+use processor::{MemgraphWrapper, memory::MemoryWrapper};
 use simple_moving_average::{SMA, SumTreeSMA};
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
@@ -15,6 +16,7 @@ use tracing::{error, info, warn};
 use tracing_subscriber;
 
 mod at_event_processor;
+mod backfill;
 pub mod bsky;
 pub mod common;
 mod event_database;
@@ -22,6 +24,7 @@ mod filter;
 mod forward_server;
 pub mod graph;
 mod processor;
+mod ranking;
 mod server;
 mod ws;
 
@@ -69,7 +72,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let pw = env::var("MM_PW").unwrap_or("pass".into());
     //
     let lock = Arc::new(RwLock::new(()));
+    let stats = Arc::new(IngestStats::default());
     let (send_channel, recieve_channel) = mpsc::channel::<FetchMessage>(100);
+    let (backfill_send, backfill_recieve) = mpsc::channel::<BackfillMessage>(100);
     // If env says we need to forward DB requests, just do that & nothing else
     if !forward_mode.is_empty() {
         info!("Starting forward web server");
@@ -78,6 +83,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     } else {
         // Otherwise, spin this off to accept incoming requests (feed serving atm, will likely just be DB reads)
+        let web_stats = stats.clone();
         thread::spawn(move || {
             let web_runtime = tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
@@ -87,7 +93,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             info!("Starting web listener thread");
             let wait = web_runtime.spawn(async move {
-                server::serve(send_channel).await.unwrap();
+                server::serve(send_channel, backfill_send, web_stats)
+                    .await
+                    .unwrap();
             });
             web_runtime.block_on(wait).unwrap();
             info!("Exiting web listener thread");
@@ -112,23 +120,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     filters.insert(ATEventType::Global, global_filters);
     //
 
+    let requests = RequestChannels {
+        feed: recieve_channel,
+        backfill: backfill_recieve,
+    };
+    // Everything in process instead, for running without Memgraph
+    if env::var("GRAPH_BACKEND").unwrap_or_default() == "memory" {
+        info!("Using the in memory graph");
+        let graph = MemoryWrapper::serve(requests, filters);
+        graph.start_retention(stats.clone());
+        return ingest(graph, lock, stats, !compression.is_empty()).await;
+    }
+
     info!("Connecting to memgraph");
-    let mut graph = MemgraphWrapper::new(
+    let graph = MemgraphWrapper::new(
         "bolt://localhost:7687",
         "bolt://localhost:7688",
         &user,
         &pw,
-        recieve_channel,
+        requests,
         lock.clone(),
         filters,
     )
     .await
     .unwrap();
+    graph.start_retention(stats.clone());
     info!("Connected to memgraph");
 
+    ingest(graph, lock, stats, !compression.is_empty()).await
+}
+
+/// Feeds the firehose into `graph` until something goes badly wrong
+async fn ingest(
+    mut graph: impl ATEventProcessor,
+    lock: Arc<RwLock<()>>,
+    stats: Arc<IngestStats>,
+    compressed: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Connect to the websocket
     info!("Connecting to Bluesky firehose");
-    let compressed = !compression.is_empty();
     let url = format!(
         "wss://jetstream1.us-east.bsky.network/subscribe?wantedCollections=app.bsky.graph.*&wantedCollections=app.bsky.feed.*&compress={}",
         compressed
@@ -180,7 +210,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 m
             }
         } {
-            // TODO - Write test EventDatabase impl to check params are being processed properly, then chain w/ test ATEventProcessor
             Ok(msg) => {
                 match msg.opcode {
                     fastwebsockets::OpCode::Binary | fastwebsockets::OpCode::Text => {
@@ -223,6 +252,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             info!("Reconnected to Bluesky jetstream2");
                                         }
                                         ctr.lock().await.add_sample(drift);
+                                        stats.record(drift);
                                         last_time = SystemTime::now();
                                         if recv_chan.is_some() {
                                             recv = recv_chan;
@@ -0,0 +1,509 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use tokio::sync::{RwLock, mpsc};
+use tracing::{info, warn};
+
+use super::pending::{Engagement, PendingEngagement};
+use crate::at_event_processor::{ATEventProcessor, MaybeSemaphore};
+use crate::backfill::crawl_log::CrawlLog;
+use crate::backfill::{
+    BackfillMessage, CRAWL_BATCH, JobState, JobStatus, THRESHOLD_TARGET, crawl_follows_of,
+};
+use crate::bsky::types::ATEventType;
+use crate::bsky::uri::AtUri;
+use crate::bsky::{did::DidResolver, get_blocks, get_follows, xrpc::XrpcClient};
+use crate::common::RequestChannels;
+use crate::common::stats::{IngestStats, PurgeImpact};
+use crate::event_database::{Param, Params};
+use crate::filter::{Filter, FilterList};
+use crate::graph::memory::MemoryGraph;
+use crate::graph::retention::{Policy, Target};
+use crate::server;
+use crate::server::listen::{RequestPool, now};
+use crate::server::prewarm::Prewarm;
+use crate::server::snapshot::SnapshotCache;
+
+/// Ingests straight into a `MemoryGraph`, so there is nothing to queue or commit & the semaphore is only ever
+/// passed back. Engagement that beats its post is still held for it, like `MemgraphWrapper` does
+pub struct MemoryWrapper {
+    graph: MemoryGraph,
+    /// Every post is its own commit
+    written: u64,
+    pending: PendingEngagement,
+    snapshots: Arc<SnapshotCache>,
+
+    filters: HashMap<ATEventType, VecDeque<Box<dyn Filter + Send>>>,
+}
+
+impl MemoryWrapper {
+    pub fn new(
+        graph: MemoryGraph,
+        snapshots: Arc<SnapshotCache>,
+        filters: HashMap<ATEventType, FilterList>,
+    ) -> Self {
+        Self {
+            graph,
+            written: 0,
+            pending: PendingEngagement::from_env(),
+            snapshots,
+            filters,
+        }
+    }
+
+    /// Serves feeds off a fresh `MemoryGraph`, as `MemgraphWrapper::new` does off Memgraph. Viewers are onboarded
+    /// by a `Seeder` rather than a `Backfill`
+    pub fn serve(requests: RequestChannels, filters: HashMap<ATEventType, FilterList>) -> Self {
+        let graph = MemoryGraph::default();
+        let snapshots = Arc::new(SnapshotCache::from_env());
+        let snapshots_prune = snapshots.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                snapshots_prune.prune();
+            }
+        });
+
+        let client = Arc::new(XrpcClient::from_env());
+        let resolver = Arc::new(DidResolver::from_env(client.clone()));
+        let seeder = Seeder::new(graph.clone(), snapshots.clone(), client, resolver);
+        tokio::spawn(seeder.run(requests.backfill));
+
+        let prewarm = Arc::new(Prewarm::from_env());
+        tokio::spawn(prewarm.clone().run(graph.clone(), snapshots.clone()));
+
+        let (writer, fetcher, snapshots_listen) = (graph.clone(), graph.clone(), snapshots.clone());
+        tokio::spawn(async move {
+            match server::listen::listen_for_requests(
+                writer,
+                fetcher,
+                snapshots_listen,
+                prewarm,
+                RequestPool::from_env(),
+                requests.feed,
+            )
+            .await
+            {
+                Ok(_) => {}
+                Err(e) => panic!("Error listening for requests, aborting: {}", e),
+            };
+        });
+
+        Self::new(graph, snapshots, filters)
+    }
+
+    /// Purges in one go per pass rather than in batches. Ingestion waits on the graph while it runs, but with no
+    /// round trips to make a pass is quick
+    pub fn start_retention(&self, stats: Arc<IngestStats>) {
+        let policy = Policy::from_env();
+        let graph = self.graph.clone();
+        tokio::spawn(async move {
+            if policy.rules.is_empty() {
+                info!("No retention rules, nothing will be purged");
+                return;
+            }
+            loop {
+                tokio::time::sleep(policy.interval).await;
+                let started_at = now();
+                let started = Instant::now();
+                let drift_before_ms = stats.drift_ms();
+                let report = graph.purge(&policy.rules, policy.protect, policy.dry_run);
+                let impact = PurgeImpact {
+                    started_at,
+                    took_ms: started.elapsed().as_millis() as u64,
+                    dry_run: policy.dry_run,
+                    deleted: report
+                        .iter()
+                        .map(|(target, n)| (target.name().to_owned(), *n))
+                        .collect(),
+                    batches: 1,
+                    drift_before_ms,
+                    drift_during_ms: stats.drift_ms(),
+                    ..Default::default()
+                };
+                info!(
+                    "Purged in memory graph in {}ms{}: {:?}",
+                    impact.took_ms,
+                    match policy.dry_run {
+                        true => " (dry run)",
+                        false => "",
+                    },
+                    impact.deleted
+                );
+                stats.set_purge(impact);
+            }
+        });
+    }
+
+    fn write_engagement(&self, kind: Engagement, params: &Params) {
+        let field = |key: &str| match params.get(key) {
+            Some(Param::Str(s)) => s.as_str(),
+            _ => "",
+        };
+        let (target, subject) = match kind {
+            Engagement::Like => (Target::Likes, field("subject")),
+            Engagement::Repost => (Target::Reposts, field("subject")),
+            Engagement::Quote => (Target::Quotes, field("subject")),
+            Engagement::Reply => (Target::Replies, field("parent")),
+        };
+        self.graph
+            .add_edge(target, field("did"), subject, field("rkey"));
+    }
+
+    fn release_pending(&mut self) {
+        for (kind, params) in self.pending.release(self.written) {
+            self.write_engagement(kind, &params);
+        }
+    }
+
+    /// Writes it now, unless its post hasnt turned up yet
+    fn engage(&mut self, kind: Engagement, subject: &str, params: Params) {
+        self.release_pending();
+        if let Some(params) = self.pending.hold(kind, subject, params, now()) {
+            self.write_engagement(kind, &params);
+        }
+    }
+}
+
+/// Stands in for `Backfill` on the in memory graph, crawling the viewer's 2nd degree the same way. Jobs & the
+/// crawl log only last as long as the process does
+#[derive(Clone)]
+pub struct Seeder {
+    graph: MemoryGraph,
+    snapshots: Arc<SnapshotCache>,
+    client: Arc<XrpcClient>,
+    resolver: Arc<DidResolver>,
+    jobs: Arc<DashMap<String, JobStatus>>,
+    crawl_log: Arc<CrawlLog>,
+    write_lock: Arc<RwLock<()>>,
+}
+
+impl Seeder {
+    pub fn new(
+        graph: MemoryGraph,
+        snapshots: Arc<SnapshotCache>,
+        client: Arc<XrpcClient>,
+        resolver: Arc<DidResolver>,
+    ) -> Self {
+        Self {
+            graph,
+            snapshots,
+            client,
+            resolver,
+            jobs: Arc::new(DashMap::new()),
+            crawl_log: Arc::new(CrawlLog::in_memory_from_env()),
+            write_lock: Arc::new(RwLock::new(())),
+        }
+    }
+
+    pub async fn run(self, mut recv: mpsc::Receiver<BackfillMessage>) {
+        while let Some(msg) = recv.recv().await {
+            match msg {
+                BackfillMessage::Enqueue { did } => {
+                    self.enqueue(&did, false);
+                }
+                BackfillMessage::Requeue { did, resp } => {
+                    let _ = resp.send(self.enqueue(&did, true)).await;
+                }
+                BackfillMessage::Status { did, resp } => {
+                    let status = self.jobs.get(&did).map(|j| j.clone());
+                    let _ = resp.send(status).await;
+                }
+            }
+        }
+    }
+
+    /// Like `ENQUEUE_CRAWL`, false if the viewer already has a job, or when forced, one that is still going
+    fn enqueue(&self, did: &str, force: bool) -> bool {
+        let job = JobStatus {
+            did: did.to_owned(),
+            state: JobState::Queued,
+            follows_total: 0,
+            follows_done: 0,
+            follows_last: None,
+            attempts: 0,
+            error: None,
+            updated_at: now() as i64,
+        };
+        match self.jobs.entry(did.to_owned()) {
+            Entry::Occupied(mut e)
+                if force && matches!(e.get().state, JobState::Done | JobState::Failed) =>
+            {
+                e.insert(job);
+            }
+            Entry::Occupied(_) => return false,
+            Entry::Vacant(e) => {
+                e.insert(job);
+            }
+        };
+
+        let this = self.clone();
+        let did = did.to_owned();
+        tokio::spawn(async move { this.seed(&did).await });
+        true
+    }
+
+    async fn seed(&self, did: &str) {
+        self.update(did, |j| {
+            j.state = JobState::Running;
+            j.attempts += 1;
+        });
+        let blocks = match get_blocks(did.to_owned(), &self.client, &self.resolver).await {
+            Ok(b) => b,
+            Err((e, _)) => return self.failed(did, format!("blocks: {e}")),
+        };
+        let follows = match get_follows(did.to_owned(), &self.client, &self.resolver).await {
+            Ok(f) => f,
+            Err((e, _)) => return self.failed(did, format!("follows: {e}")),
+        };
+
+        seed_network(&self.graph, did, &blocks, &follows);
+        self.update(did, |j| j.follows_total = follows.len() as u64);
+
+        // Each follow's follows, as `Backfill::crawl` does
+        for batch in follows.chunks(CRAWL_BATCH) {
+            let dids: Vec<String> = batch.iter().map(|(f, _)| f.clone()).collect();
+            if let Some(e) = crawl_follows_of(
+                &dids,
+                &self.client,
+                &self.resolver,
+                &self.crawl_log,
+                self.graph.clone(),
+                self.write_lock.clone(),
+            )
+            .await
+            {
+                return self.failed(did, format!("2nd degree follows: {e}"));
+            }
+            self.update(did, |j| j.follows_done += batch.len() as u64);
+        }
+
+        self.graph.set_thresholds(did, THRESHOLD_TARGET as i64);
+        self.snapshots.invalidate_viewer(did);
+        info!(
+            "Seeded {} follows & {} blocks for {}",
+            follows.len(),
+            blocks.len(),
+            did
+        );
+        self.update(did, |j| j.state = JobState::Done);
+    }
+
+    fn failed(&self, did: &str, error: String) {
+        warn!("Unable to seed {}: {}", did, error);
+        self.update(did, |j| {
+            j.state = JobState::Failed;
+            j.error = Some(error);
+        });
+    }
+
+    fn update(&self, did: &str, f: impl FnOnce(&mut JobStatus)) {
+        if let Some(mut job) = self.jobs.get_mut(did) {
+            f(&mut job);
+            job.updated_at = now() as i64;
+        }
+    }
+}
+
+/// The viewer's own blocks & follows, each written like `POPULATE_BLOCK` & `POPULATE_FOLLOW`
+pub fn seed_network(
+    graph: &MemoryGraph,
+    did: &str,
+    blocks: &[(String, String)],
+    follows: &[(String, String)],
+) {
+    for (blockee, rkey) in blocks {
+        graph.populate(Target::Blocks, did, blockee, rkey);
+    }
+    for (out, rkey) in follows {
+        graph.populate(Target::Follows, did, out, rkey);
+    }
+}
+
+impl ATEventProcessor for MemoryWrapper {
+    async fn add_reply(
+        &mut self,
+        did: String,
+        rkey: String,
+        parent: String,
+        root: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        let params = HashMap::from([
+            ("did".to_owned(), did.into()),
+            ("rkey".to_owned(), rkey.into()),
+            ("parent".to_owned(), parent.as_str().into()),
+            ("root".to_owned(), root.into()),
+        ]);
+        self.engage(Engagement::Reply, &parent, params);
+        rec
+    }
+
+    async fn add_quote(
+        &mut self,
+        did: String,
+        rkey: String,
+        subject: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.engage(
+            Engagement::Quote,
+            &subject.clone(),
+            super::engagement(did, rkey, subject),
+        );
+        rec
+    }
+
+    async fn add_post(
+        &mut self,
+        did: String,
+        rkey: String,
+        timestamp: &i64,
+        parent_did: String,
+        _post_type: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        let uri = AtUri::post(&did, &rkey).to_string();
+        self.graph.add_post(&did, &uri, *timestamp, &parent_did);
+        self.pending.queued(uri, &rkey, now());
+        self.written += 1;
+        self.pending.flushed(self.written);
+        self.release_pending();
+        rec
+    }
+
+    async fn add_repost(
+        &mut self,
+        did: String,
+        subject: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.engage(
+            Engagement::Repost,
+            &subject.clone(),
+            super::engagement(did, rkey, subject),
+        );
+        rec
+    }
+
+    async fn add_follow(
+        &mut self,
+        did: String,
+        out: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.snapshots.invalidate_viewer(&did);
+        self.graph.add_edge(Target::Follows, &did, &out, &rkey);
+        rec
+    }
+
+    async fn add_like(
+        &mut self,
+        did: String,
+        subject: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.engage(
+            Engagement::Like,
+            &subject.clone(),
+            super::engagement(did, rkey, subject),
+        );
+        rec
+    }
+
+    async fn add_block(
+        &mut self,
+        blockee: String,
+        did: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.snapshots.invalidate_viewer(&did);
+        self.graph.add_edge(Target::Blocks, &did, &blockee, &rkey);
+        rec
+    }
+
+    async fn update_handle(
+        &mut self,
+        did: String,
+        _handle: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.graph.update_handle(&did);
+        rec
+    }
+
+    async fn set_account_status(
+        &mut self,
+        did: String,
+        active: bool,
+        _status: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.graph.set_account(&did, active);
+        rec
+    }
+
+    async fn rm_post(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        let uri = AtUri::post(&did, &rkey).to_string();
+        self.snapshots.forget_post(uri.clone());
+        self.graph.rm_post(&did, &uri);
+        rec
+    }
+
+    async fn rm_repost(
+        &mut self,
+        did: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.graph.remove_edge(Target::Reposts, &did, &rkey);
+        rec
+    }
+
+    async fn rm_follow(
+        &mut self,
+        did: String,
+        rkey: String,
+        rec: MaybeSemaphore,
+    ) -> MaybeSemaphore {
+        self.snapshots.invalidate_viewer(&did);
+        self.graph.remove_edge(Target::Follows, &did, &rkey);
+        rec
+    }
+
+    async fn rm_like(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        self.graph.remove_edge(Target::Likes, &did, &rkey);
+        rec
+    }
+
+    async fn rm_block(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        self.snapshots.invalidate_viewer(&did);
+        self.graph.remove_edge(Target::Blocks, &did, &rkey);
+        rec
+    }
+
+    async fn rm_reply(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        self.graph.remove_edge(Target::Replies, &did, &rkey);
+        rec
+    }
+
+    async fn rm_quote(&mut self, did: String, rkey: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        self.graph.remove_edge(Target::Quotes, &did, &rkey);
+        rec
+    }
+
+    async fn rm_account(&mut self, did: String, rec: MaybeSemaphore) -> MaybeSemaphore {
+        self.graph.rm_account(&did);
+        rec
+    }
+
+    fn get_filters(&self) -> &HashMap<ATEventType, VecDeque<Box<dyn Filter + Send>>> {
+        &self.filters
+    }
+}
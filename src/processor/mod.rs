@@ -1,9 +1,17 @@
 use crate::at_event_processor::ATEventProcessor;
+use crate::backfill::{Backfill, crawl_log::CrawlLog, reconcile::Reconciler, store::JobStore};
 use crate::bsky::types::ATEventType;
-use crate::common::FetchMessage;
+use crate::bsky::uri::AtUri;
+use crate::bsky::{did::DidResolver, xrpc::XrpcClient};
+use crate::common::RequestChannels;
+use crate::common::stats::IngestStats;
+use crate::event_database::{Param, Params};
 use crate::filter::Filter;
 use crate::filter::FilterList;
 use crate::server;
+use crate::server::listen::{RequestPool, now};
+use crate::server::prewarm::Prewarm;
+use crate::server::snapshot::SnapshotCache;
 use backoff::ExponentialBackoffBuilder;
 use backoff::future::retry;
 use dashmap::DashMap;
@@ -12,13 +20,22 @@ use std::collections::VecDeque;
 use std::env;
 use std::mem;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use std::{collections::HashMap, time::Instant};
 use tokio::sync::{RwLock, mpsc};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::graph::dialect::Dialect;
+use crate::graph::retention::Retention;
 use crate::graph::*;
+use pending::{Engagement, PendingEngagement};
+
+pub mod memory;
+mod pending;
+mod processor_test;
+
 const Q_LIMIT: usize = 55;
 
 const TX_Q_LEN: usize = 70;
@@ -27,17 +44,20 @@ macro_rules! queue_event_write {
     ($self:ident, $query_name:expr_2021, $recv:ident, $( $arg:ident ),+) => {{
         let queue_and_query = match $query_name {
             "reply" =>  (&mut $self.reply_queue,queries::ADD_REPLY),
+            "quote" =>  (&mut $self.quote_queue,queries::ADD_QUOTE),
             "post" =>   (&mut $self.post_queue,queries::ADD_POST),
             "repost" => (&mut $self.repost_queue,queries::ADD_REPOST),
             "follow" => (&mut $self.follow_queue,queries::ADD_FOLLOW),
             "block" =>  (&mut $self.block_queue, queries::ADD_BLOCK),
             "like" =>   (&mut $self.like_queue,queries::ADD_LIKE),
+            "handle" => (&mut $self.handle_queue,queries::UPDATE_HANDLE),
+            "account" => (&mut $self.account_queue,queries::UPDATE_ACCOUNT),
             _ => panic!("unknown query name")
         };
         // HashMap-ify the input params w/ the same name as defined in Ruat
-        let mut params = HashMap::<String, String>::new();
+        let mut params = Params::new();
         $(
-            params.insert(stringify!($arg).to_string(), $arg);
+            params.insert(stringify!($arg).to_string(), Param::from($arg));
         )*
         queue_and_query.0.push(params);
         // Check if the queue is full
@@ -58,17 +78,19 @@ macro_rules! queue_event_remove {
     ($query_name:expr_2021,$recv:ident, $self:ident, $( $arg:ident ),+) => {{
         let queue_and_query = match $query_name {
             "reply" =>  (&mut $self.rm_reply_queue,queries::REMOVE_REPLY),
+            "quote" =>  (&mut $self.rm_quote_queue,queries::REMOVE_QUOTE),
             "post" =>   (&mut $self.rm_post_queue,queries::REMOVE_POST),
             "repost" => (&mut $self.rm_repost_queue,queries::REMOVE_REPOST),
             "follow" => (&mut $self.rm_follow_queue,queries::REMOVE_FOLLOW),
             "block" =>  (&mut $self.rm_block_queue, queries::REMOVE_BLOCK),
             "like" =>   (&mut $self.rm_like_queue,queries::REMOVE_LIKE),
+            "account" => (&mut $self.rm_account_queue,queries::REMOVE_ACCOUNT),
             _ => panic!("unknown query name")
         };
         // Helper to build the argument map with variable names as keys
-        let mut params = HashMap::new();
+        let mut params = Params::new();
         $(
-            params.insert(stringify!($arg).to_string(), $arg);
+            params.insert(stringify!($arg).to_string(), Param::from($arg));
         )*
 
         queue_and_query.0.push(params);
@@ -86,21 +108,32 @@ macro_rules! queue_event_remove {
 
 pub struct MemgraphWrapper {
     inner: Graph,
-    like_queue: Vec<HashMap<String, String>>,
-    post_queue: Vec<HashMap<String, String>>,
-    reply_queue: Vec<HashMap<String, String>>,
-    repost_queue: Vec<HashMap<String, String>>,
-    follow_queue: Vec<HashMap<String, String>>,
-    block_queue: Vec<HashMap<String, String>>,
-
-    rm_like_queue: Vec<HashMap<String, String>>,
-    rm_post_queue: Vec<HashMap<String, String>>,
-    rm_reply_queue: Vec<HashMap<String, String>>,
-    rm_repost_queue: Vec<HashMap<String, String>>,
-    rm_follow_queue: Vec<HashMap<String, String>>,
-    rm_block_queue: Vec<HashMap<String, String>>,
-
+    like_queue: Vec<Params>,
+    post_queue: Vec<Params>,
+    reply_queue: Vec<Params>,
+    quote_queue: Vec<Params>,
+    repost_queue: Vec<Params>,
+    follow_queue: Vec<Params>,
+    block_queue: Vec<Params>,
+    handle_queue: Vec<Params>,
+    account_queue: Vec<Params>,
+
+    rm_like_queue: Vec<Params>,
+    rm_post_queue: Vec<Params>,
+    rm_reply_queue: Vec<Params>,
+    rm_quote_queue: Vec<Params>,
+    rm_repost_queue: Vec<Params>,
+    rm_follow_queue: Vec<Params>,
+    rm_block_queue: Vec<Params>,
+    rm_account_queue: Vec<Params>,
+
+    dialect: Dialect,
     tx_queue: Arc<DashMap<String, Query>>,
+    /// Commits spawned off the tx queue, & the newest to go through. They run one after the other
+    spawned: u64,
+    committed: Arc<AtomicU64>,
+    pending: PendingEngagement,
+    snapshots: Arc<SnapshotCache>,
 
     filters: HashMap<ATEventType, VecDeque<Box<dyn Filter + Send>>>,
 }
@@ -111,10 +144,11 @@ impl MemgraphWrapper {
         replica_uri: &str,
         user: &str,
         pass: &str,
-        recieve_channel: mpsc::Receiver<FetchMessage>,
+        requests: RequestChannels,
         lock: Arc<RwLock<()>>,
         filters: HashMap<ATEventType, FilterList>, //FilterList,
     ) -> Result<Self, neo4rs::Error> {
+        let dialect = Dialect::from_env();
         let replica = env::var("REPLICA").unwrap_or("".into());
         let mut replica_conn = None;
         if replica != "" {
@@ -124,22 +158,23 @@ impl MemgraphWrapper {
                 .fetch_size(8192)
                 .user(user)
                 .password(pass)
-                .db("memgraph")
+                .db(dialect.db())
                 .build()?;
             let replica_inner = Graph::connect(replica_cfg).await?;
-            match replica_inner
-                .run(neo4rs::query(
-                    "SET REPLICATION ROLE TO REPLICA WITH PORT 10000;",
-                ))
-                .await
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    warn!(
-                        "Unable to set replica, it has probably already been set: {}",
-                        e
-                    );
-                }
+            match dialect.replica_role(10000) {
+                Some(role) => match replica_inner.run(neo4rs::query(&role)).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!(
+                            "Unable to set replica, it has probably already been set: {}",
+                            e
+                        );
+                    }
+                },
+                None => info!(
+                    "{:?} replication is set up on the server, not by us",
+                    dialect
+                ),
             };
             replica_conn = Some(replica_inner);
             info!("Done, Connecting to main...");
@@ -150,26 +185,21 @@ impl MemgraphWrapper {
             .fetch_size(8192)
             .user(user)
             .password(pass)
-            .db("memgraph")
+            .db(dialect.db())
             .build()?;
         let inner = Graph::connect(config.clone()).await?;
-        inner
-            .run(neo4rs::query("CREATE INDEX ON :User(did)"))
-            .await?;
-        inner
-            .run(neo4rs::query("CREATE INDEX ON :Post(rkey)"))
-            .await?;
-
-        // Set off background job to do whatever cleaning we want
-        let conn_purge: Graph = inner.clone();
-
-        if replica != "" {
-            match inner
-                .run(neo4rs::query(
-                    "REGISTER REPLICA REP1 ASYNC TO \"172.18.0.3\";",
-                ))
-                .await
-            {
+        for (what, statement) in dialect.schema() {
+            if let Err(e) = inner.run(neo4rs::query(statement)).await {
+                warn!(
+                    "Unable to create {}, it has probably already been created: {}",
+                    what, e
+                );
+            }
+        }
+        if replica != ""
+            && let Some(register) = dialect.register_replica("REP1", "172.18.0.3")
+        {
+            match inner.run(neo4rs::query(&register)).await {
                 Ok(_) => {}
                 Err(e) => {
                     warn!(
@@ -184,23 +214,61 @@ impl MemgraphWrapper {
         // We also want a task to listen for first time user requests
         // As we want to fetch all followers & follows
         let write_conn = inner.clone();
-        let lclone = lock.clone();
-        tokio::spawn(async move {
-            match queries::kickoff_purge(lclone, conn_purge).await {
-                Ok(_) => {}
-                Err(e) => info!("Error purging old posts: {}", e),
-            };
-        });
         let replica = match replica_conn {
             Some(r) => r,
             None => inner.clone(),
         };
 
-        let write_conn = GraphFetcher::new(write_conn);
-        let replica = GraphFetcher::new(replica);
+        let snapshots = Arc::new(SnapshotCache::from_env());
+        let snapshots_prune = snapshots.clone();
         tokio::spawn(async move {
-            match server::listen::listen_for_requests(lock, write_conn, replica, recieve_channel)
-                .await
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                snapshots_prune.prune();
+            }
+        });
+
+        let write_conn = GraphFetcher::new(write_conn, dialect);
+        let replica = GraphFetcher::new(replica, dialect);
+        let client = Arc::new(XrpcClient::from_env());
+        let resolver = Arc::new(DidResolver::from_env(client.clone()));
+        let crawl_log = Arc::new(CrawlLog::from_env(inner.clone(), dialect));
+        let (backfill, retries) = Backfill::new(
+            JobStore::new(inner.clone(), dialect),
+            write_conn.clone(),
+            lock.clone(),
+            snapshots.clone(),
+            client.clone(),
+            resolver.clone(),
+            crawl_log.clone(),
+        );
+        tokio::spawn(Arc::new(backfill).run(requests.backfill, retries));
+
+        let reconciler = Reconciler::new(
+            JobStore::new(inner.clone(), dialect),
+            write_conn.clone(),
+            lock,
+            snapshots.clone(),
+            client,
+            resolver,
+            crawl_log,
+        );
+        tokio::spawn(Arc::new(reconciler).run());
+
+        let prewarm = Arc::new(Prewarm::from_env());
+        tokio::spawn(prewarm.clone().run(replica.clone(), snapshots.clone()));
+
+        let snapshots_listen = snapshots.clone();
+        tokio::spawn(async move {
+            match server::listen::listen_for_requests(
+                write_conn,
+                replica,
+                snapshots_listen,
+                prewarm,
+                RequestPool::from_env(),
+                requests.feed,
+            )
+            .await
             {
                 Ok(_) => {}
                 Err(e) => panic!("Error listening for requests, aborting: {}", e),
@@ -209,117 +277,202 @@ impl MemgraphWrapper {
 
         let res = Self {
             inner,
+            dialect,
             filters,
             tx_queue: Arc::new(DashMap::new()),
+            spawned: 0,
+            committed: Arc::new(AtomicU64::new(0)),
+            pending: PendingEngagement::from_env(),
+            snapshots,
             like_queue: Default::default(),
             post_queue: Default::default(),
             follow_queue: Default::default(),
             repost_queue: Default::default(),
             block_queue: Default::default(),
+            handle_queue: Default::default(),
+            account_queue: Default::default(),
             reply_queue: Default::default(),
+            quote_queue: Default::default(),
 
             rm_like_queue: Default::default(),
             rm_post_queue: Default::default(),
             rm_follow_queue: Default::default(),
             rm_repost_queue: Default::default(),
             rm_block_queue: Default::default(),
+            rm_account_queue: Default::default(),
             rm_reply_queue: Default::default(),
+            rm_quote_queue: Default::default(),
         };
 
         Ok(res)
     }
+    /// Set off background job to do whatever cleaning we want, alongside the ingest loop `stats` come from.
+    /// Old string post properties are converted first
+    pub fn start_retention(&self, stats: Arc<IngestStats>) {
+        let retention = Retention::from_env(self.inner.clone(), self.dialect, stats);
+        tokio::spawn(async move {
+            retention.migrate().await;
+            retention.run().await;
+        });
+    }
+
     async fn enqueue_query(
         &mut self,
         query_script: Option<&str>,
-        mut params: (&str, Vec<HashMap<String, String>>),
+        mut params: (&str, Vec<Params>),
         prev_recv: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
         let inner = self.inner.clone();
         let queue = self.tx_queue.clone();
-        if queue.len() > TX_Q_LEN {
-            let n = Instant::now();
-            let (send, recv) = mpsc::channel(1);
-            let id = format!("{:?}", &recv);
-
-            if prev_recv.is_some() {
-                prev_recv.unwrap().recv().await;
+        // We're using a Map instead of a set because something about DashSet didnt play nice
+        // Construct the query
+        let due = match query_script {
+            Some(s) => queue_query(
+                &queue,
+                self.dialect
+                    .query(s)
+                    .param(params.0, mem::take(&mut params.1)),
+            ),
+            None => {
+                error!("Expected a query script but none was provided");
+                return None;
             }
-            let name = (params.0.to_owned()).clone();
-
-            tokio::spawn(async move {
-                match retry(
-                    ExponentialBackoffBuilder::default()
-                        .with_initial_interval(Duration::from_millis(2))
-                        .with_max_elapsed_time(Some(Duration::from_millis(350)))
-                        .with_randomization_factor(0.35)
-                        .build(),
-                    || async {
-                        let q_vals: Vec<Query> = queue.iter().map(|v| v.value().clone()).collect();
-                        let mut tx = inner.start_txn().await.unwrap();
-                        match tx.run_queries(q_vals).await {
-                            Ok(_) => {
-                                let el: u128 = n.elapsed().as_millis();
-                                if el > 200 {
-                                    info!(
-                                        "Slow queries on tx: {}ms (~{}/s))",
-                                        el,
-                                        ((1000000000 as f64 / n.elapsed().as_nanos() as f64)
-                                            * (Q_LIMIT as f64 * TX_Q_LEN as f64))
-                                            .round()
-                                    );
-                                }
-                                match tx.commit().await {
-                                    Ok(_) => Ok(()),
-
-                                    Err(e) => Err(backoff::Error::Transient {
-                                        err: e,
-                                        retry_after: None,
-                                    }),
-                                }
+        };
+        if !due {
+            return prev_recv;
+        }
+
+        let n = Instant::now();
+        let (send, recv) = mpsc::channel(1);
+        let id = format!("{:?}", &recv);
+
+        if prev_recv.is_some() {
+            prev_recv.unwrap().recv().await;
+        }
+        let name = (params.0.to_owned()).clone();
+        // Only what is queued now, anything queued while this commits goes with the next one
+        let batch: Vec<(String, Query)> = queue
+            .iter()
+            .map(|v| (v.key().clone(), v.value().clone()))
+            .collect();
+        self.spawned += 1;
+        let commit = self.spawned;
+        let committed = self.committed.clone();
+
+        tokio::spawn(async move {
+            match retry(
+                ExponentialBackoffBuilder::default()
+                    .with_initial_interval(Duration::from_millis(2))
+                    .with_max_elapsed_time(Some(Duration::from_millis(350)))
+                    .with_randomization_factor(0.35)
+                    .build(),
+                || async {
+                    let q_vals: Vec<Query> = batch.iter().map(|(_, q)| q.clone()).collect();
+                    let mut tx = inner.start_txn().await.unwrap();
+                    match tx.run_queries(q_vals).await {
+                        Ok(_) => {
+                            let el: u128 = n.elapsed().as_millis();
+                            if el > 200 {
+                                info!(
+                                    "Slow queries on tx: {}ms (~{}/s))",
+                                    el,
+                                    ((1000000000 as f64 / n.elapsed().as_nanos() as f64)
+                                        * (Q_LIMIT as f64 * TX_Q_LEN as f64))
+                                        .round()
+                                );
+                            }
+                            match tx.commit().await {
+                                Ok(_) => Ok(()),
+
+                                Err(e) => Err(backoff::Error::Transient {
+                                    err: e,
+                                    retry_after: None,
+                                }),
                             }
-                            Err(e) => Err(backoff::Error::Transient {
-                                err: e,
-                                retry_after: None,
-                            }),
                         }
-                    },
-                )
-                .await
-                {
-                    Ok(_) => {
-                        queue.clear();
+                        Err(e) => Err(backoff::Error::Transient {
+                            err: e,
+                            retry_after: None,
+                        }),
                     }
-                    Err(e) => {
-                        warn!("Error on commit query for {}: {}", name, e);
-                    }
-                };
-                match send.send(()).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!(
-                            "Something has gone very wrong; unable to send completion channel  {id}: {}",
-                            e
-                        )
+                },
+            )
+            .await
+            {
+                Ok(_) => {
+                    for (key, _) in &batch {
+                        queue.remove(key);
                     }
-                };
-            });
+                    committed.fetch_max(commit, Ordering::Release);
+                }
+                Err(e) => {
+                    // Left on the queue for the next commit to pick up
+                    warn!("Error on commit query for {}: {}", name, e);
+                }
+            };
+            match send.send(()).await {
+                Ok(_) => {}
+                Err(e) => {
+                    error!(
+                        "Something has gone very wrong; unable to send completion channel  {id}: {}",
+                        e
+                    )
+                }
+            };
+        });
 
-            return Some(recv);
+        Some(recv)
+    }
+
+    /// Moves any engagement whose post has now been written onto its queue
+    fn release_pending(&mut self) {
+        let committed = self.committed.load(Ordering::Acquire);
+        for (kind, params) in self.pending.release(committed) {
+            self.engagement_queue(kind).0.push(params);
         }
-        // We're using a Map instead of a set because something about DashSet didnt play nice
-        // Construct the query
-        match query_script {
-            Some(s) => {
-                let qry = neo4rs::query(s).param(params.0, mem::take(&mut params.1));
-                queue.insert(Uuid::new_v4().to_string(), qry);
-            }
-            None => {
-                error!("Expected a query script but none was provided");
-                return None;
-            }
+    }
+
+    fn engagement_queue(
+        &mut self,
+        kind: Engagement,
+    ) -> (&mut Vec<Params>, &'static str, &'static str) {
+        match kind {
+            Engagement::Like => (&mut self.like_queue, queries::ADD_LIKE, "like"),
+            Engagement::Repost => (&mut self.repost_queue, queries::ADD_REPOST, "repost"),
+            Engagement::Reply => (&mut self.reply_queue, queries::ADD_REPLY, "reply"),
+            Engagement::Quote => (&mut self.quote_queue, queries::ADD_QUOTE, "quote"),
         }
-        prev_recv
     }
+
+    /// Like `queue_event_write!`, but holds the write back if its post isnt in the graph yet
+    async fn queue_engagement(
+        &mut self,
+        kind: Engagement,
+        subject: &str,
+        params: Params,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        self.release_pending();
+        let params = match self.pending.hold(kind, subject, params, now()) {
+            Some(p) => p,
+            None => return rec,
+        };
+        let (queue, query, name) = self.engagement_queue(kind);
+        queue.push(params);
+        if queue.len() >= Q_LIMIT {
+            let q = mem::take(queue);
+            return self
+                .enqueue_query(Some(query), (&pluralize(name), q), rec)
+                .await;
+        }
+        rec
+    }
+}
+
+/// Adds `qry` to the tx queue, & whether that takes it over `TX_Q_LEN` so it is time to commit
+fn queue_query(queue: &DashMap<String, Query>, qry: Query) -> bool {
+    queue.insert(Uuid::new_v4().to_string(), qry);
+    queue.len() > TX_Q_LEN
 }
 
 impl ATEventProcessor for MemgraphWrapper {
@@ -328,10 +481,33 @@ impl ATEventProcessor for MemgraphWrapper {
         did: String,
         rkey: String,
         parent: String,
+        root: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
-        let resp = queue_event_write!(self, "reply", rec, did, rkey, parent);
-        resp
+        let params = HashMap::from([
+            ("did".to_owned(), did.into()),
+            ("rkey".to_owned(), rkey.into()),
+            ("parent".to_owned(), parent.as_str().into()),
+            ("root".to_owned(), root.into()),
+        ]);
+        self.queue_engagement(Engagement::Reply, &parent, params, rec)
+            .await
+    }
+
+    async fn add_quote(
+        &mut self,
+        did: String,
+        rkey: String,
+        subject: String,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        self.queue_engagement(
+            Engagement::Quote,
+            &subject.clone(),
+            engagement(did, rkey, subject),
+            rec,
+        )
+        .await
     }
 
     async fn add_post(
@@ -339,30 +515,39 @@ impl ATEventProcessor for MemgraphWrapper {
         did: String,
         rkey: String,
         timestamp: &i64,
-        is_reply: bool,
+        parent_did: String,
         post_type: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
-        let is_reply = if is_reply {
-            "y".to_owned()
-        } else {
-            "n".to_owned()
-        };
-
-        let timestamp = format! {"{timestamp}"};
-        let resp = queue_event_write!(self, "post", rec, did, rkey, is_reply, post_type, timestamp);
+        let is_reply = !parent_did.is_empty();
+        let timestamp = *timestamp;
+        let uri = AtUri::post(&did, &rkey).to_string();
+        self.release_pending();
+        self.pending.queued(uri.clone(), &rkey, now());
+        // This one fills the queue, so it goes to the tx queue & out with the next commit
+        if self.post_queue.len() + 1 >= Q_LIMIT {
+            self.pending.flushed(self.spawned + 1);
+        }
+        let resp = queue_event_write!(
+            self, "post", rec, did, rkey, uri, is_reply, parent_did, post_type, timestamp
+        );
         resp
     }
 
     async fn add_repost(
         &mut self,
         did: String,
-        rkey_parent: String,
+        subject: String,
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
-        let resp = queue_event_write!(self, "repost", rec, did, rkey, rkey_parent);
-        resp
+        self.queue_engagement(
+            Engagement::Repost,
+            &subject.clone(),
+            engagement(did, rkey, subject),
+            rec,
+        )
+        .await
     }
 
     async fn add_follow(
@@ -372,6 +557,7 @@ impl ATEventProcessor for MemgraphWrapper {
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
+        self.snapshots.invalidate_viewer(&did);
         let resp = queue_event_write!(self, "follow", rec, out, rkey, did);
         resp
     }
@@ -383,6 +569,7 @@ impl ATEventProcessor for MemgraphWrapper {
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
+        self.snapshots.invalidate_viewer(&did);
         let resp = queue_event_write!(self, "block", rec, blockee, rkey, did);
         resp
     }
@@ -390,12 +577,36 @@ impl ATEventProcessor for MemgraphWrapper {
     async fn add_like(
         &mut self,
         did: String,
-        rkey_parent: String,
+        subject: String,
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
-        let resp = queue_event_write!(self, "like", rec, did, rkey, rkey_parent);
-        resp
+        self.queue_engagement(
+            Engagement::Like,
+            &subject.clone(),
+            engagement(did, rkey, subject),
+            rec,
+        )
+        .await
+    }
+
+    async fn update_handle(
+        &mut self,
+        did: String,
+        handle: String,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        queue_event_write!(self, "handle", rec, did, handle)
+    }
+
+    async fn set_account_status(
+        &mut self,
+        did: String,
+        active: bool,
+        status: String,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        queue_event_write!(self, "account", rec, did, active, status)
     }
 
     async fn rm_post(
@@ -404,8 +615,9 @@ impl ATEventProcessor for MemgraphWrapper {
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
-        let resp = queue_event_remove!("post", rec, self, did, rkey);
-        resp
+        let uri = AtUri::post(&did, &rkey).to_string();
+        self.snapshots.forget_post(uri.clone());
+        queue_event_remove!("post", rec, self, did, uri)
     }
 
     async fn rm_repost(
@@ -424,6 +636,7 @@ impl ATEventProcessor for MemgraphWrapper {
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
+        self.snapshots.invalidate_viewer(&did);
         let resp = queue_event_remove!("follow", rec, self, did, rkey);
         resp
     }
@@ -444,6 +657,7 @@ impl ATEventProcessor for MemgraphWrapper {
         rkey: String,
         rec: Option<mpsc::Receiver<()>>,
     ) -> Option<mpsc::Receiver<()>> {
+        self.snapshots.invalidate_viewer(&did);
         let resp = queue_event_remove!("block", rec, self, did, rkey);
         resp
     }
@@ -458,11 +672,36 @@ impl ATEventProcessor for MemgraphWrapper {
         resp
     }
 
+    async fn rm_quote(
+        &mut self,
+        did: String,
+        rkey: String,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        queue_event_remove!("quote", rec, self, did, rkey)
+    }
+
+    async fn rm_account(
+        &mut self,
+        did: String,
+        rec: Option<mpsc::Receiver<()>>,
+    ) -> Option<mpsc::Receiver<()>> {
+        queue_event_remove!("account", rec, self, did)
+    }
+
     fn get_filters(&self) -> &HashMap<ATEventType, VecDeque<Box<dyn Filter + Send>>> {
         &self.filters
     }
 }
 
+fn engagement(did: String, rkey: String, subject: String) -> Params {
+    HashMap::from([
+        ("did".to_owned(), did.into()),
+        ("rkey".to_owned(), rkey.into()),
+        ("subject".to_owned(), subject.into()),
+    ])
+}
+
 fn pluralize(word: &str) -> String {
     let word_len = word.len();
     let snip = &word[..word_len - 1];
@@ -0,0 +1,226 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env, mem,
+    time::{Duration, Instant},
+};
+
+use crate::bsky::uri::{AtUri, tid_micros};
+use crate::event_database::Params;
+
+/// The writes that `MATCH` their subject post, & so do nothing if it isnt in the graph yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engagement {
+    Like,
+    Repost,
+    Reply,
+    Quote,
+}
+
+/// Where a post we have queued is up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Written {
+    /// In the tx queue, & in the graph once that commit goes through
+    InCommit(u64),
+    /// Committed, remembered for `ttl` so engagement on it isnt held for nothing
+    Done,
+}
+
+/// Engagement on posts that arent in the graph yet, keyed by the subject URI, held until the post is committed.
+/// That is either a post still sitting in a queue (or a commit) of ours, or one we havent seen but whose TID says
+/// it is new enough to be on its way. Nothing is held longer than `ttl` or past `capacity`, after which it is
+/// written regardless like it always was
+pub struct PendingEngagement {
+    ttl: Duration,
+    capacity: usize,
+    held: usize,
+    /// Newest post TID seen, so a firehose catching up isnt judged by the wall clock
+    clock: u64,
+
+    /// Posts in the post queue, not yet handed to a commit
+    queued: HashSet<String>,
+    posts: HashMap<String, Written>,
+    /// Posts by the commit they are in (& when it was handed out), & by when they were done, for pruning `posts`
+    posts_by_commit: BTreeMap<u64, (Instant, Vec<String>)>,
+    posts_done: VecDeque<(Instant, String)>,
+
+    /// Engagement on posts not yet in a commit
+    waiting: HashMap<String, Vec<(Engagement, Params)>>,
+    /// When each unseen post started waiting, to give up on them
+    unseen: VecDeque<(Instant, String)>,
+    /// Engagement that goes once the commit with its post does, or after `ttl` if that commit never goes through
+    by_commit: BTreeMap<u64, (Instant, Vec<(Engagement, Params)>)>,
+}
+
+impl PendingEngagement {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            held: 0,
+            clock: 0,
+            queued: HashSet::new(),
+            posts: HashMap::new(),
+            posts_by_commit: BTreeMap::new(),
+            posts_done: VecDeque::new(),
+            waiting: HashMap::new(),
+            unseen: VecDeque::new(),
+            by_commit: BTreeMap::new(),
+        }
+    }
+
+    /// `PENDING_ENGAGEMENT_TTL_SECS` (default 60) & `PENDING_ENGAGEMENT_CAPACITY` (default 100000, 0 turns it off)
+    pub fn from_env() -> Self {
+        let var = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            Duration::from_secs(var("PENDING_ENGAGEMENT_TTL_SECS", 60)),
+            var("PENDING_ENGAGEMENT_CAPACITY", 100000) as usize,
+        )
+    }
+
+    /// A post was pushed on the post queue. `now` is the wall clock in micros
+    pub fn queued(&mut self, uri: String, rkey: &str, now: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(t) = tid_micros(rkey) {
+            self.clock = self.clock.max(t.min(now));
+        }
+        self.queued.insert(uri);
+    }
+
+    /// The post queue went into the tx queue, to be written by commit number `commit`
+    pub fn flushed(&mut self, commit: u64) {
+        for uri in mem::take(&mut self.queued) {
+            if let Some(held) = self.waiting.remove(&uri) {
+                self.for_commit(commit).extend(held);
+            }
+            self.posts.insert(uri.clone(), Written::InCommit(commit));
+            self.posts_by_commit
+                .entry(commit)
+                .or_insert_with(|| (Instant::now(), Vec::new()))
+                .1
+                .push(uri);
+        }
+    }
+
+    /// Hands `params` back if they can be written now, otherwise holds on to them
+    pub fn hold(
+        &mut self,
+        kind: Engagement,
+        subject: &str,
+        params: Params,
+        now: u64,
+    ) -> Option<Params> {
+        if self.held >= self.capacity {
+            return Some(params);
+        }
+        match self.posts.get(subject) {
+            Some(Written::Done) => return Some(params),
+            Some(Written::InCommit(commit)) => {
+                let commit = *commit;
+                self.for_commit(commit).push((kind, params));
+                self.held += 1;
+                return None;
+            }
+            None => {}
+        }
+
+        if !self.queued.contains(subject) {
+            // Old posts are either in the graph already or never will be
+            let fresh = AtUri::parse(subject)
+                .and_then(|u| tid_micros(&u.rkey))
+                .is_some_and(|t| {
+                    let clock = match self.clock {
+                        0 => now,
+                        c => c,
+                    };
+                    clock.saturating_sub(t) < self.ttl.as_micros() as u64
+                });
+            if !fresh {
+                return Some(params);
+            }
+            if !self.waiting.contains_key(subject) {
+                self.unseen.push_back((Instant::now(), subject.to_owned()));
+            }
+        }
+        self.waiting
+            .entry(subject.to_owned())
+            .or_default()
+            .push((kind, params));
+        self.held += 1;
+        None
+    }
+
+    /// Everything that can be written now that commits up to `committed` are in, plus anything given up on
+    pub fn release(&mut self, committed: u64) -> Vec<(Engagement, Params)> {
+        let now = Instant::now();
+        let mut ready = Vec::new();
+        // Commits are numbered in the order they are handed out, so the first is also the oldest
+        while let Some((commit, (since, _))) = self.by_commit.first_key_value()
+            && (*commit <= committed || now.duration_since(*since) >= self.ttl)
+        {
+            if let Some((_, (_, held))) = self.by_commit.pop_first() {
+                ready.extend(held);
+            }
+        }
+
+        while let Some((commit, (since, _))) = self.posts_by_commit.first_key_value()
+            && (*commit <= committed || now.duration_since(*since) >= self.ttl)
+        {
+            if let Some((commit, (_, uris))) = self.posts_by_commit.pop_first() {
+                for uri in uris {
+                    match commit <= committed {
+                        true => {
+                            self.posts.insert(uri.clone(), Written::Done);
+                            self.posts_done.push_back((now, uri));
+                        }
+                        // Its commit never went through, so as far as we know it isnt in the graph
+                        false => {
+                            if self.posts.get(&uri) == Some(&Written::InCommit(commit)) {
+                                self.posts.remove(&uri);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        while let Some((at, _)) = self.posts_done.front()
+            && (now.duration_since(*at) >= self.ttl || self.posts_done.len() > self.capacity)
+        {
+            // Unless it has been replayed into another commit since
+            if let Some((_, uri)) = self.posts_done.pop_front()
+                && self.posts.get(&uri) == Some(&Written::Done)
+            {
+                self.posts.remove(&uri);
+            }
+        }
+
+        // Posts that never turned up, unless they have since been queued & will go with their commit
+        while let Some((at, _)) = self.unseen.front()
+            && now.duration_since(*at) >= self.ttl
+        {
+            if let Some((_, uri)) = self.unseen.pop_front()
+                && !self.queued.contains(&uri)
+                && let Some(held) = self.waiting.remove(&uri)
+            {
+                ready.extend(held);
+            }
+        }
+
+        self.held -= ready.len();
+        ready
+    }
+
+    fn for_commit(&mut self, commit: u64) -> &mut Vec<(Engagement, Params)> {
+        &mut self
+            .by_commit
+            .entry(commit)
+            .or_insert_with(|| (Instant::now(), Vec::new()))
+            .1
+    }
+}
@@ -0,0 +1,479 @@
+#[cfg(test)]
+mod processor_test {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use axum::{
+        Json, Router,
+        extract::{Path, Query},
+        routing::get,
+    };
+    use dashmap::DashMap;
+    use serde_json::{Value, json};
+
+    use crate::backfill::{BackfillMessage, JobState};
+    use crate::bsky::{self, did::DidResolver, xrpc::XrpcClient};
+    use crate::event_database::{EventDatabase, Params};
+    use crate::graph::retention::Target;
+    use crate::graph::{memory::MemoryGraph, queries};
+    use crate::processor::memory::{MemoryWrapper, Seeder, seed_network};
+    use crate::processor::pending::{Engagement, PendingEngagement};
+    use crate::processor::{TX_Q_LEN, queue_query};
+    use crate::server::{listen::now, snapshot::SnapshotCache};
+
+    // 2023-11-14T22:13:20Z & ten minutes later, in micros
+    const THEN: u64 = 1_700_000_000_000_000;
+    const LATER: u64 = THEN + 600_000_000;
+
+    fn tid(micros: u64) -> String {
+        const CHARS: &[u8] = b"234567abcdefghijklmnopqrstuvwxyz";
+        let n = micros << 10;
+        (0..13)
+            .rev()
+            .map(|i| CHARS[((n >> (i * 5)) & 31) as usize] as char)
+            .collect()
+    }
+
+    fn post(micros: u64) -> (String, String) {
+        post_by("did:plc:author", micros)
+    }
+
+    fn post_by(did: &str, micros: u64) -> (String, String) {
+        let rkey = tid(micros);
+        (format!("at://{did}/app.bsky.feed.post/{rkey}"), rkey)
+    }
+
+    fn like(subject: &str) -> Params {
+        HashMap::from([("subject".to_owned(), subject.into())])
+    }
+
+    #[test]
+    fn holds_engagement_until_the_commit_with_its_post() {
+        let mut pending = PendingEngagement::new(Duration::from_secs(60), 100);
+        let (uri, rkey) = post(THEN);
+        pending.queued(uri.clone(), &rkey, THEN);
+
+        assert!(
+            pending
+                .hold(Engagement::Like, &uri, like(&uri), THEN)
+                .is_none()
+        );
+        pending.flushed(3);
+        assert!(
+            pending
+                .hold(Engagement::Repost, &uri, like(&uri), THEN)
+                .is_none()
+        );
+
+        assert!(pending.release(2).is_empty());
+        let released = pending.release(3);
+        assert_eq!(
+            released.iter().map(|r| r.0).collect::<Vec<_>>(),
+            vec![Engagement::Like, Engagement::Repost]
+        );
+
+        // Written, so nothing else waits on it
+        assert!(
+            pending
+                .hold(Engagement::Like, &uri, like(&uri), THEN)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn holds_new_unseen_posts_but_not_old_ones() {
+        let mut pending = PendingEngagement::new(Duration::from_secs(60), 100);
+        let (old, _) = post(THEN);
+        let (new, rkey) = post(LATER);
+
+        assert!(
+            pending
+                .hold(Engagement::Like, &old, like(&old), LATER)
+                .is_some()
+        );
+        assert!(
+            pending
+                .hold(Engagement::Reply, &new, like(&new), LATER)
+                .is_none()
+        );
+        assert!(
+            pending
+                .hold(Engagement::Like, "not a uri", like("x"), LATER)
+                .is_some()
+        );
+
+        // The post turns up & goes with it
+        pending.queued(new.clone(), &rkey, LATER);
+        pending.flushed(1);
+        assert_eq!(pending.release(1).len(), 1);
+    }
+
+    #[test]
+    fn judges_freshness_by_the_newest_post_not_the_wall_clock() {
+        let mut pending = PendingEngagement::new(Duration::from_secs(60), 100);
+        let (seen, seen_rkey) = post(THEN);
+        let (next, _) = post(THEN + 1_000_000);
+        // Catching up on the firehose, ten minutes behind
+        pending.queued(seen, &seen_rkey, LATER);
+        assert!(
+            pending
+                .hold(Engagement::Like, &next, like(&next), LATER)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn gives_up_on_posts_that_never_arrive() {
+        let mut pending = PendingEngagement::new(Duration::from_millis(50), 100);
+        let (unseen, _) = post(THEN);
+        let (queued, rkey) = post(THEN - 1);
+        pending.queued(queued.clone(), &rkey, THEN);
+        assert!(
+            pending
+                .hold(Engagement::Like, &unseen, like(&unseen), THEN)
+                .is_none()
+        );
+        assert!(
+            pending
+                .hold(Engagement::Like, &queued, like(&queued), THEN)
+                .is_none()
+        );
+        assert!(pending.release(0).is_empty());
+
+        std::thread::sleep(Duration::from_millis(60));
+        // The queued post waits for its commit regardless
+        let released = pending.release(0);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].1["subject"], *unseen);
+    }
+
+    #[test]
+    fn gives_up_on_commits_that_never_go_through() {
+        let mut pending = PendingEngagement::new(Duration::from_millis(50), 100);
+        let (uri, rkey) = post(THEN);
+        pending.queued(uri.clone(), &rkey, THEN);
+        pending.flushed(4);
+        assert!(
+            pending
+                .hold(Engagement::Like, &uri, like(&uri), THEN)
+                .is_none()
+        );
+        assert!(pending.release(3).is_empty());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pending.release(3).len(), 1);
+        // Nothing left over for when it does
+        assert!(pending.release(4).is_empty());
+    }
+
+    #[test]
+    fn writes_through_when_full() {
+        let mut pending = PendingEngagement::new(Duration::from_secs(60), 1);
+        let (uri, rkey) = post(THEN);
+        pending.queued(uri.clone(), &rkey, THEN);
+        assert!(
+            pending
+                .hold(Engagement::Like, &uri, like(&uri), THEN)
+                .is_none()
+        );
+        assert!(
+            pending
+                .hold(Engagement::Like, &uri, like(&uri), THEN)
+                .is_some()
+        );
+
+        let mut off = PendingEngagement::new(Duration::from_secs(60), 0);
+        off.queued(uri.clone(), &rkey, THEN);
+        assert!(off.hold(Engagement::Like, &uri, like(&uri), THEN).is_some());
+    }
+
+    #[test]
+    fn keeps_the_query_that_fills_the_tx_queue() {
+        let queue = DashMap::new();
+        for _ in 0..TX_Q_LEN {
+            assert!(!queue_query(&queue, neo4rs::query("RETURN 1")));
+        }
+        assert!(queue_query(&queue, neo4rs::query("RETURN 1")));
+        assert_eq!(queue.len(), TX_Q_LEN + 1);
+    }
+
+    fn event(did: &str, operation: &str, collection: &str, rkey: &str, record: &str) -> String {
+        format!(
+            r#"{{"did":"{did}","time_us":1732000000000000,"kind":"commit","commit":{{"rev":"r","operation":"{operation}","collection":"{collection}","rkey":"{rkey}","record":{record}}}}}"#
+        )
+    }
+
+    // Straight from the firehose into the in memory graph, & back out as a feed
+    #[tokio::test]
+    async fn memory_graph_ingests_the_firehose() {
+        let graph = MemoryGraph::default();
+        let mut wrapper = MemoryWrapper::new(
+            graph.clone(),
+            Arc::new(SnapshotCache::from_env()),
+            HashMap::new(),
+        );
+        let (uri, rkey) = post_by("did:plc:author", now());
+
+        let mut events = vec![event(
+            "did:plc:viewer",
+            "create",
+            "app.bsky.graph.follow",
+            "f1",
+            r#"{"$type":"app.bsky.graph.follow","createdAt":"2024-11-19T07:06:40.000Z","subject":"did:plc:author"}"#,
+        )];
+        // The likes beat the post here, so have to wait for it
+        for i in 0..11 {
+            events.push(event(
+                &format!("did:plc:fan{i}"),
+                "create",
+                "app.bsky.feed.like",
+                &format!("l{i}"),
+                &format!(
+                    r#"{{"$type":"app.bsky.feed.like","createdAt":"2024-11-19T07:06:40.000Z","subject":{{"cid":"c","uri":"{uri}"}}}}"#
+                ),
+            ));
+        }
+        events.push(event(
+            "did:plc:author",
+            "create",
+            "app.bsky.feed.post",
+            &rkey,
+            r#"{"$type":"app.bsky.feed.post","createdAt":"2024-11-19T07:06:40.000Z","text":"hi"}"#,
+        ));
+        for evt in &events {
+            bsky::handle_event_fast(evt.as_bytes(), &mut wrapper, None, false)
+                .await
+                .unwrap();
+        }
+
+        let params: Params = HashMap::from([
+            ("did".to_owned(), "did:plc:viewer".into()),
+            ("replies".to_owned(), false.into()),
+            ("time".to_owned(), now().into()),
+        ]);
+        let feed = graph
+            .read(
+                "GET_BEST_FOLLOWED",
+                queries::GET_BEST_FOLLOWED,
+                Some(params.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(feed[&uri].likes, 11);
+
+        let delete = r#"{"did":"did:plc:author","time_us":1732000000000000,"kind":"commit","commit":{"rev":"r","operation":"delete","collection":"app.bsky.feed.post","rkey":"RKEY"}}"#
+            .replace("RKEY", &rkey);
+        bsky::handle_event_fast(delete.as_bytes(), &mut wrapper, None, false)
+            .await
+            .unwrap();
+        let feed = graph
+            .read(
+                "GET_BEST_FOLLOWED",
+                queries::GET_BEST_FOLLOWED,
+                Some(params),
+            )
+            .await
+            .unwrap();
+        assert!(feed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn seeding_a_viewer_fills_in_their_follows() {
+        let graph = MemoryGraph::default();
+        let (uri, _) = post_by("did:plc:author", now());
+        graph.add_post("did:plc:author", &uri, now() as i64 - 1, "");
+        for i in 0..11 {
+            graph.add_edge(
+                Target::Likes,
+                &format!("did:plc:fan{i}"),
+                &uri,
+                &format!("l{i}"),
+            );
+        }
+        let params: Params = HashMap::from([
+            ("did".to_owned(), "did:plc:viewer".into()),
+            ("replies".to_owned(), false.into()),
+            ("time".to_owned(), now().into()),
+        ]);
+
+        let follows = vec![("did:plc:author".to_owned(), "f1".to_owned())];
+        seed_network(&graph, "did:plc:viewer", &[], &follows);
+        let feed = graph
+            .read(
+                "GET_BEST_FOLLOWED",
+                queries::GET_BEST_FOLLOWED,
+                Some(params),
+            )
+            .await
+            .unwrap();
+        assert!(feed.contains_key(&uri));
+    }
+
+    #[tokio::test]
+    async fn seeder_tracks_jobs_like_the_backfill() {
+        // Nothing listening, so every seed fails straight away
+        let client = Arc::new(XrpcClient::new(1000.0, 1000.0, 0));
+        let resolver = Arc::new(DidResolver::new(
+            client.clone(),
+            "http://127.0.0.1:1",
+            Duration::from_secs(60),
+        ));
+        let seeder = Seeder::new(
+            MemoryGraph::default(),
+            Arc::new(SnapshotCache::from_env()),
+            client,
+            resolver,
+        );
+        let (send, recv) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(seeder.run(recv));
+
+        let status = || async {
+            let (resp, mut recv) = tokio::sync::mpsc::channel(1);
+            let did = "did:plc:viewer".to_owned();
+            send.send(BackfillMessage::Status { did, resp })
+                .await
+                .unwrap();
+            recv.recv().await.unwrap()
+        };
+        let requeue = || async {
+            let (resp, mut recv) = tokio::sync::mpsc::channel(1);
+            let did = "did:plc:viewer".to_owned();
+            send.send(BackfillMessage::Requeue { did, resp })
+                .await
+                .unwrap();
+            recv.recv().await.unwrap()
+        };
+
+        assert_eq!(status().await, None);
+        send.send(BackfillMessage::Enqueue {
+            did: "did:plc:viewer".to_owned(),
+        })
+        .await
+        .unwrap();
+        let mut job = status().await.unwrap();
+        for _ in 0..100 {
+            if job.state == JobState::Failed {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            job = status().await.unwrap();
+        }
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.attempts, 1);
+        assert!(job.error.is_some());
+
+        assert!(requeue().await);
+        assert_ne!(status().await.unwrap().state, JobState::Done);
+    }
+
+    /// A PLC directory & PDS in one, where the viewer follows a friend who follows the author
+    async fn stub_network() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let pds = base.clone();
+        let plc = move |Path(did): Path<String>| async move {
+            Json(json!({
+                "id": did,
+                "service": [{"id": "#atproto_pds", "type": "AtprotoPersonalDataServer", "serviceEndpoint": pds}]
+            }))
+        };
+        let list_records = |Query(params): Query<HashMap<String, String>>| async move {
+            let repo = params.get("repo").cloned().unwrap_or_default();
+            let follows = match (repo.as_str(), params.get("collection").map(|c| c.as_str())) {
+                ("did:plc:viewer", Some("app.bsky.graph.follow")) => vec!["did:plc:friend"],
+                ("did:plc:friend", Some("app.bsky.graph.follow")) => vec!["did:plc:author"],
+                _ => vec![],
+            };
+            let records: Vec<Value> = follows
+                .into_iter()
+                .enumerate()
+                .map(|(i, subject)| {
+                    json!({
+                        "uri": format!("at://{repo}/app.bsky.graph.follow/rkey{i}"),
+                        "cid": "bafy",
+                        "value": {
+                            "$type": "app.bsky.graph.follow",
+                            "subject": subject,
+                            "createdAt": "2024-01-01T00:00:00.000Z"
+                        }
+                    })
+                })
+                .collect();
+            Json(json!({"records": records}))
+        };
+        let router = Router::new()
+            .route("/xrpc/com.atproto.repo.listRecords", get(list_records))
+            .route("/:did", get(plc));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        base
+    }
+
+    #[tokio::test]
+    async fn seeding_a_viewer_crawls_their_2nd_degree() {
+        let base = stub_network().await;
+        let client = Arc::new(XrpcClient::new(1000.0, 1000.0, 0));
+        let resolver = Arc::new(DidResolver::new(
+            client.clone(),
+            &base,
+            Duration::from_secs(60),
+        ));
+        let graph = MemoryGraph::default();
+        let (uri, _) = post_by("did:plc:author", now());
+        graph.add_post("did:plc:author", &uri, now() as i64 - 1, "");
+        // Well under the default like threshold, so it only shows up once thresholds are set
+        for i in 0..3 {
+            graph.add_edge(
+                Target::Likes,
+                &format!("did:plc:fan{i}"),
+                &uri,
+                &format!("l{i}"),
+            );
+        }
+
+        let seeder = Seeder::new(
+            graph.clone(),
+            Arc::new(SnapshotCache::from_env()),
+            client,
+            resolver,
+        );
+        let (send, recv) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(seeder.run(recv));
+        let status = || async {
+            let (resp, mut recv) = tokio::sync::mpsc::channel(1);
+            let did = "did:plc:viewer".to_owned();
+            send.send(BackfillMessage::Status { did, resp })
+                .await
+                .unwrap();
+            recv.recv().await.unwrap().unwrap()
+        };
+        send.send(BackfillMessage::Enqueue {
+            did: "did:plc:viewer".to_owned(),
+        })
+        .await
+        .unwrap();
+        let mut job = status().await;
+        for _ in 0..100 {
+            if matches!(job.state, JobState::Done | JobState::Failed) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            job = status().await;
+        }
+        assert_eq!(job.state, JobState::Done, "{:?}", job.error);
+        assert_eq!((job.follows_total, job.follows_done), (1, 1));
+
+        let params: Params = HashMap::from([
+            ("did".to_owned(), "did:plc:viewer".into()),
+            ("replies".to_owned(), false.into()),
+            ("time".to_owned(), now().into()),
+        ]);
+        let feed = graph
+            .read(
+                "GET_FOLLOWING_PLUS_LIKES",
+                queries::GET_FOLLOWING_PLUS_LIKES,
+                Some(params),
+            )
+            .await
+            .unwrap();
+        assert!(feed.contains_key(&uri));
+    }
+}
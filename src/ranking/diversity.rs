@@ -0,0 +1,168 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::bsky::uri::AtUri;
+
+use super::Ranked;
+
+/// Which kind of network signal surfaced a post, taken from the name of the query that found it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReasonGroup {
+    Followed,
+    Liked,
+    Reposted,
+    Quoted,
+}
+
+/// Order reasons are interleaved in on each page
+const INTERLEAVE_ORDER: [ReasonGroup; 4] = [
+    ReasonGroup::Followed,
+    ReasonGroup::Liked,
+    ReasonGroup::Reposted,
+    ReasonGroup::Quoted,
+];
+
+impl ReasonGroup {
+    pub fn from_reason(reason: &str) -> Self {
+        if reason.contains("QUOTES") {
+            ReasonGroup::Quoted
+        } else if reason.contains("REPOSTS") {
+            ReasonGroup::Reposted
+        } else if reason.contains("LIKES") {
+            ReasonGroup::Liked
+        } else {
+            ReasonGroup::Followed
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diversity {
+    /// Most posts a single author can have on one page, 0 for no cap
+    pub max_per_author: usize,
+    /// Each score is divided by `author_post_count ^ volume_exponent`, so prolific posters dont dominate
+    pub volume_exponent: f64,
+    /// Round-robin between reason groups within a page, rather than pure score order
+    pub interleave_reasons: bool,
+}
+
+impl Default for Diversity {
+    fn default() -> Self {
+        Self {
+            max_per_author: 2,
+            volume_exponent: 0.5,
+            interleave_reasons: true,
+        }
+    }
+}
+
+impl Diversity {
+    /// Re-ranks a best-first list one page at a time. Posts over an author's cap are deferred to a later
+    /// page rather than dropped. Scores are rewritten to stay strictly decreasing, so the result can still
+    /// be paginated by score
+    pub fn rerank(&self, ranked: Vec<Ranked>, page_size: usize) -> Vec<Ranked> {
+        if ranked.is_empty() || page_size == 0 {
+            return ranked;
+        }
+
+        let mut remaining = self.normalise_volume(ranked);
+        let mut out: Vec<Ranked> = Vec::with_capacity(remaining.len());
+
+        while !remaining.is_empty() {
+            let page = self.next_page(&mut remaining, page_size);
+            for mut r in page {
+                if let Some(prev) = out.last() {
+                    r.score = r.score.min(prev.score.next_down());
+                }
+                out.push(r);
+            }
+        }
+
+        out
+    }
+
+    fn normalise_volume(&self, ranked: Vec<Ranked>) -> Vec<Ranked> {
+        if self.volume_exponent == 0.0 {
+            return ranked;
+        }
+
+        let mut volume: HashMap<String, usize> = HashMap::new();
+        for r in ranked.iter() {
+            *volume.entry(author(r)).or_default() += 1;
+        }
+
+        let mut ranked: Vec<Ranked> = ranked
+            .into_iter()
+            .map(|mut r| {
+                let n = volume[&author(&r)] as f64;
+                r.score /= n.powf(self.volume_exponent);
+                r
+            })
+            .collect();
+        ranked.sort_by(super::best_first);
+        ranked
+    }
+
+    /// Pulls the next page out of `remaining`, which must be best-first
+    fn next_page(&self, remaining: &mut Vec<Ranked>, page_size: usize) -> Vec<Ranked> {
+        let authors: Vec<String> = remaining.iter().map(author).collect();
+        let groups: Vec<Option<ReasonGroup>> = if self.interleave_reasons {
+            INTERLEAVE_ORDER.iter().copied().map(Some).collect()
+        } else {
+            vec![None]
+        };
+
+        // Each group's posts, best first
+        let mut queues: Vec<VecDeque<usize>> = vec![VecDeque::new(); groups.len()];
+        for (i, r) in remaining.iter().enumerate() {
+            let group = ReasonGroup::from_reason(&r.post.reason);
+            if let Some(q) = groups.iter().position(|g| g.is_none_or(|g| g == group)) {
+                queues[q].push_back(i);
+            }
+        }
+
+        let mut per_author: HashMap<&str, usize> = HashMap::new();
+        let mut slot_of: Vec<Option<usize>> = vec![None; remaining.len()];
+        let mut picked = 0;
+
+        // Best eligible post of each group in turn, until the page is full or a round finds nothing
+        let mut progressed = true;
+        while picked < page_size && progressed {
+            progressed = false;
+            for queue in queues.iter_mut() {
+                if picked >= page_size {
+                    break;
+                }
+                // Anything skipped is by an author at their cap, who stays there for the rest of the page
+                while let Some(i) = queue.pop_front() {
+                    let n = per_author.entry(authors[i].as_str()).or_default();
+                    if self.max_per_author == 0 || *n < self.max_per_author {
+                        *n += 1;
+                        slot_of[i] = Some(picked);
+                        picked += 1;
+                        progressed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut slots: Vec<Option<Ranked>> = vec![None; picked];
+        let mut rest = Vec::with_capacity(remaining.len() - picked);
+        for (r, slot) in remaining.drain(..).zip(slot_of) {
+            match slot {
+                Some(slot) => slots[slot] = Some(r),
+                None => rest.push(r),
+            }
+        }
+        *remaining = rest;
+
+        slots.into_iter().flatten().collect()
+    }
+}
+
+fn author(r: &Ranked) -> String {
+    match AtUri::parse(&r.post.uri) {
+        Some(u) => u.did,
+        None => r.post.uri.clone(),
+    }
+}
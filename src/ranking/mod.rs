@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+
+use crate::common::PostMsg;
+
+pub mod diversity;
+mod ranking_test;
+
+const MICROS_PER_HOUR: f64 = 3_600_000_000.0;
+
+/// How merged candidates are ordered (and therefore paginated) before being served
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scoring {
+    /// Newest first; the score is just the post timestamp
+    Recency,
+    /// Weighted engagement over age, a la Hacker News:
+    /// `(1 + engagement) / (age_hours + 2) ^ gravity`
+    Gravity {
+        gravity: f64,
+        like_weight: f64,
+        repost_weight: f64,
+        reply_weight: f64,
+        quote_weight: f64,
+    },
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring::Gravity {
+            gravity: 1.8,
+            like_weight: 1.0,
+            repost_weight: 2.0,
+            reply_weight: 1.5,
+            quote_weight: 2.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked {
+    pub score: f64,
+    pub post: PostMsg,
+}
+
+impl Scoring {
+    /// `now` is in microseconds, same as post timestamps
+    pub fn score(&self, post: &PostMsg, now: u64) -> f64 {
+        match self {
+            Scoring::Recency => post.timestamp as f64,
+            Scoring::Gravity {
+                gravity,
+                like_weight,
+                repost_weight,
+                reply_weight,
+                quote_weight,
+            } => {
+                let engagement = like_weight * post.likes as f64
+                    + repost_weight * post.reposts as f64
+                    + reply_weight * post.replies as f64
+                    + quote_weight * post.quotes as f64;
+                let age_hours = now.saturating_sub(post.timestamp) as f64 / MICROS_PER_HOUR;
+                (1.0 + engagement) / (age_hours + 2.0).powf(*gravity)
+            }
+        }
+    }
+
+    /// Scores every candidate and sorts them best first. Ties fall back to newest, then uri,
+    /// so the order is stable between requests
+    pub fn rank(&self, posts: impl IntoIterator<Item = PostMsg>, now: u64) -> Vec<Ranked> {
+        let mut ranked: Vec<Ranked> = posts
+            .into_iter()
+            .map(|post| Ranked {
+                score: self.score(&post, now),
+                post,
+            })
+            .collect();
+
+        ranked.sort_unstable_by(best_first);
+        ranked
+    }
+}
+
+/// Highest score first, then newest, then uri
+pub(crate) fn best_first(a: &Ranked, b: &Ranked) -> Ordering {
+    b.score
+        .partial_cmp(&a.score)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| b.post.timestamp.cmp(&a.post.timestamp))
+        .then_with(|| a.post.uri.cmp(&b.post.uri))
+}
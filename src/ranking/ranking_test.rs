@@ -0,0 +1,141 @@
+#[cfg(test)]
+mod ranking_test {
+    use crate::{
+        common::PostMsg,
+        ranking::{Ranked, Scoring, diversity::Diversity},
+    };
+
+    const HOUR: u64 = 3_600_000_000;
+    const NOW: u64 = 1_732_000_000_000_000;
+
+    fn post(uri: &str, age_hours: u64, likes: u64, reposts: u64) -> PostMsg {
+        PostMsg {
+            uri: uri.to_owned(),
+            timestamp: NOW - age_hours * HOUR,
+            likes,
+            reposts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recency_is_newest_first() {
+        let ranked =
+            Scoring::Recency.rank(vec![post("old", 3, 500, 100), post("new", 0, 0, 0)], NOW);
+        assert_eq!(ranked[0].post.uri, "new");
+        assert_eq!(ranked[0].score, NOW as f64);
+    }
+
+    #[test]
+    fn gravity_keeps_popular_older_posts_above_weak_new_ones() {
+        let ranked = Scoring::default().rank(
+            vec![
+                post("weak_new", 0, 2, 0),
+                post("popular_old", 2, 400, 50),
+                post("stale", 20, 400, 50),
+            ],
+            NOW,
+        );
+        let uris: Vec<&str> = ranked.iter().map(|r| r.post.uri.as_str()).collect();
+        assert_eq!(uris, vec!["popular_old", "stale", "weak_new"]);
+    }
+
+    #[test]
+    fn ties_are_broken_deterministically() {
+        let ranked = Scoring::default().rank(
+            vec![
+                post("b", 1, 10, 0),
+                post("a", 1, 10, 0),
+                post("c", 0, 10, 0),
+            ],
+            NOW,
+        );
+        let uris: Vec<&str> = ranked.iter().map(|r| r.post.uri.as_str()).collect();
+        assert_eq!(uris, vec!["c", "a", "b"]);
+    }
+
+    fn ranked(author: &str, n: usize, score: f64, reason: &str) -> Ranked {
+        Ranked {
+            score,
+            post: PostMsg {
+                uri: format!("at://did:plc:{author}/app.bsky.feed.post/{n}"),
+                reason: reason.to_owned(),
+                timestamp: NOW - n as u64,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn authors(ranked: &[Ranked]) -> Vec<String> {
+        ranked
+            .iter()
+            .map(|r| r.post.uri.split('/').nth(2).unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn author_cap_defers_rather_than_drops() {
+        let diversity = Diversity {
+            max_per_author: 1,
+            volume_exponent: 0.0,
+            interleave_reasons: false,
+        };
+        let out = diversity.rerank(
+            vec![
+                ranked("a", 1, 10.0, ""),
+                ranked("a", 2, 9.0, ""),
+                ranked("a", 3, 8.0, ""),
+                ranked("b", 4, 7.0, ""),
+            ],
+            2,
+        );
+        assert_eq!(
+            authors(&out),
+            vec!["did:plc:a", "did:plc:b", "did:plc:a", "did:plc:a"]
+        );
+        assert!(out.windows(2).all(|w| w[0].score > w[1].score));
+    }
+
+    #[test]
+    fn prolific_authors_are_normalised_by_volume() {
+        let diversity = Diversity {
+            max_per_author: 0,
+            volume_exponent: 1.0,
+            interleave_reasons: false,
+        };
+        let out = diversity.rerank(
+            vec![
+                ranked("a", 1, 10.0, ""),
+                ranked("a", 2, 10.0, ""),
+                ranked("a", 3, 10.0, ""),
+                ranked("b", 4, 5.0, ""),
+            ],
+            30,
+        );
+        assert_eq!(authors(&out)[0], "did:plc:b");
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn reasons_are_interleaved_within_a_page() {
+        let diversity = Diversity {
+            max_per_author: 0,
+            volume_exponent: 0.0,
+            interleave_reasons: true,
+        };
+        let out = diversity.rerank(
+            vec![
+                ranked("a", 1, 10.0, "GET_BEST_FOLLOWED"),
+                ranked("b", 2, 9.0, "GET_BEST_FOLLOWED"),
+                ranked("c", 3, 8.0, "GET_BEST_2ND_DEG_LIKES"),
+                ranked("d", 4, 7.0, "GET_BEST_2ND_DEG_REPOSTS"),
+            ],
+            30,
+        );
+        assert_eq!(
+            authors(&out),
+            vec!["did:plc:a", "did:plc:c", "did:plc:d", "did:plc:b"]
+        );
+        assert!(out.windows(2).all(|w| w[0].score > w[1].score));
+    }
+}
@@ -1,38 +1,43 @@
-use core::error;
 use std::collections::HashMap;
-use std::time::SystemTime;
-use std::{mem, sync::Arc, time::Duration};
+use std::time::{Instant, SystemTime};
+use std::{env, sync::Arc, time::Duration};
 
-use dashmap::DashSet;
+use dashmap::{DashMap, mapref::entry::Entry};
 use futures::stream::{FuturesUnordered, StreamExt};
-use hyper::StatusCode;
-use tokio::sync::mpsc::error::SendError;
-use tokio::sync::{RwLock, mpsc};
-use tokio::task::JoinSet;
+use tokio::sync::{Semaphore, mpsc};
 use tracing::{error, info, warn};
 
-use crate::bsky::types::RecNotFound;
-use crate::common::{FetchMessage, PostMsg, PostResp};
+use crate::common::{FetchMessage, PostMsg, PostResp, cursor, feed::FeedConfig};
 
-use crate::bsky;
-use crate::event_database::EventDatabase;
+use crate::event_database::{EventDatabase, Params};
 use crate::graph::queries;
-
-pub async fn listen_for_requests<T: EventDatabase<HashMap<String, PostMsg>> + Clone + 'static>(
-    write_lock: Arc<RwLock<()>>,
+use crate::ranking::Ranked;
+use crate::server::{
+    prewarm::Prewarm,
+    snapshot::{Snapshot, SnapshotCache},
+};
+
+/// Size of the pages diversity rules are applied over
+const DIVERSITY_WINDOW: usize = 30;
+/// Leaves time to fall back to a stale snapshot before the server gives up on us
+const FETCH_TIMEOUT: Duration = Duration::from_secs(7);
+
+pub async fn listen_for_requests<
+    T: EventDatabase<HashMap<String, PostMsg>> + Clone + Sync + 'static,
+>(
     writer: T,
     fetcher: T,
+    snapshots: Arc<SnapshotCache>,
+    prewarm: Arc<Prewarm>,
+    pool: RequestPool,
     mut recv: mpsc::Receiver<FetchMessage>,
 ) -> Result<(), neo4rs::Error> {
-    let mut client = reqwest::ClientBuilder::new();
-    client = client.timeout(Duration::from_secs(10));
-    let client = client.build().unwrap();
-
-    let in_flight: Arc<DashSet<String>> = Arc::new(DashSet::new());
-    let seen_map = Arc::new(DashSet::new());
+    // Requests currently being worked on, and everyone waiting on the same answer
+    let waiting: Arc<DashMap<String, Vec<mpsc::Sender<PostResp>>>> = Arc::new(DashMap::new());
+    let poked: DashMap<String, Instant> = DashMap::new();
 
     loop {
-        let mut msg = match recv.recv().await {
+        let msg = match recv.recv().await {
             Some(s) => s,
             None => continue,
         };
@@ -42,11 +47,7 @@ pub async fn listen_for_requests<T: EventDatabase<HashMap<String, PostMsg>> + Cl
             match msg
                 .resp
                 .send(PostResp {
-                    posts: vec![PostMsg {
-                        uri: "".to_owned(),
-                        reason: "".to_owned(),
-                        timestamp: 0,
-                    }],
+                    posts: vec![PostMsg::default()],
                     cursor: Some("EMPTY_DID".to_owned()),
                 })
                 .await
@@ -60,325 +61,274 @@ pub async fn listen_for_requests<T: EventDatabase<HashMap<String, PostMsg>> + Cl
             continue;
         }
 
-        info!("Got event for {:?}", msg.did);
-        let cursor;
-        if msg.cursor.is_some() {
-            let cur = mem::take(&mut msg.cursor);
-            cursor = cur.unwrap();
-            info!("cursor is {cursor}");
-        } else {
-            cursor = now();
+        info!("Got event for {:?} on {}", msg.did, msg.feed.name);
+        prewarm.seen(&msg.did, &msg.feed);
+
+        // Nothing but the feed itself holds up the response
+        if should_poke(&poked, &msg.did) {
+            let poke = writer.clone();
+            let hm = HashMap::from([("did".to_owned(), msg.did.as_str().into())]);
+            tokio::spawn(async move {
+                if let Some(e) = poke.write(queries::POKE, Some(hm)).await {
+                    error!("While poking: {}", e);
+                }
+            });
         }
-        let mut hm = HashMap::new();
-        hm.insert("did".to_owned(), msg.did.clone());
 
-        match writer.write(queries::POKE, Some(hm)).await {
-            Some(e) => {
-                error!("While poking: {}", e);
+        let key = coalesce_key(&msg);
+        match waiting.entry(key.clone()) {
+            Entry::Occupied(mut e) => {
+                info!("Coalescing request for {}", msg.did);
+                e.get_mut().push(msg.resp);
+                continue;
+            }
+            Entry::Vacant(e) => {
+                e.insert(vec![msg.resp.clone()]);
             }
-            None => {}
         };
 
-        // Blocks
-        let did_blocks = msg.did.clone();
-        if !seen_map.contains(&did_blocks) {
-            let cl_blocks = client.clone();
-            let block_chunks: Option<Vec<HashMap<String, String>>>;
-            match get_blocks(&did_blocks, cl_blocks).await {
-                Ok(b) => {
-                    block_chunks = Some(
-                        b.iter()
-                            .map(|vals| {
-                                HashMap::from([
-                                    ("out".to_owned(), vals.0.clone()),
-                                    ("did".to_owned(), did_blocks.clone()),
-                                    ("rkey".to_owned(), vals.1.clone()),
-                                ])
-                            })
-                            .collect(),
-                    );
-                }
-                Err(e) => {
-                    warn!("Error getting blocks for {}: {}", &msg.did, e);
-                    block_chunks = None
+        let fetcher = fetcher.clone();
+        let snapshots = snapshots.clone();
+        let pool = pool.clone();
+        let waiting = waiting.clone();
+        tokio::spawn(async move {
+            // Past the queue, whatever is cached is better than waiting, & with nothing cached the dropped
+            // senders are a 503
+            let _slot = match pool.slots.try_acquire_owned() {
+                Ok(s) => s,
+                Err(_) => {
+                    let waiters = waiting.remove(&key).map(|(_, w)| w).unwrap_or_default();
+                    match cached_response(&snapshots, &msg) {
+                        Some(resp) => {
+                            warn!("No request worker free, serving {} from cache", msg.did);
+                            reply(waiters, resp, &msg.did).await;
+                        }
+                        None => warn!("No request worker free & nothing cached for {}", msg.did),
+                    }
+                    return;
                 }
             };
+            let _permit = pool.workers.acquire_owned().await.unwrap();
+
+            // Everyone gave up while we were queued
+            let abandoned = waiting
+                .get(&key)
+                .is_none_or(|w| w.iter().all(|r| r.is_closed()));
+            let resp = match abandoned {
+                true => None,
+                false => Some(feed_response(fetcher, &snapshots, &msg).await),
+            };
 
-            match block_chunks {
-                Some(b) => {
-                    match writer
-                        .chunk_write(queries::POPULATE_BLOCK, b, 60, "blocks")
-                        .await
-                    {
-                        Some(e) => {
-                            error!("Error adding blocks for {}: {}", &msg.did, e);
-                        }
-                        None => {
-                            seen_map.insert(did_blocks);
-                        }
-                    };
-                }
-                None => {}
+            let waiters = waiting.remove(&key).map(|(_, w)| w).unwrap_or_default();
+            match resp {
+                Some(r) => reply(waiters, r, &msg.did).await,
+                None => info!("Dropping abandoned request for {}", msg.did),
             }
-        }
-
-        // Follows
-        let did = msg.did.clone();
-        let cl_follows = client.clone();
-
-        match get_follows(&did, cl_follows).await {
-            Ok(follows) => {
-                let lock = write_lock.clone();
-                let seen_map = seen_map.clone();
-                let in_flight = in_flight.clone();
-                let writer = writer.clone();
-                tokio::spawn(async move {
-                    if in_flight.contains(&did) {
-                        warn!("Already in flight for {did}, skipping...");
-                        return;
-                    }
+        });
+    }
+}
 
-                    in_flight.insert(did.clone());
-                    info!("Recursively fetching {} follows for {did}", follows.len());
+async fn reply(waiters: Vec<mpsc::Sender<PostResp>>, resp: PostResp, did: &str) {
+    for w in waiters {
+        if let Err(e) = w.send(resp.clone()).await {
+            warn!("Error replying to post request for {}: {:?}", did, e);
+        }
+    }
+}
 
-                    let all_follows_result = Arc::new(DashSet::new());
-                    follows.iter().for_each(|f| {
-                        all_follows_result.insert((f.0.clone(), f.1.clone(), did.clone()));
-                    });
+/// Feed requests ranked at once, & how many more can queue for a turn
+#[derive(Clone)]
+pub struct RequestPool {
+    workers: Arc<Semaphore>,
+    // Running or queued
+    slots: Arc<Semaphore>,
+}
 
-                    let mut filtered_follows = Vec::new();
-                    for (mut f, _) in follows {
-                        if !seen_map.contains(&f) {
-                            let fcl = f.clone();
-                            filtered_follows.push(mem::take(&mut f));
-                            seen_map.insert(fcl);
-                        }
-                    }
-                    let all_follows_chunks: Vec<&[String]>;
-                    if filtered_follows.len() == 0 {
-                        in_flight.remove(&did);
-                        return;
-                    }
+impl RequestPool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            slots: Arc::new(Semaphore::new(workers + queue)),
+        }
+    }
 
-                    if filtered_follows.len() < 24 {
-                        all_follows_chunks = filtered_follows.chunks(1).collect();
-                    } else {
-                        all_follows_chunks = filtered_follows
-                            .chunks(filtered_follows.len() / 24)
-                            .collect();
-                    }
+    /// `REQUEST_WORKERS` (default 16) feed requests are ranked at once, & `REQUEST_QUEUE` (default 64) more wait
+    pub fn from_env() -> Self {
+        let var = |key: &str, default: usize| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(var("REQUEST_WORKERS", 16), var("REQUEST_QUEUE", 64))
+    }
+}
 
-                    let mut set = JoinSet::new();
-
-                    for idx in 0..all_follows_chunks.len() {
-                        let mut c = match all_follows_chunks.get(idx) {
-                            Some(c) => *c,
-                            None => {
-                                continue;
-                            }
-                        };
-
-                        let yoinked = mem::take(&mut c);
-                        let chunk = Vec::from(yoinked);
-
-                        let seen_map = seen_map.clone();
-                        let all_follows_result = all_follows_result.clone();
-
-                        set.spawn(async move {
-                             for did in chunk {
-                                 let mut web_client = reqwest::ClientBuilder::new();
-                                 web_client = web_client.timeout(Duration::from_secs(5));
-                                 let web_client = web_client.build().unwrap();
-                                 match get_follows(&did, web_client).await {
-                                     Ok(mut f) => {
-                                         f.iter_mut().for_each(|f| {
-                                             all_follows_result.insert((
-                                                 mem::take(&mut f.0),
-                                                 mem::take(&mut f.1),
-                                                 did.clone(),
-                                             ));
-                                         });
-                                     }
-                                     Err(e) => {
-                                         if e.is::<bsky::types::RecNotFound>() {
-                                             info!("{did} probably doesnt exist on this PDS, skipping...")
-                                         } else {
-                                             warn!(
-                                                 "Error getting 2nd degree follows for {did}: {:?}",
-                                                 e
-                                             );
-                                             seen_map.remove(&did);
-                                         }
-
-                                         continue;
-                                     }
-                                 };
-                             }
-                         });
-                    }
-                    set.join_all().await;
-                    info!("There are {} chunks", all_follows_chunks.len());
-                    info!(
-                        "Done Recursively fetching {} for {did}; writing...",
-                        filtered_follows.len()
-                    );
-
-                    match chunk_and_write_follows(all_follows_result, writer, lock).await {
-                        Some(e) => warn!("Error writing 2nd degree follows for {did}: {:?}", e),
-                        None => {}
-                    };
-
-                    in_flight.remove(&did);
-                });
+/// Each viewer's requests only poke the graph once a minute
+const POKE_EVERY: Duration = Duration::from_secs(60);
+/// Viewers remembered before the ones poked over a minute ago are forgotten
+const POKE_TRACKED: usize = 10000;
+
+fn should_poke(poked: &DashMap<String, Instant>, did: &str) -> bool {
+    let poke = match poked.entry(did.to_owned()) {
+        Entry::Occupied(mut e) => match e.get().elapsed() < POKE_EVERY {
+            true => false,
+            false => {
+                e.insert(Instant::now());
+                true
             }
-            Err(e) => {
-                warn!("Error getting follows for {}: {}", &msg.did, e);
-                in_flight.remove(&msg.did);
-            }
-        }; // todo - split into 2 funcs
-        _ = fetch_and_return_posts(fetcher.clone(), msg, &cursor).await;
+        },
+        Entry::Vacant(e) => {
+            e.insert(Instant::now());
+            true
+        }
+    };
+    if poked.len() > POKE_TRACKED {
+        poked.retain(|_, at| at.elapsed() < POKE_EVERY);
     }
+    poke
+}
+
+/// Requests that would get the exact same page back
+fn coalesce_key(msg: &FetchMessage) -> String {
+    format!(
+        "{}|{}|{}|{}",
+        msg.did,
+        msg.feed.name,
+        msg.cursor.as_ref().map(|c| c.encode()).unwrap_or_default(),
+        msg.limit
+    )
 }
 
-async fn fetch_and_return_posts(
+async fn feed_response(
     fetcher: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
-    msg: FetchMessage,
-    time: &String,
-) -> Result<(), SendError<PostResp>> {
-    let mut res_vec = Vec::new();
-    let mut timestamp = time.clone();
-
-    let mut first_time = false;
-    while res_vec.len() < 40 && !first_time {
-        let fetcher = fetcher.clone();
-        // max resp size is 30, but pre-fetch the next one a little just for some wiggle room
-        if let Some(posts) = fetch_posts(fetcher, &msg, &timestamp).await {
-            for p in posts.iter() {
-                res_vec.push(p.1.to_owned());
+    snapshots: &SnapshotCache,
+    msg: &FetchMessage,
+) -> PostResp {
+    let ranked_at = SystemTime::now();
+    let cursor_horizon = msg.cursor.as_ref().map(|c| c.horizon);
+    let horizon = cursor_horizon.unwrap_or_else(now);
+    let snapshot = match snapshots.fresh(&msg.did, &msg.feed.name, cursor_horizon) {
+        Some(s) => Some(s),
+        None => match rank_feed(fetcher, &msg.did, &msg.feed, horizon).await {
+            Some(ranked) => Some(snapshots.store(&msg.did, &msg.feed.name, ranked, horizon)),
+            // Better to serve an old feed than nothing at all
+            None => {
+                warn!("Falling back to a stale feed for {}", msg.did);
+                snapshots.stale(&msg.did, &msg.feed.name)
             }
+        },
+    };
 
-            if let Some(val) = res_vec.last() {
-                timestamp = val.timestamp.to_string();
-            } else {
-                info!("Reached the end");
-                break;
-            }
-            info!("Earliest timestamp is {:?}", timestamp);
-            first_time = true; // make sure we call at least once
-        }
-    }
+    info!("Ranked in {}ms", ranked_at.elapsed().unwrap().as_millis());
+    page_response(snapshots, snapshot, msg)
+}
 
-    if res_vec.len() == 0 {
-        match msg
-            .resp
-            .send(PostResp {
-                posts: res_vec,
-                cursor: None,
-            })
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                warn!("Error replying to post request for {}: {:?}", msg.did, e);
-                return Err(e);
-            }
-        };
-        return Ok(());
-    } else if res_vec.len() > 30 {
-        res_vec.truncate(30);
-    }
+/// Whatever can be served without going to the graph
+fn cached_response(snapshots: &SnapshotCache, msg: &FetchMessage) -> Option<PostResp> {
+    let cursor_horizon = msg.cursor.as_ref().map(|c| c.horizon);
+    let snapshot = snapshots
+        .fresh(&msg.did, &msg.feed.name, cursor_horizon)
+        .or_else(|| snapshots.stale(&msg.did, &msg.feed.name))?;
+    Some(page_response(snapshots, Some(snapshot), msg))
+}
 
-    let now = SystemTime::now();
-    res_vec.sort_unstable();
-    info!("Sorted in {}ms", now.elapsed().unwrap().as_millis());
+fn page_response(
+    snapshots: &SnapshotCache,
+    snapshot: Option<Snapshot>,
+    msg: &FetchMessage,
+) -> PostResp {
+    let (ranked, cursor) = match snapshot {
+        Some(s) => cursor::page(
+            snapshots.live(&s),
+            msg.cursor.as_ref(),
+            msg.limit,
+            s.horizon,
+        ),
+        None => (vec![], None),
+    };
+
+    if cursor.is_none() {
+        info!("Reached the end");
+    }
+    let cursor = cursor.map(|c| c.encode());
+    let res_vec: Vec<PostMsg> = ranked.into_iter().map(|r| r.post).collect();
 
     for v in res_vec.iter() {
         info!("Adding {:?}", v);
     }
 
-    match msg
-        .resp
-        .send(PostResp {
-            posts: res_vec,
-            cursor: Some(timestamp),
-        })
-        .await
-    {
-        Ok(_) => {
-            info!("Done!")
-        }
-        Err(e) => {
-            warn!("Error replying to post request for {}: {:?}", msg.did, e);
-            return Err(e);
+    PostResp {
+        posts: res_vec,
+        cursor,
+    }
+}
+
+/// Fetches & ranks a viewer's whole feed as of `horizon`. Every page of a session is fetched & scored as of
+/// the first page, so it ranks the same each time. None if the graph errored or was too slow
+pub(crate) async fn rank_feed(
+    fetcher: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
+    did: &str,
+    feed: &FeedConfig,
+    horizon: u64,
+) -> Option<Vec<Ranked>> {
+    let posts =
+        match tokio::time::timeout(FETCH_TIMEOUT, fetch_posts(fetcher, did, feed, horizon)).await {
+            Ok(Some(posts)) => posts,
+            Ok(None) => return None,
+            Err(_) => {
+                warn!("Timed out fetching posts for {}", did);
+                return None;
+            }
+        };
+
+    let ranked = feed.scoring.rank(posts.into_values(), horizon);
+    // Diversity always works in fixed windows, so the order doesnt depend on the requested limit
+    Some(feed.diversity.rerank(ranked, DIVERSITY_WINDOW))
+}
+
+/// Later queries win, except a post found via a followed account's repost keeps that attribution
+fn merge_post(posts: &mut HashMap<String, PostMsg>, uri: String, post: PostMsg) {
+    match posts.get(&uri) {
+        Some(existing) if existing.repost.is_some() && post.repost.is_none() => {}
+        _ => {
+            posts.insert(uri, post);
         }
-    };
-    Ok(())
+    }
 }
 
 async fn fetch_posts(
     fetcher: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
-    msg: &FetchMessage,
-    time: &String,
+    did: &str,
+    feed: &FeedConfig,
+    time: u64,
 ) -> Option<HashMap<String, PostMsg>> {
     // Fetch posts
 
     let now = SystemTime::now();
-    let q1 = &queries::GET_BEST_2ND_DEG_LIKES
-        .to_string()
-        .replace("{}", &time);
-
-    let q2 = &queries::GET_BEST_2ND_DEG_REPOSTS
-        .to_string()
-        .replace("{}", &time);
-
-    let q3 = &queries::GET_FOLLOWING_PLUS_LIKES
-        .to_string()
-        .replace("{}", &time);
-
-    let q4 = &queries::GET_FOLLOWING_PLUS_REPOSTS
-        .to_string()
-        .replace("{}", &time);
-
-    let q5 = &queries::GET_BEST_FOLLOWED.to_string().replace("{}", &time);
+    let params: Params = HashMap::from([
+        ("did".to_string(), did.into()),
+        ("replies".to_string(), feed.include_replies.into()),
+        ("time".to_string(), time.into()),
+    ]);
 
     // For some reason tokio::join did not play nice with the Trait, but this is also more succinct so :shrug:
     let mut tasks = FuturesUnordered::new();
-    tasks.push(fetcher.read(
-        "2ND_DEG_LIKES",
-        q1,
-        Some(HashMap::from([("did".to_string(), msg.did.clone())])),
-    ));
-
-    tasks.push(fetcher.read(
-        "GET_BEST_2ND_DEG_REPOSTS",
-        q2,
-        Some(HashMap::from([("did".to_string(), msg.did.clone())])),
-    ));
-
-    tasks.push(fetcher.read(
-        "GET_FOLLOWING_PLUS_LIKES",
-        q3,
-        Some(HashMap::from([("did".to_string(), msg.did.clone())])),
-    ));
-
-    tasks.push(fetcher.read(
-        "GET_FOLLOWING_PLUS_REPOSTS",
-        q4,
-        Some(HashMap::from([("did".to_string(), msg.did.clone())])),
-    ));
-
-    tasks.push(fetcher.read(
-        "GET_BEST_FOLLOWED",
-        q5,
-        Some(HashMap::from([("did".to_string(), msg.did.clone())])),
-    ));
+    for (name, query) in queries::FEED_QUERIES {
+        tasks.push(fetcher.read(name, query, Some(params.clone())));
+    }
 
     let mut posts: HashMap<String, PostMsg> = HashMap::new();
     while let Some(result) = tasks.next().await {
         match result {
-            Ok(value) => posts.extend(value),
+            Ok(value) => {
+                for (uri, post) in value {
+                    merge_post(&mut posts, uri, post);
+                }
+            }
             Err(e) => {
-                warn!("Error joining post fetches for {}: {}", &msg.did, e);
+                warn!("Error joining post fetches for {}: {}", did, e);
                 return None;
             }
         }
@@ -388,85 +338,9 @@ async fn fetch_posts(
     Some(posts)
 }
 
-fn now() -> String {
+pub(crate) fn now() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
-        .as_micros()
-        .to_string()
-}
-
-async fn get_follows(
-    did_follows: &String,
-    cl_follows: reqwest::Client,
-) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
-    let follows = match bsky::get_follows(did_follows.clone(), cl_follows).await {
-        Ok(f) => f,
-        Err(e) => {
-            let err_str = format!("{:?}", e);
-            let status: StatusCode = match e.1 {
-                Some(s) => s,
-                None => StatusCode::IM_A_TEAPOT,
-            };
-            if err_str.contains("missing field `records`")
-                && status == StatusCode::from_u16(400).unwrap()
-            {
-                return Err(Box::new(RecNotFound {}));
-            }
-            return Err(e.0);
-        }
-    };
-
-    Ok(follows)
-}
-
-async fn get_blocks(
-    did_blocks: &String,
-    cl_blocks: reqwest::Client,
-) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
-    let blocks = match bsky::get_blocks(did_blocks.clone(), cl_blocks).await {
-        Ok(f) => f,
-        Err(e) => {
-            let err_str = format!("{:?}", e);
-            let status: StatusCode = match e.1 {
-                Some(s) => s,
-                None => StatusCode::IM_A_TEAPOT,
-            };
-            if err_str.contains("missing field `records`")
-                && status == StatusCode::from_u16(400).unwrap()
-            {
-                return Err(Box::new(RecNotFound {}));
-            }
-            return Err(e.0);
-        }
-    };
-
-    Ok(blocks)
-}
-
-async fn chunk_and_write_follows(
-    follows: Arc<DashSet<(String, String, String)>>,
-    conn: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
-    write_lock: Arc<RwLock<()>>,
-) -> Option<Box<dyn error::Error>> {
-    let follow_chunks: Vec<HashMap<String, String>> = follows
-        .iter()
-        .map(|vals| {
-            HashMap::from([
-                ("out".to_owned(), vals.0.clone()),
-                ("rkey".to_owned(), vals.1.clone()),
-                ("did".to_owned(), vals.2.clone()),
-            ])
-        })
-        .collect();
-    let l = write_lock.write().await;
-    let r = match conn
-        .chunk_write(queries::POPULATE_FOLLOW, follow_chunks, 20, "follows")
-        .await
-    {
-        Some(e) => Some(e),
-        None => None,
-    };
-    drop(l);
-    r
+        .as_micros() as u64
 }
@@ -1,9 +1,15 @@
-use crate::common::FetchMessage;
+use crate::backfill::{BackfillMessage, JobStatus, crawl_log::RecentCrawls};
+use crate::common::{
+    FetchMessage,
+    cursor::{Cursor, DEFAULT_LIMIT, MAX_LIMIT},
+    feed::FeedConfig,
+    stats::{IngestStats, PurgeImpact},
+};
 use axum::{
     Json, Router,
     extract::{Query, State},
     http::Method,
-    routing::get,
+    routing::{get, post},
 };
 use axum_extra::{
     TypedHeader,
@@ -11,21 +17,37 @@ use axum_extra::{
 };
 
 use hyper::{HeaderMap, StatusCode};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, env, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::sync::mpsc::Sender;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::error;
+use tracing::{error, warn};
 use urlencoding::decode;
 
 pub mod auth;
 pub mod listen;
+pub mod prewarm;
+mod server_test;
+pub mod snapshot;
 pub mod types;
+
+/// A viewer is only handed to the backfill once an hour, the job store knows if they were already crawled
+const ENQUEUE_EVERY: Duration = Duration::from_secs(3600);
+/// Viewers remembered as enqueued, oldest forgotten first
+const ENQUEUE_TRACKED: usize = 100000;
+
 struct StateStruct {
     send_chan: Sender<FetchMessage>,
+    backfill_chan: Sender<BackfillMessage>,
+    enqueued: RecentCrawls,
+    stats: Arc<IngestStats>,
 }
 
-pub async fn serve(chan: Sender<FetchMessage>) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn serve(
+    chan: Sender<FetchMessage>,
+    backfill_chan: Sender<BackfillMessage>,
+    stats: Arc<IngestStats>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let cors = CorsLayer::new()
         .allow_methods([
             Method::GET,
@@ -37,9 +59,15 @@ pub async fn serve(chan: Sender<FetchMessage>) -> Result<(), Box<dyn std::error:
         .allow_origin(Any);
     let state = StateStruct {
         send_chan: chan.clone(),
+        backfill_chan,
+        enqueued: RecentCrawls::new(ENQUEUE_EVERY, ENQUEUE_TRACKED),
+        stats,
     };
     let router = Router::new()
         .route("/get_feed", get(index))
+        .route("/backfill/status", get(backfill_status))
+        .route("/backfill/requeue", post(backfill_requeue))
+        .route("/purge/status", get(purge_status))
         .layer(ServiceBuilder::new().layer(cors))
         .with_state(Arc::new(state));
 
@@ -67,17 +95,50 @@ async fn index(
             return Err(axum::http::StatusCode::NOT_FOUND);
         }
     };
-    let cursor;
-    if let Some(c) = params.get("cursor") {
-        cursor = Some(c.clone());
-    } else {
-        cursor = None;
+    let cursor = match params.get("cursor") {
+        Some(c) => match Cursor::decode(c) {
+            Some(c) => Some(c),
+            None => {
+                warn!("Rejecting malformed cursor for {}", did);
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        },
+        None => None,
+    };
+
+    let limit = match params.get("limit") {
+        Some(l) => match l.parse::<usize>() {
+            Ok(l) if (1..=MAX_LIMIT).contains(&l) => l,
+            _ => return Err(StatusCode::BAD_REQUEST),
+        },
+        None => DEFAULT_LIMIT,
+    };
+
+    let feed = match params.get("feed") {
+        Some(f) => FeedConfig::for_feed(f),
+        None => FeedConfig::default(),
+    };
+
+    // Crawls their network the first time we see them
+    if state.enqueued.try_claim(&did)
+        && let Err(e) = state
+            .backfill_chan
+            .try_send(BackfillMessage::Enqueue { did: did.clone() })
+    {
+        warn!("Unable to queue onboarding for {}: {}", did, e);
+        state.enqueued.remove(&did);
     }
 
     let (resp, mut recv) = tokio::sync::mpsc::channel(1);
     state
         .send_chan
-        .send(FetchMessage { did, cursor, resp })
+        .send(FetchMessage {
+            did: did.clone(),
+            cursor,
+            limit,
+            feed,
+            resp,
+        })
         .await
         .unwrap();
 
@@ -94,9 +155,10 @@ async fn index(
 
     let resp = match resp {
         Some(r) => r,
+        // Every worker was busy, with nothing cached to serve instead
         None => {
-            error!("nil response from channel");
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            warn!("No feed for {}, the graph workers are full", did);
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
         }
     };
 
@@ -107,3 +169,88 @@ async fn index(
         feed: posts,
     }))
 }
+
+/// Admin only, like `backfill_requeue`
+async fn backfill_status(
+    Query(params): Query<HashMap<String, String>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    State(state): State<Arc<StateStruct>>,
+) -> Result<Json<JobStatus>, StatusCode> {
+    if !is_admin(bearer) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let did = match params.get("did") {
+        Some(d) => d.clone(),
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let (resp, mut recv) = tokio::sync::mpsc::channel(1);
+    if state
+        .backfill_chan
+        .send(BackfillMessage::Status { did, resp })
+        .await
+        .is_err()
+    {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    match tokio::time::timeout(std::time::Duration::from_secs(10), recv.recv()).await {
+        Ok(Some(Some(status))) => Ok(Json(status)),
+        Ok(Some(None)) => Err(StatusCode::NOT_FOUND),
+        _ => {
+            error!("timed out waiting for backfill status");
+            Err(StatusCode::REQUEST_TIMEOUT)
+        }
+    }
+}
+
+/// Admin only
+async fn backfill_requeue(
+    Query(params): Query<HashMap<String, String>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    State(state): State<Arc<StateStruct>>,
+) -> Result<StatusCode, StatusCode> {
+    if !is_admin(bearer) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let did = match params.get("did") {
+        Some(d) => d.clone(),
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let (resp, mut recv) = tokio::sync::mpsc::channel(1);
+    if state
+        .backfill_chan
+        .send(BackfillMessage::Requeue { did, resp })
+        .await
+        .is_err()
+    {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    match tokio::time::timeout(std::time::Duration::from_secs(10), recv.recv()).await {
+        Ok(Some(true)) => Ok(StatusCode::ACCEPTED),
+        // Already queued or running
+        Ok(Some(false)) => Err(StatusCode::CONFLICT),
+        _ => Err(StatusCode::REQUEST_TIMEOUT),
+    }
+}
+
+/// The bearer token has to match `BACKFILL_ADMIN_TOKEN`, which has to be set
+fn is_admin(bearer: Option<TypedHeader<Authorization<Bearer>>>) -> bool {
+    let admin_token = env::var("BACKFILL_ADMIN_TOKEN").unwrap_or("".into());
+    match bearer {
+        Some(b) => !admin_token.is_empty() && b.0.0.token() == admin_token,
+        None => false,
+    }
+}
+
+/// The last retention pass, & what it did to ingestion
+async fn purge_status(
+    State(state): State<Arc<StateStruct>>,
+) -> Result<Json<PurgeImpact>, StatusCode> {
+    match state.stats.last_purge() {
+        Some(p) => Ok(Json(p)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
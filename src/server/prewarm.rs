@@ -0,0 +1,119 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::common::{PostMsg, feed::FeedConfig};
+use crate::event_database::EventDatabase;
+use crate::server::{
+    listen::{now, rank_feed},
+    snapshot::SnapshotCache,
+};
+
+/// Keeps the feeds of recently active viewers ranked ahead of time, so their first page is just a cache hit
+pub struct Prewarm {
+    interval: Duration,
+    active_for: Duration,
+    concurrency: usize,
+    // (viewer did, feed name) -> when they last asked, & the feed they asked for
+    viewers: DashMap<(String, String), (Instant, FeedConfig)>,
+}
+
+impl Prewarm {
+    pub fn new(interval: Duration, active_for: Duration, concurrency: usize) -> Self {
+        Self {
+            interval,
+            active_for,
+            concurrency: concurrency.max(1),
+            viewers: DashMap::new(),
+        }
+    }
+
+    /// `PREWARM_INTERVAL_SECS` (default 30, keep it under `FEED_CACHE_TTL_SECS`), `PREWARM_ACTIVE_SECS`
+    /// (default 900) & `PREWARM_CONCURRENCY` (default 4). An interval of 0 turns it off
+    pub fn from_env() -> Self {
+        let var = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            Duration::from_secs(var("PREWARM_INTERVAL_SECS", 30)),
+            Duration::from_secs(var("PREWARM_ACTIVE_SECS", 900)),
+            var("PREWARM_CONCURRENCY", 4) as usize,
+        )
+    }
+
+    pub fn seen(&self, did: &str, feed: &FeedConfig) {
+        self.viewers.insert(
+            (did.to_owned(), feed.name.clone()),
+            (Instant::now(), feed.clone()),
+        );
+    }
+
+    /// Viewers who asked for a feed within `active_for`, most recent first. Everyone else is forgotten
+    pub fn due(&self) -> Vec<(String, FeedConfig)> {
+        self.viewers
+            .retain(|_, (at, _)| at.elapsed() < self.active_for);
+
+        let mut due: Vec<(Instant, String, FeedConfig)> = self
+            .viewers
+            .iter()
+            .map(|e| (e.value().0, e.key().0.clone(), e.value().1.clone()))
+            .collect();
+        due.sort_by_key(|d| Reverse(d.0));
+        due.into_iter().map(|(_, did, feed)| (did, feed)).collect()
+    }
+
+    pub async fn run(
+        self: Arc<Self>,
+        fetcher: impl EventDatabase<HashMap<String, PostMsg>> + Clone,
+        snapshots: Arc<SnapshotCache>,
+    ) {
+        if self.interval.is_zero() {
+            info!("Feed prewarming disabled");
+            return;
+        }
+
+        loop {
+            tokio::time::sleep(self.interval).await;
+
+            let started = Instant::now();
+            let due: Vec<(String, FeedConfig)> = self
+                .due()
+                .into_iter()
+                .filter(|(did, feed)| snapshots.needs_refresh(did, &feed.name, self.interval))
+                .collect();
+            let n = due.len();
+
+            futures::stream::iter(due)
+                .for_each_concurrent(self.concurrency, |(did, feed)| {
+                    let fetcher = fetcher.clone();
+                    let snapshots = snapshots.clone();
+                    async move {
+                        let horizon = now();
+                        if let Some(ranked) = rank_feed(fetcher, &did, &feed, horizon).await {
+                            snapshots.store(&did, &feed.name, ranked, horizon);
+                        }
+                    }
+                })
+                .await;
+
+            if n > 0 {
+                info!(
+                    "Prewarmed {} feeds in {}ms",
+                    n,
+                    started.elapsed().as_millis()
+                );
+            }
+        }
+    }
+}
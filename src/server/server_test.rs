@@ -0,0 +1,293 @@
+#[cfg(test)]
+mod server_test {
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        common::{FetchMessage, PostMsg, PostResp, feed::FeedConfig},
+        event_database::{EventDatabase, Params},
+        graph::queries,
+        ranking::Ranked,
+        server::{
+            listen::{RequestPool, listen_for_requests},
+            prewarm::Prewarm,
+            snapshot::SnapshotCache,
+        },
+    };
+
+    const FEED: &str = "following_plus";
+
+    fn ranked(uris: &[&str]) -> Vec<Ranked> {
+        uris.iter()
+            .enumerate()
+            .map(|(i, uri)| Ranked {
+                score: 10.0 - i as f64,
+                post: PostMsg {
+                    uri: uri.to_string(),
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+
+    fn uris(ranked: &[Ranked]) -> Vec<&str> {
+        ranked.iter().map(|r| r.post.uri.as_str()).collect()
+    }
+
+    #[test]
+    fn snapshots_are_per_viewer_feed_and_horizon() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 1000);
+        cache.store("did:plc:a", FEED, ranked(&["p1", "p2"]), 100);
+
+        assert!(cache.fresh("did:plc:a", FEED, None).is_some());
+        assert!(cache.fresh("did:plc:a", FEED, Some(100)).is_some());
+        // A cursor from an older session has to be re-ranked at its own horizon
+        assert!(cache.fresh("did:plc:a", FEED, Some(99)).is_none());
+        assert!(cache.fresh("did:plc:a", "other", None).is_none());
+        assert!(cache.fresh("did:plc:b", FEED, None).is_none());
+    }
+
+    #[test]
+    fn prewarming_leaves_a_paging_session_its_snapshot() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 1000);
+        cache.store("did:plc:a", FEED, ranked(&["p1", "p2"]), 100);
+        // Prewarm re-ranks at a newer horizon while the viewer is still on page one of the last
+        cache.store("did:plc:a", FEED, ranked(&["p3", "p1", "p2"]), 200);
+
+        let paging = cache.fresh("did:plc:a", FEED, Some(100)).unwrap();
+        assert_eq!(uris(&paging.ranked), vec!["p1", "p2"]);
+        assert_eq!(cache.fresh("did:plc:a", FEED, None).unwrap().horizon, 200);
+        assert_eq!(cache.stale("did:plc:a", FEED).unwrap().horizon, 200);
+
+        // Re-ranking for an old cursor doesnt make it the first page for everyone else
+        cache.store("did:plc:a", FEED, ranked(&["p1"]), 50);
+        assert_eq!(cache.fresh("did:plc:a", FEED, None).unwrap().horizon, 200);
+        assert!(cache.fresh("did:plc:a", FEED, Some(50)).is_some());
+    }
+
+    #[test]
+    fn invalidated_and_expired_snapshots_are_only_served_stale() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 1000);
+        cache.store("did:plc:a", FEED, ranked(&["p1"]), 100);
+        cache.invalidate_viewer("did:plc:a");
+        assert!(cache.fresh("did:plc:a", FEED, None).is_none());
+        assert!(cache.stale("did:plc:a", FEED).is_some());
+
+        let expired = SnapshotCache::new(Duration::ZERO, Duration::from_secs(600), 1000);
+        expired.store("did:plc:a", FEED, ranked(&["p1"]), 100);
+        assert!(expired.fresh("did:plc:a", FEED, None).is_none());
+        assert!(expired.stale("did:plc:a", FEED).is_some());
+
+        let gone = SnapshotCache::new(Duration::ZERO, Duration::ZERO, 1000);
+        gone.store("did:plc:a", FEED, ranked(&["p1"]), 100);
+        assert!(gone.stale("did:plc:a", FEED).is_none());
+        gone.prune();
+        assert!(gone.stale("did:plc:a", FEED).is_none());
+    }
+
+    #[test]
+    fn deleted_posts_are_sliced_out() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 1000);
+        let snapshot = cache.store("did:plc:a", FEED, ranked(&["p1", "p2", "p3"]), 100);
+        cache.forget_post("p2".to_owned());
+
+        assert_eq!(uris(&cache.live(&snapshot)), vec!["p1", "p3"]);
+        // The snapshot itself is untouched & still fresh
+        assert!(cache.fresh("did:plc:a", FEED, None).is_some());
+    }
+
+    #[test]
+    fn only_the_newest_deletes_are_kept() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 10);
+        let snapshot = cache.store("did:plc:a", FEED, ranked(&["p0", "p5", "p10"]), 100);
+        for i in 0..=10 {
+            cache.forget_post(format!("p{i}"));
+        }
+        // Back down to 90% of capacity, losing the oldest
+        assert_eq!(uris(&cache.live(&snapshot)), vec!["p0"]);
+    }
+
+    #[test]
+    fn snapshots_refresh_when_old_or_invalidated() {
+        let cache = SnapshotCache::new(Duration::from_secs(60), Duration::from_secs(600), 1000);
+        assert!(cache.needs_refresh("did:plc:a", FEED, Duration::from_secs(30)));
+
+        cache.store("did:plc:a", FEED, ranked(&["p1"]), 100);
+        assert!(!cache.needs_refresh("did:plc:a", FEED, Duration::from_secs(30)));
+        assert!(cache.needs_refresh("did:plc:a", FEED, Duration::ZERO));
+
+        cache.invalidate_viewer("did:plc:a");
+        assert!(cache.needs_refresh("did:plc:a", FEED, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn prewarm_most_recent_viewers_first() {
+        let prewarm = Prewarm::new(Duration::from_secs(30), Duration::from_secs(900), 2);
+        let feed = FeedConfig::default();
+        prewarm.seen("did:plc:a", &feed);
+        prewarm.seen("did:plc:b", &feed);
+        prewarm.seen("did:plc:c", &feed);
+        // Asking again bumps them back to the front
+        prewarm.seen("did:plc:a", &feed);
+
+        let dids: Vec<String> = prewarm.due().into_iter().map(|(did, _)| did).collect();
+        assert_eq!(dids, vec!["did:plc:a", "did:plc:c", "did:plc:b"]);
+    }
+
+    #[test]
+    fn prewarm_forgets_inactive_viewers() {
+        let prewarm = Prewarm::new(Duration::from_secs(30), Duration::ZERO, 2);
+        prewarm.seen("did:plc:a", &FeedConfig::default());
+        assert!(prewarm.due().is_empty());
+    }
+
+    /// Has one post for every feed query, slowly enough for requests to pile up, & counts the queries run
+    #[derive(Clone, Default)]
+    struct SlowGraph {
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl SlowGraph {
+        fn rankings(&self) -> usize {
+            self.reads.load(Ordering::SeqCst) / queries::FEED_QUERIES.len()
+        }
+    }
+
+    impl EventDatabase<HashMap<String, PostMsg>> for SlowGraph {
+        async fn read(
+            &self,
+            query_name: &str,
+            _query: &str,
+            _params: Option<Params>,
+        ) -> Result<HashMap<String, PostMsg>, Box<dyn Error>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let uri = "at://did:plc:author/app.bsky.feed.post/3kabc".to_owned();
+            let post = PostMsg {
+                uri: uri.clone(),
+                reason: query_name.to_owned(),
+                ..Default::default()
+            };
+            Ok(HashMap::from([(uri, post)]))
+        }
+        async fn write(&self, _query: &str, _params: Option<Params>) -> Option<Box<dyn Error>> {
+            None
+        }
+        async fn batch_write(
+            &self,
+            _queries: Vec<&str>,
+            _params: Vec<Option<Params>>,
+        ) -> Option<Box<dyn Error>> {
+            None
+        }
+        async fn chunk_write(
+            &self,
+            _query: &str,
+            _params: Vec<Params>,
+            _chunk_size: usize,
+            _param_name: &str,
+        ) -> Option<Box<dyn Error>> {
+            None
+        }
+        async fn batch_read(
+            &self,
+            _queries: Vec<&str>,
+            _params: Vec<Option<Params>>,
+        ) -> Result<Vec<HashMap<String, PostMsg>>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+    }
+
+    fn listen(
+        graph: SlowGraph,
+        snapshots: Arc<SnapshotCache>,
+        pool: RequestPool,
+    ) -> mpsc::Sender<FetchMessage> {
+        let (send, recv) = mpsc::channel(16);
+        let prewarm = Arc::new(Prewarm::new(Duration::ZERO, Duration::ZERO, 1));
+        tokio::spawn(listen_for_requests(
+            graph.clone(),
+            graph,
+            snapshots,
+            prewarm,
+            pool,
+            recv,
+        ));
+        send
+    }
+
+    async fn request(send: &mpsc::Sender<FetchMessage>, did: &str) -> mpsc::Receiver<PostResp> {
+        let (resp, recv) = mpsc::channel(1);
+        let msg = FetchMessage {
+            did: did.to_owned(),
+            cursor: None,
+            limit: 30,
+            feed: FeedConfig::default(),
+            resp,
+        };
+        send.send(msg).await.unwrap();
+        recv
+    }
+
+    fn cache() -> Arc<SnapshotCache> {
+        Arc::new(SnapshotCache::new(
+            Duration::from_secs(60),
+            Duration::from_secs(600),
+            1000,
+        ))
+    }
+
+    #[tokio::test]
+    async fn identical_requests_share_one_ranking() {
+        let graph = SlowGraph::default();
+        let send = listen(graph.clone(), cache(), RequestPool::new(4, 4));
+        let mut first = request(&send, "did:plc:a").await;
+        let mut second = request(&send, "did:plc:a").await;
+        // A different viewer is a different page
+        let mut other = request(&send, "did:plc:b").await;
+
+        for recv in [&mut first, &mut second, &mut other] {
+            assert_eq!(recv.recv().await.unwrap().posts.len(), 1);
+        }
+        assert_eq!(graph.rankings(), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_past_the_queue_get_the_cache_or_a_503() {
+        let graph = SlowGraph::default();
+        let snapshots = cache();
+        let feed = FeedConfig::default();
+        snapshots.store("did:plc:cached", &feed.name, ranked(&["p1", "p2"]), 100);
+        snapshots.invalidate_viewer("did:plc:cached");
+        let send = listen(graph.clone(), snapshots, RequestPool::new(1, 1));
+
+        // One ranks while the next waits for it
+        let mut running = request(&send, "did:plc:a").await;
+        let mut queued = request(&send, "did:plc:b").await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut cached = request(&send, "did:plc:cached").await;
+        let mut shed = request(&send, "did:plc:c").await;
+
+        let page = cached.recv().await.unwrap();
+        assert_eq!(
+            page.posts
+                .iter()
+                .map(|p| p.uri.as_str())
+                .collect::<Vec<_>>(),
+            vec!["p1", "p2"]
+        );
+        // Nothing to answer with, so the sender is dropped
+        assert!(shed.recv().await.is_none());
+
+        assert_eq!(running.recv().await.unwrap().posts.len(), 1);
+        assert_eq!(queued.recv().await.unwrap().posts.len(), 1);
+        assert_eq!(graph.rankings(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::ranking::Ranked;
+
+/// A fully ranked feed for one viewer, as of `horizon`
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub ranked: Arc<Vec<Ranked>>,
+    pub horizon: u64,
+    built_at: Instant,
+    invalidated: bool,
+}
+
+/// Ranked feeds per viewer, feed & horizon, so later pages (and refreshes within the TTL) are just a slice.
+/// A newer horizon doesnt replace an older one still within the TTL, as someone may be paging through it.
+/// Invalidated snapshots are kept around, as a last good copy to serve if the graph is struggling
+pub struct SnapshotCache {
+    ttl: Duration,
+    max_stale: Duration,
+    max_deleted: usize,
+    // viewer did -> (feed name, horizon) -> snapshot
+    snapshots: DashMap<String, HashMap<(String, u64), Snapshot>>,
+    // Posts deleted since snapshots were built, filtered out when slicing. Every delete on the network lands
+    // here, so it is capped at `max_deleted`
+    deleted: DashMap<String, Instant>,
+}
+
+impl SnapshotCache {
+    pub fn new(ttl: Duration, max_stale: Duration, max_deleted: usize) -> Self {
+        Self {
+            ttl,
+            max_stale,
+            max_deleted: max_deleted.max(1),
+            snapshots: DashMap::new(),
+            deleted: DashMap::new(),
+        }
+    }
+
+    /// `FEED_CACHE_TTL_SECS` (default 60), `FEED_CACHE_MAX_STALE_SECS` (default 3600) &
+    /// `FEED_CACHE_MAX_DELETED` (default 200000) deletes remembered
+    pub fn from_env() -> Self {
+        let var = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            Duration::from_secs(var("FEED_CACHE_TTL_SECS", 60)),
+            Duration::from_secs(var("FEED_CACHE_MAX_STALE_SECS", 3600)),
+            var("FEED_CACHE_MAX_DELETED", 200000) as usize,
+        )
+    }
+
+    /// A snapshot that is safe to serve as-is. If `horizon` is set, it must be the one the snapshot was built at,
+    /// otherwise it is the newest
+    pub fn fresh(&self, did: &str, feed: &str, horizon: Option<u64>) -> Option<Snapshot> {
+        let snapshot = match horizon {
+            Some(h) => self
+                .snapshots
+                .get(did)?
+                .get(&(feed.to_owned(), h))
+                .cloned()?,
+            None => self.get(did, feed)?,
+        };
+        match !snapshot.invalidated && snapshot.built_at.elapsed() < self.ttl {
+            true => Some(snapshot),
+            false => None,
+        }
+    }
+
+    /// The newest snapshot, however outdated, as long as it is within `max_stale`
+    pub fn stale(&self, did: &str, feed: &str) -> Option<Snapshot> {
+        self.get(did, feed)
+            .filter(|s| s.built_at.elapsed() < self.max_stale)
+    }
+
+    /// Whether the newest snapshot is missing, invalidated, or older than `every`
+    pub fn needs_refresh(&self, did: &str, feed: &str, every: Duration) -> bool {
+        match self.get(did, feed) {
+            Some(s) => s.invalidated || s.built_at.elapsed() >= every,
+            None => true,
+        }
+    }
+
+    pub fn store(&self, did: &str, feed: &str, ranked: Vec<Ranked>, horizon: u64) -> Snapshot {
+        let snapshot = Snapshot {
+            ranked: Arc::new(ranked),
+            horizon,
+            built_at: Instant::now(),
+            invalidated: false,
+        };
+        let mut feeds = self.snapshots.entry(did.to_owned()).or_default();
+        // Older horizons only matter while they can still be paged through
+        feeds.retain(|(f, h), s| f != feed || *h == horizon || s.built_at.elapsed() < self.ttl);
+        feeds.insert((feed.to_owned(), horizon), snapshot.clone());
+        snapshot
+    }
+
+    /// The viewer's follows or blocks changed, so every one of their feeds needs rebuilding
+    pub fn invalidate_viewer(&self, did: &str) {
+        if let Some(mut feeds) = self.snapshots.get_mut(did) {
+            for snapshot in feeds.values_mut() {
+                snapshot.invalidated = true;
+            }
+        }
+    }
+
+    pub fn forget_post(&self, uri: String) {
+        self.deleted.insert(uri, Instant::now());
+        self.evict_deleted();
+    }
+
+    /// The snapshot's posts, minus any recently deleted
+    pub fn live(&self, snapshot: &Snapshot) -> Vec<Ranked> {
+        snapshot
+            .ranked
+            .iter()
+            // Deletes are written in batches, so a snapshot built just after one can still have the post
+            .filter(|r| !self.deleted.contains_key(&r.post.uri))
+            .cloned()
+            .collect()
+    }
+
+    /// Drops anything too old to be served, even as a fallback
+    pub fn prune(&self) {
+        self.snapshots.retain(|_, feeds| {
+            feeds.retain(|_, s| s.built_at.elapsed() < self.max_stale);
+            !feeds.is_empty()
+        });
+        self.deleted.retain(|_, at| at.elapsed() < self.max_stale);
+    }
+
+    /// Over capacity, drops the oldest deletes down to 90% so this doesnt run every insert. By then the
+    /// snapshots that could have had them are mostly rebuilt
+    fn evict_deleted(&self) {
+        if self.deleted.len() <= self.max_deleted {
+            return;
+        }
+        let keep = self.max_deleted - self.max_deleted / 10;
+        let mut by_age: Vec<(Instant, String)> = self
+            .deleted
+            .iter()
+            .map(|e| (*e.value(), e.key().clone()))
+            .collect();
+        by_age.sort();
+        let excess = by_age.len().saturating_sub(keep);
+        for (_, uri) in by_age.into_iter().take(excess) {
+            self.deleted.remove(&uri);
+        }
+    }
+
+    /// The one with the newest horizon
+    fn get(&self, did: &str, feed: &str) -> Option<Snapshot> {
+        self.snapshots
+            .get(did)?
+            .iter()
+            .filter(|((f, _), _)| f == feed)
+            .max_by_key(|((_, h), _)| *h)
+            .map(|(_, s)| s.clone())
+    }
+}
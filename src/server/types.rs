@@ -17,6 +17,18 @@ impl IntoResponse for Response {
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct Post {
     pub post: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkeletonReason>,
+    /// Which query surfaced the post, passed back to us with any interactions on it
+    #[serde(rename = "feedContext", skip_serializing_if = "Option::is_none")]
+    pub feed_context: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$type")]
+pub enum SkeletonReason {
+    #[serde(rename = "app.bsky.feed.defs#skeletonReasonRepost")]
+    Repost { repost: String },
 }
 
 #[derive(Debug, Serialize, Deserialize)]